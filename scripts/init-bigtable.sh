@@ -1,9 +1,11 @@
 #!/usr/bin/env bash
 #
 # Configures a BigTable instance with the expected tables
-# Usage: init-bigtable.sh [bigtable-instance-name]
+# Usage: init-bigtable.sh [bigtable-instance-name] [table-prefix]
 # If bigtable-instance-name is not given. It will be the default
 # solana-geyser-plugin-bigtable
+# The table-prefix is prepended to the table names, it must match the
+# "table_prefix" of the plugin configuration
 #
 
 set -e
@@ -14,6 +16,7 @@ then
 else
   instance=solana-geyser-plugin-bigtable
 fi
+prefix=$2
 
 cbt=(
   cbt
@@ -24,7 +27,8 @@ if [[ -n $BIGTABLE_EMULATOR_HOST ]]; then
   cbt+=(-project emulator)
 fi
 
-for table in account slot block transaction; do
+for table in account account_chunk slot block transaction transaction_by_signature; do
+  table=$prefix$table
   (
     set -x
     "${cbt[@]}" createtable $table
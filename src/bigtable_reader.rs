@@ -0,0 +1,350 @@
+/// Library reading back the data stored by the plugin
+use {
+    crate::{
+        compression::decompress_with_dictionaries,
+        dictionary::ZstdDictionaries,
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        models::{accounts, slots},
+        parallel_bigtable_client::{
+            account::ACCOUNT_TABLE,
+            account_chunk::{
+                account_chunk_row_key, reassemble_account, AccountDataChunk, ChunkedAccount,
+                ACCOUNT_CHUNK_TABLE,
+            },
+            block_metadata::{block_row_key, DbBlockInfo, BLOCK_TABLE},
+            slot::SLOT_TABLE,
+            table_routing::TableRoutes,
+            transaction::{DbTransaction, TRANSACTION_BY_SIGNATURE_TABLE, TRANSACTION_TABLE},
+            BufferedBigtableClient,
+        },
+        schema::{decode_cell, SchemaError},
+    },
+    prost::Message,
+    serde::de::DeserializeOwned,
+    solana_bigtable_connection::bigtable::{
+        BigTableConnection as Client, Error as BigtableError, RowData, RowKey,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::{collections::HashMap, io},
+    thiserror::Error,
+};
+
+#[derive(Error, Debug)]
+pub enum BigtableReaderError {
+    #[error("Bigtable error: ({0})")]
+    BigtableError(#[from] BigtableError),
+
+    #[error("Error decompressing the cell: ({0})")]
+    DecompressionError(#[from] io::Error),
+
+    #[error("Error decoding the row {key}: ({err})")]
+    SchemaError { key: RowKey, err: SchemaError },
+
+    #[error("The row {0} has no cell written by the plugin")]
+    MissingCell(RowKey),
+
+    #[error("Error decoding the bincode cell of the row {key}: ({err})")]
+    BincodeError { key: RowKey, err: bincode::Error },
+
+    #[error("The account {key} is corrupt: ({msg})")]
+    CorruptAccount { key: RowKey, msg: String },
+}
+
+pub struct BigtableReader {
+    routes: TableRoutes,
+    dictionaries: ZstdDictionaries,
+}
+
+impl BigtableReader {
+    pub async fn new(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
+        let routes = TableRoutes::connect(config).await?;
+        let dictionaries = BufferedBigtableClient::load_dictionaries(config)?;
+        Ok(Self {
+            routes,
+            dictionaries,
+        })
+    }
+
+    pub fn from_client(client: Client) -> Self {
+        Self {
+            routes: TableRoutes::from_client(client),
+            dictionaries: ZstdDictionaries::default(),
+        }
+    }
+
+    /// Use the dictionaries to decompress the cells compressed with a zstd dictionary
+    pub fn with_dictionaries(mut self, dictionaries: ZstdDictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    /// Decode a cell as written by the plugin, compressed and of any of the schema versions
+    pub fn decode_compressed_cell<T: Message + Default>(
+        &self,
+        key: &str,
+        value: &[u8],
+    ) -> Result<T, BigtableReaderError> {
+        decode_compressed_cell(key, value, &self.dictionaries)
+    }
+
+    /// Scan at most `limit` account rows starting at the given key. The data of the
+    /// chunked accounts is not reassembled.
+    pub async fn scan_accounts(
+        &self,
+        start_at: Option<RowKey>,
+        limit: i64,
+    ) -> Result<Vec<(RowKey, accounts::Account)>, BigtableReaderError> {
+        self.routes
+            .client(ACCOUNT_TABLE)
+            .client()
+            .get_row_data(&self.routes.table(ACCOUNT_TABLE), start_at, None, limit)
+            .await?
+            .into_iter()
+            .map(|(key, row_data)| {
+                let account: ChunkedAccount =
+                    decode_protobuf_cell(&key, &row_data, &self.dictionaries)?;
+                Ok((key, accounts::Account::from(account)))
+            })
+            .collect()
+    }
+
+    /// Scan at most `limit` account rows starting at the given key, reassembling the
+    /// chunked accounts
+    pub async fn scan_accounts_with_data(
+        &self,
+        start_at: Option<RowKey>,
+        limit: i64,
+    ) -> Result<Vec<(RowKey, accounts::Account)>, BigtableReaderError> {
+        let rows = self
+            .routes
+            .client(ACCOUNT_TABLE)
+            .client()
+            .get_row_data(&self.routes.table(ACCOUNT_TABLE), start_at, None, limit)
+            .await?;
+        let mut accounts = Vec::with_capacity(rows.len());
+        for (key, row_data) in rows {
+            let account: ChunkedAccount =
+                decode_protobuf_cell(&key, &row_data, &self.dictionaries)?;
+            let account = self.load_account_data(key.clone(), account).await?;
+            accounts.push((key, account));
+        }
+        Ok(accounts)
+    }
+
+    /// Get the latest state of the account, reassembling it from its chunks if needed.
+    pub async fn get_account(
+        &self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<accounts::Account>, BigtableReaderError> {
+        let key = pubkey.to_string();
+        let row_data = match self
+            .routes
+            .client(ACCOUNT_TABLE)
+            .client()
+            .get_single_row_data(&self.routes.table(ACCOUNT_TABLE), key.clone())
+            .await
+        {
+            Ok(row_data) => row_data,
+            Err(BigtableError::RowNotFound) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let account: ChunkedAccount = decode_protobuf_cell(&key, &row_data, &self.dictionaries)?;
+        self.load_account_data(key, account).await.map(Some)
+    }
+
+    /// Get the latest state of the accounts found, keyed by pubkey
+    pub async fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, accounts::Account>, BigtableReaderError> {
+        let keys: Vec<RowKey> = pubkeys.iter().map(|pubkey| pubkey.to_string()).collect();
+        let rows = self
+            .routes
+            .client(ACCOUNT_TABLE)
+            .client()
+            .get_multi_row_data(&self.routes.table(ACCOUNT_TABLE), &keys)
+            .await?;
+
+        let mut accounts = HashMap::with_capacity(rows.len());
+        for (key, row_data) in rows {
+            let account: ChunkedAccount =
+                decode_protobuf_cell(&key, &row_data, &self.dictionaries)?;
+            let account = self.load_account_data(key, account).await?;
+            accounts.insert(Pubkey::new(&account.pubkey), account);
+        }
+        Ok(accounts)
+    }
+
+    /// Rebuild the account from its data chunks if it is stored in chunks
+    async fn load_account_data(
+        &self,
+        key: RowKey,
+        account: ChunkedAccount,
+    ) -> Result<accounts::Account, BigtableReaderError> {
+        if account.data_chunks == 0 {
+            return Ok(accounts::Account::from(account));
+        }
+
+        let chunk_keys: Vec<RowKey> = (0..account.data_chunks)
+            .map(|index| account_chunk_row_key(&key, account.write_version, index))
+            .collect();
+        let chunks = self
+            .routes
+            .client(ACCOUNT_CHUNK_TABLE)
+            .client()
+            .get_multi_row_data(&self.routes.table(ACCOUNT_CHUNK_TABLE), &chunk_keys)
+            .await?
+            .into_iter()
+            .map(|(chunk_key, row_data)| {
+                decode_protobuf_cell::<AccountDataChunk>(&chunk_key, &row_data, &self.dictionaries)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        reassemble_account(account, chunks)
+            .map_err(|msg| BigtableReaderError::CorruptAccount { key, msg })
+    }
+
+    /// Get the latest status of the slot
+    pub async fn get_slot(&self, slot: u64) -> Result<Option<slots::Slot>, BigtableReaderError> {
+        let key = slot.to_string();
+        match self
+            .routes
+            .client(SLOT_TABLE)
+            .client()
+            .get_single_row_data(&self.routes.table(SLOT_TABLE), key.clone())
+            .await
+        {
+            Ok(row_data) => decode_protobuf_cell(&key, &row_data, &self.dictionaries).map(Some),
+            Err(BigtableError::RowNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Scan at most `limit` slot rows starting at the given key, in the order of the
+    /// decimal keys
+    pub async fn scan_slots(
+        &self,
+        start_at: Option<RowKey>,
+        limit: i64,
+    ) -> Result<Vec<(RowKey, slots::Slot)>, BigtableReaderError> {
+        self.routes
+            .client(SLOT_TABLE)
+            .client()
+            .get_row_data(&self.routes.table(SLOT_TABLE), start_at, None, limit)
+            .await?
+            .into_iter()
+            .map(|(key, row_data)| {
+                let slot = decode_protobuf_cell(&key, &row_data, &self.dictionaries)?;
+                Ok((key, slot))
+            })
+            .collect()
+    }
+
+    /// Get the slots stored within the range, inclusive. The slot rows are keyed by the
+    /// decimal slot which does not sort numerically, so each of the slots is looked up.
+    pub async fn get_slots(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<Vec<slots::Slot>, BigtableReaderError> {
+        let keys: Vec<RowKey> = (start_slot..=end_slot)
+            .map(|slot| slot.to_string())
+            .collect();
+        let mut slots = self
+            .routes
+            .client(SLOT_TABLE)
+            .client()
+            .get_multi_row_data(&self.routes.table(SLOT_TABLE), &keys)
+            .await?
+            .into_iter()
+            .map(|(key, row_data)| {
+                decode_protobuf_cell::<slots::Slot>(&key, &row_data, &self.dictionaries)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        slots.sort_by_key(|slot| slot.slot);
+        Ok(slots)
+    }
+
+    /// Get the transaction with the signature, looked up in the signature index
+    pub async fn get_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<DbTransaction>, BigtableReaderError> {
+        let key = signature.to_string();
+        let row_key: RowKey = match self
+            .get_bincode_row(TRANSACTION_BY_SIGNATURE_TABLE, key)
+            .await?
+        {
+            Some(row_key) => row_key,
+            None => return Ok(None),
+        };
+        self.get_bincode_row(TRANSACTION_TABLE, row_key).await
+    }
+
+    /// Get the metadata of the block of the slot
+    pub async fn get_block(&self, slot: u64) -> Result<Option<DbBlockInfo>, BigtableReaderError> {
+        self.get_bincode_row(BLOCK_TABLE, block_row_key(slot)).await
+    }
+
+    async fn get_bincode_row<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        key: RowKey,
+    ) -> Result<Option<T>, BigtableReaderError> {
+        match self
+            .routes
+            .client(table)
+            .client()
+            .get_single_row_data(&self.routes.table(table), key.clone())
+            .await
+        {
+            Ok(row_data) => decode_bincode_cell(&key, &row_data, &self.dictionaries).map(Some),
+            Err(BigtableError::RowNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Decode the protobuf cell of a row written by the plugin, of any of the schema versions
+pub fn decode_protobuf_cell<T: Message + Default>(
+    key: &str,
+    row_data: &RowData,
+    dictionaries: &ZstdDictionaries,
+) -> Result<T, BigtableReaderError> {
+    let (_, value) = row_data
+        .iter()
+        .find(|(name, _)| name == "proto")
+        .ok_or_else(|| BigtableReaderError::MissingCell(key.to_string()))?;
+    decode_compressed_cell(key, value, dictionaries)
+}
+
+/// Decode the bincode cell of a row written by the plugin, for the transactions and blocks
+pub fn decode_bincode_cell<T: DeserializeOwned>(
+    key: &str,
+    row_data: &RowData,
+    dictionaries: &ZstdDictionaries,
+) -> Result<T, BigtableReaderError> {
+    let (_, value) = row_data
+        .iter()
+        .find(|(name, _)| name == "bin")
+        .ok_or_else(|| BigtableReaderError::MissingCell(key.to_string()))?;
+    let data = decompress_with_dictionaries(value, dictionaries)?;
+    bincode::deserialize(&data).map_err(|err| BigtableReaderError::BincodeError {
+        key: key.to_string(),
+        err,
+    })
+}
+
+fn decode_compressed_cell<T: Message + Default>(
+    key: &str,
+    value: &[u8],
+    dictionaries: &ZstdDictionaries,
+) -> Result<T, BigtableReaderError> {
+    let data = decompress_with_dictionaries(value, dictionaries)?;
+    decode_cell(&data).map_err(|err| BigtableReaderError::SchemaError {
+        key: key.to_string(),
+        err,
+    })
+}
@@ -0,0 +1,1068 @@
+#[cfg(feature = "ledger")]
+use solana_geyser_plugin_bigtable::{
+    ledger::{block_rows, LedgerReader},
+    transaction_selector::TransactionSelector,
+};
+/// Command line tool to manage the data stored by the Bigtable plugin
+use {
+    clap::{
+        crate_description, crate_name, value_t, value_t_or_exit, App, AppSettings, Arg, ArgMatches,
+        SubCommand,
+    },
+    log::*,
+    serde_json::{json, Value},
+    solana_account_decoder::{UiAccount, UiAccountEncoding},
+    solana_geyser_plugin_bigtable::{
+        accounts_selector::AccountsSelector,
+        bigtable_reader::BigtableReader,
+        dictionary::{train_dictionary, DEFAULT_MAX_DICTIONARY_SIZE},
+        geyser_plugin_bigtable::{GeyserPluginBigtable, GeyserPluginBigtableConfig},
+        models::{accounts, slots},
+        parallel_bigtable_client::{
+            block_metadata::DbBlockInfo, standalone::StandaloneWriter, transaction::DbTransaction,
+        },
+        parquet_export::{
+            export_accounts, export_slots, DEFAULT_ROWS_PER_FILE, DEFAULT_SLOT_PARTITION_SIZE,
+        },
+        replay::{ReplayReport, Replayer, DEFAULT_REPLAY_BATCH_SIZE},
+        schema::encode_cell,
+        snapshot::{stream_accounts, PubkeyRange},
+        verifier::{Verifier, DEFAULT_VERIFY_BATCH_SIZE},
+    },
+    solana_sdk::{account::Account, pubkey::Pubkey, signature::Signature},
+    std::{error::Error, fs, path::PathBuf, process::exit, str::FromStr},
+};
+
+/// The count of account rows read from Bigtable at once
+const SCAN_PAGE_SIZE: i64 = 1000;
+
+/// The maximum count of slots looked up by a range
+const MAX_SLOT_RANGE: u64 = 10_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Json,
+    Table,
+}
+
+impl OutputFormat {
+    fn from_matches(matches: &ArgMatches<'_>) -> Self {
+        match matches.value_of("output_format") {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Table,
+        }
+    }
+}
+
+fn is_pubkey(value: String) -> Result<(), String> {
+    Pubkey::from_str(&value)
+        .map(|_| ())
+        .map_err(|err| format!("{}", err))
+}
+
+fn is_parsable<T: FromStr>(value: String) -> Result<(), String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|err| format!("{}", err))
+}
+
+fn load_config(path: &str) -> Result<GeyserPluginBigtableConfig, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Load the `accounts_selector` of the configuration, which is not part of the typed
+/// configuration
+fn load_accounts_selector(path: &str) -> Result<AccountsSelector, Box<dyn Error>> {
+    let contents: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(GeyserPluginBigtable::create_accounts_selector_from_config(
+        &contents,
+    ))
+}
+
+/// Load the `transaction_selector` of the configuration, which is not part of the typed
+/// configuration
+#[cfg(feature = "ledger")]
+fn load_transaction_selector(path: &str) -> Result<TransactionSelector, Box<dyn Error>> {
+    let contents: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(GeyserPluginBigtable::create_transaction_selector_from_config(&contents))
+}
+
+/// Sample the cells of the accounts of the owner, encoded as they are before being
+/// compressed, scanning at most `max_scan_rows` rows
+async fn sample_account_cells(
+    reader: &BigtableReader,
+    owner: &Pubkey,
+    max_samples: usize,
+    max_scan_rows: usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut samples = vec![];
+    let mut scanned_rows = 0;
+    let mut start_at = None;
+    while samples.len() < max_samples && scanned_rows < max_scan_rows {
+        let page = reader
+            .scan_accounts(start_at.clone(), SCAN_PAGE_SIZE)
+            .await?;
+        let page_len = page.len();
+        // The scan starts at the last key of the previous page, skip it
+        let skip = usize::from(
+            start_at.is_some() && page.first().map(|(key, _)| key) == start_at.as_ref(),
+        );
+
+        let mut last_key = None;
+        for (key, account) in page.into_iter().skip(skip) {
+            scanned_rows += 1;
+            // The accounts stored in chunks are not compressed with the dictionaries
+            if account.owner == owner.as_ref() && !account.data.is_empty() {
+                samples.push(encode_cell(&account));
+            }
+            last_key = Some(key);
+        }
+        if last_key.is_none() || page_len < SCAN_PAGE_SIZE as usize {
+            break;
+        }
+        start_at = last_key;
+    }
+    info!(
+        "Sampled {} accounts of {} out of {} rows",
+        samples.len(),
+        owner,
+        scanned_rows
+    );
+    samples.truncate(max_samples);
+    Ok(samples)
+}
+
+async fn train(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let owner = value_t_or_exit!(matches, "owner", Pubkey);
+    let output = matches.value_of("output").unwrap();
+    let max_samples = value_t_or_exit!(matches, "samples", usize);
+    let max_scan_rows = value_t_or_exit!(matches, "max_scan_rows", usize);
+    let max_size = value_t_or_exit!(matches, "max_size", usize);
+
+    let reader = BigtableReader::new(config).await?;
+    let samples = sample_account_cells(&reader, &owner, max_samples, max_scan_rows).await?;
+    if samples.is_empty() {
+        return Err(format!("No accounts of {} found to train the dictionary", owner).into());
+    }
+
+    let dictionary = train_dictionary(&samples, max_size)?;
+    fs::write(output, &dictionary)?;
+    println!(
+        "Trained a dictionary of {} bytes from {} accounts of {} to {}",
+        dictionary.len(),
+        samples.len(),
+        owner,
+        output
+    );
+    println!("Add it to \"zstd_dictionaries\" with an id not used by any other dictionary.");
+    Ok(())
+}
+
+/// The account as JSON, with the data encoded like the RPC does
+fn account_json(account: &accounts::Account) -> Value {
+    let pubkey = Pubkey::new(&account.pubkey);
+    let ui_account = UiAccount::encode(
+        &pubkey,
+        &Account {
+            lamports: account.lamports,
+            data: account.data.clone(),
+            owner: Pubkey::new(&account.owner),
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        },
+        UiAccountEncoding::Base64,
+        None,
+        None,
+    );
+    json!({
+        "pubkey": pubkey.to_string(),
+        "slot": account.slot,
+        "writeVersion": account.write_version,
+        "updatedOn": account.updated_on.as_ref().map(|updated_on| updated_on.timestamp),
+        "account": ui_account,
+    })
+}
+
+fn slot_json(slot: &slots::Slot) -> Value {
+    json!({
+        "slot": slot.slot,
+        "parent": slot.parent,
+        "status": slot.status,
+        "updatedOn": slot.updated_on.as_ref().map(|updated_on| updated_on.timestamp),
+    })
+}
+
+/// The transaction as JSON, with the keys and signatures base58 encoded
+fn transaction_json(transaction: &DbTransaction) -> Value {
+    let encode = |bytes: &Vec<u8>| bs58::encode(bytes).into_string();
+    let (account_keys, recent_blockhash) =
+        match (&transaction.legacy_message, &transaction.v0_loaded_message) {
+            (Some(message), _) => (&message.account_keys, &message.recent_blockhash),
+            (None, Some(loaded_message)) => (
+                &loaded_message.message.account_keys,
+                &loaded_message.message.recent_blockhash,
+            ),
+            (None, None) => return json!({ "signature": encode(&transaction.signature) }),
+        };
+    json!({
+        "signature": encode(&transaction.signature),
+        "slot": transaction.slot,
+        "index": transaction.index,
+        "isVote": transaction.is_vote,
+        "messageType": transaction.message_type,
+        "signatures": transaction.signatures.iter().map(encode).collect::<Vec<_>>(),
+        "accountKeys": account_keys.iter().map(encode).collect::<Vec<_>>(),
+        "recentBlockhash": encode(recent_blockhash),
+        "meta": transaction.meta,
+    })
+}
+
+fn block_json(block: &DbBlockInfo) -> Value {
+    json!({
+        "slot": block.slot,
+        "blockhash": block.blockhash,
+        "blockTime": block.block_time,
+        "blockHeight": block.block_height,
+        "rewards": block.rewards,
+    })
+}
+
+/// Print the rows aligned in columns under the headers
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(column, header)| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .chain(std::iter::once(header.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let print_row = |row: Vec<&str>| {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(headers.to_vec());
+    for row in rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn print_accounts(format: OutputFormat, accounts: &[accounts::Account]) {
+    if format == OutputFormat::Json {
+        let values: Vec<Value> = accounts.iter().map(account_json).collect();
+        println!("{}", serde_json::to_string_pretty(&values).unwrap());
+        return;
+    }
+    let rows: Vec<Vec<String>> = accounts
+        .iter()
+        .map(|account| {
+            vec![
+                bs58::encode(&account.pubkey).into_string(),
+                bs58::encode(&account.owner).into_string(),
+                account.lamports.to_string(),
+                account.slot.to_string(),
+                account.write_version.to_string(),
+                account.executable.to_string(),
+                account.rent_epoch.to_string(),
+                account.data.len().to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "PUBKEY",
+            "OWNER",
+            "LAMPORTS",
+            "SLOT",
+            "WRITE_VERSION",
+            "EXECUTABLE",
+            "RENT_EPOCH",
+            "DATA_LEN",
+        ],
+        &rows,
+    );
+}
+
+fn print_slots(format: OutputFormat, slots: &[slots::Slot]) {
+    if format == OutputFormat::Json {
+        let values: Vec<Value> = slots.iter().map(slot_json).collect();
+        println!("{}", serde_json::to_string_pretty(&values).unwrap());
+        return;
+    }
+    let rows: Vec<Vec<String>> = slots
+        .iter()
+        .map(|slot| {
+            vec![
+                slot.slot.to_string(),
+                slot.parent
+                    .map(|parent| parent.to_string())
+                    .unwrap_or_default(),
+                slot.status.clone(),
+                slot.updated_on
+                    .as_ref()
+                    .map(|updated_on| updated_on.timestamp.to_string())
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+    print_table(&["SLOT", "PARENT", "STATUS", "UPDATED_ON_MS"], &rows);
+}
+
+fn print_transaction(format: OutputFormat, transaction: &DbTransaction) {
+    if format == OutputFormat::Json {
+        let value = transaction_json(transaction);
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        return;
+    }
+    let status = match &transaction.meta.error {
+        Some(error) => serde_json::to_string(error).unwrap(),
+        None => "ok".to_string(),
+    };
+    print_table(
+        &["SIGNATURE", "SLOT", "INDEX", "IS_VOTE", "FEE", "STATUS"],
+        &[vec![
+            bs58::encode(&transaction.signature).into_string(),
+            transaction.slot.to_string(),
+            transaction
+                .index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            transaction.is_vote.to_string(),
+            transaction.meta.fee.to_string(),
+            status,
+        ]],
+    );
+}
+
+fn print_block(format: OutputFormat, block: &DbBlockInfo) {
+    if format == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&block_json(block)).unwrap()
+        );
+        return;
+    }
+    let optional = |value: Option<i64>| value.map(|value| value.to_string()).unwrap_or_default();
+    print_table(
+        &["SLOT", "BLOCKHASH", "BLOCK_TIME", "BLOCK_HEIGHT", "REWARDS"],
+        &[vec![
+            block.slot.to_string(),
+            block.blockhash.clone(),
+            optional(block.block_time),
+            optional(block.block_height),
+            block.rewards.len().to_string(),
+        ]],
+    );
+}
+
+async fn account_get(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let pubkey = value_t_or_exit!(matches, "pubkey", Pubkey);
+    let reader = BigtableReader::new(config).await?;
+    let account = reader
+        .get_account(&pubkey)
+        .await?
+        .ok_or_else(|| format!("The account {} is not found", pubkey))?;
+    print_accounts(OutputFormat::from_matches(matches), &[account]);
+    Ok(())
+}
+
+async fn slot_get(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let slot = value_t_or_exit!(matches, "slot", u64);
+    let reader = BigtableReader::new(config).await?;
+    let slot = reader
+        .get_slot(slot)
+        .await?
+        .ok_or_else(|| format!("The slot {} is not found", slot))?;
+    print_slots(OutputFormat::from_matches(matches), &[slot]);
+    Ok(())
+}
+
+async fn slot_range(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let start_slot = value_t_or_exit!(matches, "start_slot", u64);
+    let end_slot = value_t_or_exit!(matches, "end_slot", u64);
+    if end_slot < start_slot || end_slot - start_slot >= MAX_SLOT_RANGE {
+        return Err(format!("The range must hold between 1 and {} slots", MAX_SLOT_RANGE).into());
+    }
+    let reader = BigtableReader::new(config).await?;
+    let slots = reader.get_slots(start_slot, end_slot).await?;
+    print_slots(OutputFormat::from_matches(matches), &slots);
+    Ok(())
+}
+
+async fn tx_get(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let signature = value_t_or_exit!(matches, "signature", Signature);
+    let reader = BigtableReader::new(config).await?;
+    let transaction = reader
+        .get_transaction(&signature)
+        .await?
+        .ok_or_else(|| format!("The transaction {} is not found", signature))?;
+    print_transaction(OutputFormat::from_matches(matches), &transaction);
+    Ok(())
+}
+
+async fn block_get(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let slot = value_t_or_exit!(matches, "slot", u64);
+    let reader = BigtableReader::new(config).await?;
+    let block = reader
+        .get_block(slot)
+        .await?
+        .ok_or_else(|| format!("The block of slot {} is not found", slot))?;
+    print_block(OutputFormat::from_matches(matches), &block);
+    Ok(())
+}
+
+async fn verify(
+    config: &GeyserPluginBigtableConfig,
+    config_path: &str,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot_path = PathBuf::from(matches.value_of("snapshot").unwrap());
+    let repair = matches.is_present("repair");
+    let batch_size = value_t_or_exit!(matches, "batch_size", usize);
+
+    // Only the accounts selected by the plugin are expected to be stored
+    let selector = load_accounts_selector(config_path)?;
+
+    let reader = BigtableReader::new(config).await?;
+    let writer = if repair {
+        Some(StandaloneWriter::new(config, None).await?)
+    } else {
+        None
+    };
+    let mut verifier = Verifier::new(reader, writer, batch_size);
+
+    let (mut accounts, snapshot_reader) = stream_accounts(snapshot_path, move |pubkey, owner| {
+        selector.is_account_selected(pubkey, owner)
+    });
+    while let Some(account) = accounts.recv().await {
+        verifier.verify_account(account).await?;
+    }
+    snapshot_reader.await??;
+
+    let report = verifier.finish().await?;
+    print!("{}", report);
+    if report.out_of_sync() > 0 && !repair {
+        return Err(format!(
+            "{} accounts are out of sync, run with --repair to write them again",
+            report.out_of_sync()
+        )
+        .into());
+    }
+    if report.repair_failures > 0 {
+        return Err(format!("{} accounts failed to be repaired", report.repair_failures).into());
+    }
+    Ok(())
+}
+
+async fn backfill(
+    config: &GeyserPluginBigtableConfig,
+    config_path: &str,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot_path = PathBuf::from(matches.value_of("snapshot").unwrap());
+    let range = PubkeyRange {
+        start: value_t!(matches, "start_pubkey", Pubkey).ok(),
+        end: value_t!(matches, "end_pubkey", Pubkey).ok(),
+    };
+
+    // The parallelism of the load overrides the configuration of the plugin
+    let mut config = config.clone();
+    if let Ok(streams) = value_t!(matches, "streams", usize) {
+        config.startup_parallel_streams = Some(streams);
+    }
+    if let Ok(max_in_flight_requests) = value_t!(matches, "max_in_flight_requests", usize) {
+        config.max_in_flight_requests = Some(max_in_flight_requests);
+    }
+    if let Ok(batch_size) = value_t!(matches, "batch_size", usize) {
+        config.startup_batch_size = Some(batch_size);
+    }
+
+    let selector = load_accounts_selector(config_path)?;
+    let mut writer = StandaloneWriter::new(&config, config.startup_accounts_estimate).await?;
+
+    info!("Loading the accounts in the range {}", range);
+    let filter_range = range.clone();
+    let (mut accounts, snapshot_reader) = stream_accounts(snapshot_path, move |pubkey, owner| {
+        filter_range.contains(pubkey) && selector.is_account_selected(pubkey, owner)
+    });
+    let mut loaded = 0;
+    while let Some(account) = accounts.recv().await {
+        writer.write_account(account).await;
+        loaded += 1;
+    }
+    snapshot_reader.await??;
+
+    let write_failures = writer.finish().await;
+    println!("Loaded {} accounts in the range {}", loaded, range);
+    if write_failures > 0 {
+        return Err(format!(
+            "{} accounts failed to be written, load the range {} again",
+            write_failures, range
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Write the blocks and the transactions selected of the rooted slots of a local ledger
+/// within the range
+#[cfg(feature = "ledger")]
+async fn backfill_ledger(
+    config: &GeyserPluginBigtableConfig,
+    config_path: &str,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let ledger_path = PathBuf::from(matches.value_of("ledger").unwrap());
+    let start_slot = value_t_or_exit!(matches, "start_slot", u64);
+    let end_slot = value_t_or_exit!(matches, "end_slot", u64);
+    if end_slot < start_slot {
+        return Err("The end slot precedes the start slot".into());
+    }
+
+    let selector = load_transaction_selector(config_path)?;
+    let ledger = LedgerReader::open(&ledger_path)?;
+    let writer = StandaloneWriter::new(config, None).await?;
+
+    info!(
+        "Loading the blocks of the slots {} to {}",
+        start_slot, end_slot
+    );
+    let (mut blocks, mut transactions, mut skipped_transactions) = (0, 0, 0);
+    for slot in ledger.rooted_slots(start_slot, end_slot)? {
+        let block = match ledger.get_block(slot)? {
+            Some(block) => block,
+            None => {
+                warn!("The block of slot {} is not available in the ledger", slot);
+                continue;
+            }
+        };
+        let rows = block_rows(slot, block, &selector);
+        transactions += rows.transactions.len();
+        skipped_transactions += rows.skipped_transactions;
+        writer.write_block_rows(rows).await.map_err(|err| {
+            format!(
+                "Failed to write the block of slot {}, load the slots from {} again: {}",
+                slot, slot, err
+            )
+        })?;
+        blocks += 1;
+    }
+
+    println!(
+        "Loaded {} blocks and {} transactions of the slots {} to {}",
+        blocks, transactions, start_slot, end_slot
+    );
+    if skipped_transactions > 0 {
+        warn!(
+            "{} transactions could not be sanitized and were skipped",
+            skipped_transactions
+        );
+    }
+    Ok(())
+}
+
+async fn export(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let output = PathBuf::from(matches.value_of("output").unwrap());
+    let tables: Vec<&str> = matches
+        .values_of("tables")
+        .map(|tables| tables.collect())
+        .unwrap_or_else(|| vec!["account", "slot"]);
+    let owner = value_t!(matches, "owner", Pubkey).ok();
+    let rows_per_file = value_t_or_exit!(matches, "rows_per_file", usize);
+    let slot_partition_size = value_t_or_exit!(matches, "slot_partition_size", u64);
+
+    let reader = BigtableReader::new(config).await?;
+    for table in tables {
+        let summary = match table {
+            "account" => export_accounts(&reader, &output, rows_per_file, owner.as_ref()).await?,
+            "slot" => export_slots(&reader, &output, rows_per_file, slot_partition_size).await?,
+            _ => unreachable!(),
+        };
+        println!(
+            "Exported {} rows of the {} table to {} files in {} partitions",
+            summary.rows, table, summary.files, summary.partitions
+        );
+    }
+    Ok(())
+}
+
+async fn replay_failed(
+    config: &GeyserPluginBigtableConfig,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let dir = match matches
+        .value_of("dir")
+        .or(config.error_spill_path.as_deref())
+    {
+        Some(dir) => PathBuf::from(dir),
+        None => return Err("No --dir given and no error_spill_path configured".into()),
+    };
+    let batch_size = value_t_or_exit!(matches, "batch_size", usize);
+
+    // The writes failing again are kept in the files rather than handled by the error
+    // policies of the plugin, which could spill them to the files being replayed
+    let mut config = config.clone();
+    config.error_policy = None;
+    config.panic_on_db_errors = Some(false);
+
+    let reader = BigtableReader::new(&config).await?;
+    let writer = StandaloneWriter::new(&config, None).await?;
+    let replayer = Replayer::new(reader, writer, batch_size);
+
+    let mut total = ReplayReport::default();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "failed")
+        {
+            continue;
+        }
+        let table = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(table) => table.to_string(),
+            None => continue,
+        };
+        info!("Replaying the failed writes of the {} table", table);
+        let report = replayer.replay_file(&table, &path).await?;
+        print!("{}: {}", table, report);
+        total.replayed += report.replayed;
+        total.superseded += report.superseded;
+        total.failed += report.failed;
+    }
+    if total.failed > 0 {
+        return Err(format!(
+            "{} rows failed to be replayed, they are kept in {}",
+            total.failed,
+            dir.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn run(matches: ArgMatches<'_>) -> Result<(), Box<dyn Error>> {
+    let config_path = matches.value_of("config").unwrap();
+    let config = load_config(config_path)?;
+    match matches.subcommand() {
+        ("dictionary", Some(matches)) => match matches.subcommand() {
+            ("train", Some(matches)) => train(&config, matches).await,
+            _ => unreachable!(),
+        },
+        ("account", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => account_get(&config, matches).await,
+            _ => unreachable!(),
+        },
+        ("slot", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => slot_get(&config, matches).await,
+            ("range", Some(matches)) => slot_range(&config, matches).await,
+            _ => unreachable!(),
+        },
+        ("tx", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => tx_get(&config, matches).await,
+            _ => unreachable!(),
+        },
+        ("block", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => block_get(&config, matches).await,
+            _ => unreachable!(),
+        },
+        ("verify", Some(matches)) => verify(&config, config_path, matches).await,
+        ("backfill", Some(matches)) => backfill(&config, config_path, matches).await,
+        #[cfg(feature = "ledger")]
+        ("backfill-ledger", Some(matches)) => backfill_ledger(&config, config_path, matches).await,
+        ("export", Some(matches)) => export(&config, matches).await,
+        ("replay-failed", Some(matches)) => replay_failed(&config, matches).await,
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    solana_logger::setup_with_default("info");
+    let default_max_size = DEFAULT_MAX_DICTIONARY_SIZE.to_string();
+    let default_verify_batch_size = DEFAULT_VERIFY_BATCH_SIZE.to_string();
+    let default_rows_per_file = DEFAULT_ROWS_PER_FILE.to_string();
+    let default_slot_partition_size = DEFAULT_SLOT_PARTITION_SIZE.to_string();
+    let default_replay_batch_size = DEFAULT_REPLAY_BATCH_SIZE.to_string();
+    let app = App::new(crate_name!())
+        .about(crate_description!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .global(true)
+                .help("The plugin configuration file"),
+        )
+        .arg(
+            Arg::with_name("output_format")
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["json", "table"])
+                .default_value("table")
+                .global(true)
+                .help("The format of the data printed"),
+        )
+        .subcommand(
+            SubCommand::with_name("dictionary")
+                .about("Manage the zstd dictionaries used to compress account data")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("train")
+                        .about("Train a dictionary from a sample of the accounts of a program")
+                        .arg(
+                            Arg::with_name("owner")
+                                .long("owner")
+                                .value_name("PUBKEY")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_pubkey)
+                                .help("The owner program of the accounts to sample"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .short("o")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The file to write the dictionary to"),
+                        )
+                        .arg(
+                            Arg::with_name("samples")
+                                .long("samples")
+                                .value_name("COUNT")
+                                .takes_value(true)
+                                .default_value("10000")
+                                .help("The maximum count of accounts to sample"),
+                        )
+                        .arg(
+                            Arg::with_name("max_scan_rows")
+                                .long("max-scan-rows")
+                                .value_name("COUNT")
+                                .takes_value(true)
+                                .default_value("1000000")
+                                .help("The maximum count of account rows to scan for samples"),
+                        )
+                        .arg(
+                            Arg::with_name("max_size")
+                                .long("max-size")
+                                .value_name("BYTES")
+                                .takes_value(true)
+                                .default_value(&default_max_size)
+                                .help("The maximum size of the dictionary"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("account")
+                .about("Read the accounts stored")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the latest state of an account")
+                        .arg(
+                            Arg::with_name("pubkey")
+                                .value_name("PUBKEY")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_pubkey)
+                                .help("The pubkey of the account"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("slot")
+                .about("Read the slots stored")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the latest status of a slot")
+                        .arg(
+                            Arg::with_name("slot")
+                                .value_name("SLOT")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<u64>)
+                                .help("The slot"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("range")
+                        .about("Print the slots stored within a range")
+                        .arg(
+                            Arg::with_name("start_slot")
+                                .value_name("START_SLOT")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<u64>)
+                                .help("The first slot of the range"),
+                        )
+                        .arg(
+                            Arg::with_name("end_slot")
+                                .value_name("END_SLOT")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<u64>)
+                                .help("The last slot of the range, inclusive"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tx")
+                .about("Read the transactions stored")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print a transaction")
+                        .arg(
+                            Arg::with_name("signature")
+                                .value_name("SIGNATURE")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<Signature>)
+                                .help("The signature of the transaction"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("block")
+                .about("Read the block metadata stored")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the metadata of the block of a slot")
+                        .arg(
+                            Arg::with_name("slot")
+                                .value_name("SLOT")
+                                .takes_value(true)
+                                .required(true)
+                                .validator(is_parsable::<u64>)
+                                .help("The slot"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Compare the accounts stored with the accounts of a snapshot")
+                .arg(
+                    Arg::with_name("snapshot")
+                        .long("snapshot")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The snapshot archive, or the accounts directory of a snapshot"),
+                )
+                .arg(Arg::with_name("repair").long("repair").help(
+                    "Write the accounts missing, stale or mismatched again, \
+                             meant to run with the plugin stopped",
+                ))
+                .arg(
+                    Arg::with_name("batch_size")
+                        .long("batch-size")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .default_value(&default_verify_batch_size)
+                        .validator(is_parsable::<usize>)
+                        .help("The count of accounts looked up at once"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backfill")
+                .about("Load the accounts of a snapshot without running a validator")
+                .arg(
+                    Arg::with_name("snapshot")
+                        .long("snapshot")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The snapshot archive, or the accounts directory of a snapshot"),
+                )
+                .arg(
+                    Arg::with_name("start_pubkey")
+                        .long("start-pubkey")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .validator(is_pubkey)
+                        .help("Load the accounts from this pubkey, included"),
+                )
+                .arg(
+                    Arg::with_name("end_pubkey")
+                        .long("end-pubkey")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .validator(is_pubkey)
+                        .help("Load the accounts up to this pubkey, excluded"),
+                )
+                .arg(
+                    Arg::with_name("streams")
+                        .long("streams")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "The count of requests each batch is split into, overriding \
+                               startup_parallel_streams",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("max_in_flight_requests")
+                        .long("max-in-flight")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "The maximum count of concurrent writes, overriding \
+                               max_in_flight_requests",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("batch_size")
+                        .long("batch-size")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help("The count of accounts in each batch, overriding startup_batch_size"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export the tables to partitioned Parquet files")
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The directory to write the files to"),
+                )
+                .arg(
+                    Arg::with_name("tables")
+                        .long("tables")
+                        .value_name("TABLE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .possible_values(&["account", "slot"])
+                        .help("The tables to export, all of them by default"),
+                )
+                .arg(
+                    Arg::with_name("owner")
+                        .long("owner")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .validator(is_pubkey)
+                        .help("Only export the accounts of this owner"),
+                )
+                .arg(
+                    Arg::with_name("rows_per_file")
+                        .long("rows-per-file")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .default_value(&default_rows_per_file)
+                        .validator(is_parsable::<usize>)
+                        .help("The maximum count of rows in each file"),
+                )
+                .arg(
+                    Arg::with_name("slot_partition_size")
+                        .long("slot-partition-size")
+                        .value_name("SLOTS")
+                        .takes_value(true)
+                        .default_value(&default_slot_partition_size)
+                        .validator(is_parsable::<u64>)
+                        .help("The count of slots in each of the slot partitions"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay-failed")
+                .about("Write again the failed writes spilled to disk by the error policies")
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("The directory of the .failed files, error_spill_path by default"),
+                )
+                .arg(
+                    Arg::with_name("batch_size")
+                        .long("batch-size")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .default_value(&default_replay_batch_size)
+                        .validator(is_parsable::<usize>)
+                        .help("The count of rows written at once"),
+                ),
+        );
+    #[cfg(feature = "ledger")]
+    let app = app.subcommand(
+        SubCommand::with_name("backfill-ledger")
+            .about("Load the blocks and transactions of a local ledger within a slot range")
+            .arg(
+                Arg::with_name("ledger")
+                    .long("ledger")
+                    .value_name("DIR")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The ledger directory of a validator"),
+            )
+            .arg(
+                Arg::with_name("start_slot")
+                    .value_name("START_SLOT")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<u64>)
+                    .help("The first slot of the range"),
+            )
+            .arg(
+                Arg::with_name("end_slot")
+                    .value_name("END_SLOT")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<u64>)
+                    .help("The last slot of the range, inclusive"),
+            ),
+    );
+    let matches = app.get_matches();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    if let Err(err) = runtime.block_on(run(matches)) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,59 @@
+/// The checkpoint records the last slot whose accounts are known to be durably
+/// written to Bigtable so that restarts can skip replaying them.
+use {
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        fs, io,
+        path::{Path, PathBuf},
+    },
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The highest slot whose accounts have been flushed to Bigtable.
+    pub slot: u64,
+}
+
+impl Checkpoint {
+    /// Load the checkpoint from `path`. Returns `None` if no checkpoint has been stored yet.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Store the checkpoint to `path`. The file is replaced atomically so that a crash
+    /// while storing never leaves a truncated checkpoint behind.
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        let mut tmp_path = PathBuf::from(path);
+        tmp_path.set_extension("tmp");
+        let contents = serde_json::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_store_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        let checkpoint = Checkpoint { slot: 1234 };
+        checkpoint.store(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(checkpoint));
+
+        fs::write(&path, "not json").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+    }
+}
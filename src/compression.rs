@@ -0,0 +1,347 @@
+/// The compression used for the cells stored in Bigtable. The compressed data is prefixed
+/// with the bincode encoded `CompressionMethod` so that readers can decode cells written
+/// with any of the methods.
+use {
+    crate::dictionary::ZstdDictionaries,
+    enum_iterator::IntoEnumIterator,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        io::{self, BufReader, Read, Write},
+    },
+};
+
+/// The codec used to compress the cells of a table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodec {
+    None,
+    Gzip,
+    Bzip2,
+    Zstd,
+    /// Compress with all the codecs and keep the smallest output
+    Best,
+}
+
+impl Default for CompressionCodec {
+    fn default() -> Self {
+        Self::Best
+    }
+}
+
+impl CompressionCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Zstd => "zstd",
+            Self::Best => "best",
+        }
+    }
+}
+
+/// The compression settings of a table
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub codec: CompressionCodec,
+
+    /// The compression level, the codec's default is used if not set
+    pub level: Option<i32>,
+}
+
+impl CompressionConfig {
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let method = match self.codec {
+            CompressionCodec::None => CompressionMethod::NoCompression,
+            CompressionCodec::Gzip => CompressionMethod::Gzip,
+            CompressionCodec::Bzip2 => CompressionMethod::Bzip2,
+            CompressionCodec::Zstd => CompressionMethod::Zstd,
+            CompressionCodec::Best => return compress_best(data),
+        };
+        compress_with_level(method, self.level, data)
+    }
+}
+
+/// The compression configured for each of the tables. The entry named "*" applies to
+/// the tables not configured explicitly.
+#[derive(Clone, Debug, Default)]
+pub struct TableCompression {
+    tables: HashMap<String, CompressionConfig>,
+    default: CompressionConfig,
+    dictionaries: ZstdDictionaries,
+}
+
+impl TableCompression {
+    pub fn new(mut tables: HashMap<String, CompressionConfig>) -> Self {
+        let default = tables.remove("*").unwrap_or_default();
+        Self {
+            tables,
+            default,
+            dictionaries: ZstdDictionaries::default(),
+        }
+    }
+
+    pub fn with_dictionaries(mut self, dictionaries: ZstdDictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    pub fn for_table(&self, table: &str) -> &CompressionConfig {
+        self.tables.get(table).unwrap_or(&self.default)
+    }
+
+    /// Compress the cell of an account, using the dictionary of its owner if one is loaded
+    /// and compression is enabled for the table. Returns the compressed data along with
+    /// the id of the dictionary used.
+    pub fn compress_owned(
+        &self,
+        table: &str,
+        owner: &[u8],
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Option<u32>), io::Error> {
+        let config = self.for_table(table);
+        match self.dictionaries.for_owner(owner) {
+            Some((id, dictionary)) if config.codec != CompressionCodec::None => Ok((
+                compress_with_dictionary(id, dictionary, config.level, data)?,
+                Some(id),
+            )),
+            _ => Ok((config.compress(data)?, None)),
+        }
+    }
+}
+
+/// A cell already compressed by the plugin, with the codec of its table or a zstd
+/// dictionary, and written to Bigtable as is by the row writer
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompressedCell(pub Vec<u8>);
+
+#[derive(Debug, Serialize, Deserialize, IntoEnumIterator)]
+pub enum CompressionMethod {
+    NoCompression,
+    Bzip2,
+    Gzip,
+    Zstd,
+    /// Zstd with a trained dictionary, the id of the dictionary follows the method
+    ZstdDictionary,
+}
+
+fn decompress_reader<'a, R: Read + 'a>(
+    method: CompressionMethod,
+    mut stream: R,
+    dictionaries: &ZstdDictionaries,
+) -> Result<Box<dyn Read + 'a>, io::Error> {
+    if let CompressionMethod::ZstdDictionary = method {
+        let mut id = [0; 4];
+        stream.read_exact(&mut id)?;
+        let id = u32::from_le_bytes(id);
+        let dictionary = dictionaries.get(id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("dictionary {} is not loaded", id),
+            )
+        })?;
+        return Ok(Box::new(zstd::stream::read::Decoder::with_dictionary(
+            BufReader::new(stream),
+            dictionary,
+        )?));
+    }
+
+    let buf_reader = BufReader::new(stream);
+    let decompress_reader: Box<dyn Read> = match method {
+        CompressionMethod::Bzip2 => Box::new(bzip2::bufread::BzDecoder::new(buf_reader)),
+        CompressionMethod::Gzip => Box::new(flate2::read::GzDecoder::new(buf_reader)),
+        CompressionMethod::Zstd => Box::new(zstd::stream::read::Decoder::new(buf_reader)?),
+        CompressionMethod::NoCompression => Box::new(buf_reader),
+        CompressionMethod::ZstdDictionary => unreachable!(),
+    };
+    Ok(decompress_reader)
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    decompress_with_dictionaries(data, &ZstdDictionaries::default())
+}
+
+/// Decompress the data, looking up the dictionary it was compressed with if any
+pub fn decompress_with_dictionaries(
+    data: &[u8],
+    dictionaries: &ZstdDictionaries,
+) -> Result<Vec<u8>, io::Error> {
+    let method_size = bincode::serialized_size(&CompressionMethod::NoCompression).unwrap();
+    if (data.len() as u64) < method_size {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("data len too small: {}", data.len()),
+        ));
+    }
+    let method = bincode::deserialize(&data[..method_size as usize]).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("method deserialize failed: {}", err),
+        )
+    })?;
+
+    let mut reader = decompress_reader(method, &data[method_size as usize..], dictionaries)?;
+    let mut uncompressed_data = vec![];
+    reader.read_to_end(&mut uncompressed_data)?;
+    Ok(uncompressed_data)
+}
+
+pub fn compress(method: CompressionMethod, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    compress_with_level(method, None, data)
+}
+
+/// Compress the data with the given level, or the method's default level if not specified.
+pub fn compress_with_level(
+    method: CompressionMethod,
+    level: Option<i32>,
+    data: &[u8],
+) -> Result<Vec<u8>, io::Error> {
+    let mut compressed_data = bincode::serialize(&method).unwrap();
+    compressed_data.extend(
+        match method {
+            CompressionMethod::Bzip2 => {
+                let compression = level.map_or_else(bzip2::Compression::best, |level| {
+                    bzip2::Compression::new(level.clamp(1, 9) as u32)
+                });
+                let mut e = bzip2::write::BzEncoder::new(Vec::new(), compression);
+                e.write_all(data)?;
+                e.finish()?
+            }
+            CompressionMethod::Gzip => {
+                let compression = level.map_or_else(flate2::Compression::default, |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                let mut e = flate2::write::GzEncoder::new(Vec::new(), compression);
+                e.write_all(data)?;
+                e.finish()?
+            }
+            CompressionMethod::Zstd => {
+                let mut e =
+                    zstd::stream::write::Encoder::new(Vec::new(), level.unwrap_or(0)).unwrap();
+                e.write_all(data)?;
+                e.finish()?
+            }
+            CompressionMethod::NoCompression => data.to_vec(),
+            CompressionMethod::ZstdDictionary => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "compressing with a dictionary requires the dictionary",
+                ))
+            }
+        }
+        .into_iter(),
+    );
+
+    Ok(compressed_data)
+}
+
+/// Compress the data with the zstd dictionary, recording its id after the method
+pub fn compress_with_dictionary(
+    id: u32,
+    dictionary: &[u8],
+    level: Option<i32>,
+    data: &[u8],
+) -> Result<Vec<u8>, io::Error> {
+    let mut compressed_data = bincode::serialize(&CompressionMethod::ZstdDictionary).unwrap();
+    compressed_data.extend_from_slice(&id.to_le_bytes());
+    let mut e = zstd::stream::write::Encoder::with_dictionary(
+        compressed_data,
+        level.unwrap_or(0),
+        dictionary,
+    )?;
+    e.write_all(data)?;
+    e.finish()
+}
+
+pub fn compress_best(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut candidates = vec![];
+    for method in CompressionMethod::into_enum_iter()
+        .filter(|method| !matches!(method, CompressionMethod::ZstdDictionary))
+    {
+        candidates.push(compress(method, data)?);
+    }
+
+    Ok(candidates
+        .into_iter()
+        .min_by(|a, b| a.len().cmp(&b.len()))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compress_uncompress() {
+        let data = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        assert_eq!(
+            decompress(&compress_best(&data).expect("compress_best")).expect("decompress"),
+            data
+        );
+    }
+
+    #[test]
+    fn test_compress() {
+        let data = vec![0; 256];
+        assert!(compress_best(&data).expect("compress_best").len() < data.len());
+    }
+
+    #[test]
+    fn test_table_compression() {
+        let config: HashMap<String, CompressionConfig> = serde_json::from_str(
+            r#"{"account": {"codec": "zstd", "level": 19}, "*": {"codec": "none"}}"#,
+        )
+        .unwrap();
+        let compression = TableCompression::new(config);
+        assert_eq!(
+            compression.for_table("account").codec,
+            CompressionCodec::Zstd
+        );
+        assert_eq!(compression.for_table("slot").codec, CompressionCodec::None);
+
+        let data = vec![7; 1024];
+        for table in ["account", "slot"] {
+            let compressed = compression.for_table(table).compress(&data).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_compress_with_dictionary() {
+        let owner = [1; 32];
+        let samples: Vec<Vec<u8>> = (0..1000u32)
+            .map(|i| {
+                let mut data = vec![0; 165];
+                data[..4].copy_from_slice(&i.to_le_bytes());
+                data[64..72].copy_from_slice(b"SPLTOKEN");
+                data
+            })
+            .collect();
+        let dictionary = crate::dictionary::train_dictionary(&samples, 4096).unwrap();
+        let mut dictionaries = ZstdDictionaries::default();
+        dictionaries.insert(7, &owner, dictionary.clone()).unwrap();
+        // Each owner has a single dictionary, and each id a single owner
+        assert!(dictionaries.insert(8, &owner, dictionary.clone()).is_err());
+        assert!(dictionaries.insert(7, &[3; 32], dictionary).is_err());
+        let compression = TableCompression::new(HashMap::default()).with_dictionaries(dictionaries);
+
+        let (compressed, id) = compression
+            .compress_owned("account", &owner, &samples[42])
+            .unwrap();
+        assert_eq!(id, Some(7));
+        assert_eq!(
+            decompress_with_dictionaries(&compressed, &compression.dictionaries).unwrap(),
+            samples[42]
+        );
+        // Readers without the dictionary fail instead of returning garbage
+        assert!(decompress(&compressed).is_err());
+
+        let (compressed, id) = compression
+            .compress_owned("account", &[2; 32], &samples[42])
+            .unwrap();
+        assert_eq!(id, None);
+        assert_eq!(decompress(&compressed).unwrap(), samples[42]);
+    }
+}
@@ -0,0 +1,98 @@
+/// Zstd dictionaries trained on the cells of the accounts owned by a program. Accounts of
+/// the same program share layouts, so compressing their cells with a dictionary trained
+/// on a sample of them is much more effective than compressing each cell on its own.
+use {
+    serde_derive::{Deserialize, Serialize},
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, fs, io, str::FromStr, sync::Arc},
+};
+
+/// The default maximum size in bytes of a trained dictionary
+pub const DEFAULT_MAX_DICTIONARY_SIZE: usize = 112640;
+
+/// A dictionary to be loaded by the plugin
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZstdDictionaryConfig {
+    /// The id of the dictionary recorded in the cells compressed with it. Ids must never be
+    /// reused for a different dictionary, as readers would fail to decompress the cells.
+    pub id: u32,
+
+    /// The owner program of the accounts compressed with the dictionary
+    pub owner: String,
+
+    /// The path of the dictionary file
+    pub path: String,
+}
+
+/// The dictionaries loaded, by id and by owner program
+#[derive(Clone, Debug, Default)]
+pub struct ZstdDictionaries {
+    by_id: HashMap<u32, Arc<Vec<u8>>>,
+    by_owner: HashMap<Vec<u8>, u32>,
+}
+
+impl ZstdDictionaries {
+    pub fn load(configs: &[ZstdDictionaryConfig]) -> Result<Self, io::Error> {
+        let mut dictionaries = Self::default();
+        for config in configs {
+            let owner = Pubkey::from_str(&config.owner).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid owner {} of dictionary {}: {}",
+                        config.owner, config.id, err
+                    ),
+                )
+            })?;
+            let dictionary = fs::read(&config.path)?;
+            dictionaries.insert(config.id, owner.as_ref(), dictionary)?;
+        }
+        Ok(dictionaries)
+    }
+
+    pub fn insert(&mut self, id: u32, owner: &[u8], dictionary: Vec<u8>) -> Result<(), io::Error> {
+        if self.by_id.contains_key(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Duplicate dictionary id {}", id),
+            ));
+        }
+        if let Some(other_id) = self.by_owner.get(owner) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Dictionaries {} and {} have the same owner {}",
+                    other_id,
+                    id,
+                    Pubkey::new(owner)
+                ),
+            ));
+        }
+        self.by_id.insert(id, Arc::new(dictionary));
+        self.by_owner.insert(owner.to_vec(), id);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.by_id.get(&id).map(|dictionary| dictionary.as_slice())
+    }
+
+    /// The id and the dictionary to compress the accounts of the owner with, if any
+    pub fn for_owner(&self, owner: &[u8]) -> Option<(u32, &[u8])> {
+        let id = *self.by_owner.get(owner)?;
+        self.get(id).map(|dictionary| (id, dictionary))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+}
+
+/// Train a dictionary of at most `max_size` bytes from the sample of account cells, which
+/// must be encoded as they are when compressed
+pub fn train_dictionary<S: AsRef<[u8]>>(
+    samples: &[S],
+    max_size: usize,
+) -> Result<Vec<u8>, io::Error> {
+    zstd::dict::from_samples(samples, max_size)
+}
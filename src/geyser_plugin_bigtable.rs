@@ -1,7 +1,14 @@
 /// Main entry for the Bigtable plugin
 use {
     crate::{
-        accounts_selector::AccountsSelector, parallel_bigtable_client::ParallelBigtableClient,
+        accounts_selector::AccountsSelector,
+        compression::CompressionConfig,
+        dictionary::ZstdDictionaryConfig,
+        parallel_bigtable_client::{
+            account::ReadableAccountInfo, error_policy::ErrorPolicyConfig,
+            json_lines::JsonLinesConfig, retry::RetryConfig, table_routing::TableRoutingConfig,
+            work_queue::BackpressurePolicy, ParallelBigtableClient,
+        },
         transaction_selector::TransactionSelector,
     },
     bs58,
@@ -10,19 +17,61 @@ use {
     serde_json,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPlugin, GeyserPluginError, ReplicaAccountInfoVersions, ReplicaBlockInfoVersions,
-        ReplicaTransactionInfoVersions, Result, SlotStatus,
+        ReplicaTransactionInfo, ReplicaTransactionInfoVersions, Result, SlotStatus,
     },
     solana_measure::measure::Measure,
     solana_metrics::*,
-    std::{fs::File, io::Read, time::Duration},
+    std::{collections::HashMap, fs::File, io::Read, time::Duration},
     thiserror::Error,
 };
 
+/// The options holding a file or directory the sink writes to, as JSON pointers
+const SINK_PATH_OPTIONS: [&str; 4] = [
+    "/checkpoint_path",
+    "/spill_path",
+    "/error_spill_path",
+    "/json_lines/path",
+];
+
 #[derive(Default)]
 pub struct GeyserPluginBigtable {
-    client: Option<ParallelBigtableClient>,
-    accounts_selector: Option<AccountsSelector>,
-    transaction_selector: Option<TransactionSelector>,
+    /// The destinations the notifications are fanned out to
+    sinks: Vec<Sink>,
+}
+
+/// A destination of the notifications, with its own selectors, client and queue
+struct Sink {
+    name: String,
+    client: ParallelBigtableClient,
+    accounts_selector: AccountsSelector,
+    transaction_selector: TransactionSelector,
+}
+
+impl Sink {
+    fn new(name: String, config: &serde_json::Value) -> Result<Self> {
+        let result: serde_json::Result<GeyserPluginBigtableConfig> =
+            serde_json::from_value(config.clone());
+        let parsed_config = match result {
+            Err(err) => {
+                return Err(GeyserPluginError::ConfigFileReadError {
+                    msg: format!(
+                        "The config of the sink {} is not in the JSON format expected: {:?}",
+                        name, err
+                    ),
+                })
+            }
+            Ok(parsed_config) => parsed_config,
+        };
+        info!("Creating the sink {}", name);
+        Ok(Self {
+            client: ParallelBigtableClient::new(&parsed_config)?,
+            accounts_selector: GeyserPluginBigtable::create_accounts_selector_from_config(config),
+            transaction_selector: GeyserPluginBigtable::create_transaction_selector_from_config(
+                config,
+            ),
+            name,
+        })
+    }
 }
 
 impl std::fmt::Debug for GeyserPluginBigtable {
@@ -61,18 +110,107 @@ pub struct GeyserPluginBigtableConfig {
     /// Bigtable timeout
     pub timeout: Option<Duration>,
 
-    /// Controls the number of threads establishing connections to
-    /// the Bigtable server. The default is 10.
+    /// Deprecated, the count of the workers writing to Bigtable concurrently. It is the
+    /// maximum count of requests in flight when `max_in_flight_requests` is not set.
     pub threads: Option<usize>,
 
+    /// Controls the number of threads of the runtime writing to the Bigtable server.
+    /// The default is 4.
+    pub runtime_threads: Option<usize>,
+
+    /// The maximum count of `MutateRows` requests in flight. The notifications are
+    /// blocked once the requests and the queue are full. The default is 100.
+    pub max_in_flight_requests: Option<usize>,
+
+    /// The maximum count of `MutateRows` requests in flight for the slot, block and
+    /// transaction writes, separate from the account writes. The default is 16.
+    pub priority_in_flight_requests: Option<usize>,
+
+    /// The maximum count of account updates in the queue. The default is 40960.
+    pub queue_capacity: Option<usize>,
+
+    /// The maximum count of slot, block and transaction items in the priority lane of the
+    /// queue. The default is 8192.
+    pub priority_queue_capacity: Option<usize>,
+
+    /// The maximum approximate size in bytes of the account updates in the queue. The
+    /// priority lane is bounded by its count of items alone. The default is 2GB.
+    pub queue_capacity_bytes: Option<usize>,
+
+    /// What happens to the notifications once the queue is full. The default is to block.
+    pub backpressure_policy: Option<BackpressurePolicy>,
+
+    /// The directory of the queue the account updates are spilled to with the "spill"
+    /// backpressure policy.
+    pub spill_path: Option<String>,
+
     /// Controls the batch size when bulk loading accounts.
     /// The default is 10.
     pub batch_size: Option<usize>,
 
+    /// Controls the maximum encoded size in bytes of a batch of accounts, a batch is
+    /// written once either the count or the size limit is reached. The default is 16MB.
+    pub batch_bytes: Option<usize>,
+
+    /// Accounts whose encoding is larger than this size in bytes are stored in chunks
+    /// of this size in the `account_chunk` table. The default is 8MB.
+    pub max_account_cell_bytes: Option<usize>,
+
+    /// Controls the maximum count of slots in a batch of slot status updates.
+    /// The default is 100.
+    pub slot_batch_size: Option<usize>,
+
+    /// The maximum time in milliseconds a slot status update is buffered before its
+    /// batch is written. The default is 400.
+    pub slot_batch_interval_ms: Option<u64>,
+
+    /// Controls the maximum count of transactions in a batch. The default is 100.
+    pub transaction_batch_size: Option<usize>,
+
+    /// The maximum time in milliseconds a transaction is buffered before its batch is
+    /// written. The default is 400.
+    pub transaction_batch_interval_ms: Option<u64>,
+
+    /// Controls the maximum count of accounts in a batch when bulk loading the
+    /// snapshot accounts during startup. The default is 1000.
+    pub startup_batch_size: Option<usize>,
+
+    /// Controls the maximum encoded size in bytes of a batch when bulk loading the
+    /// snapshot accounts during startup. The default is 32MB.
+    pub startup_batch_bytes: Option<usize>,
+
+    /// Controls the number of MutateRows requests a startup batch is split into and
+    /// written in parallel. The default is 4.
+    pub startup_parallel_streams: Option<usize>,
+
+    /// The estimated count of accounts in the snapshot, used to report the ETA
+    /// of the startup bulk loading.
+    pub startup_accounts_estimate: Option<u64>,
+
     /// Controls whether to panic the validator in case of errors
     /// writing to Bigtable server. The default is false
     pub panic_on_db_errors: Option<bool>,
 
+    /// What happens to the writes still failing once retried, per table, the "*" key
+    /// configures the tables not listed.
+    pub error_policy: Option<HashMap<String, ErrorPolicyConfig>>,
+
+    /// The directory the failed writes are spilled to with the "spill-to-disk" error
+    /// policy.
+    pub error_spill_path: Option<String>,
+
+    /// How long in milliseconds the end of startup waits for the workers to write out
+    /// their pending batches. The default is 300000.
+    pub end_of_startup_timeout_ms: Option<u64>,
+
+    /// How long in milliseconds the shutdown waits for the queue to be drained and the
+    /// pending batches to be written. The default is 30000.
+    pub shutdown_timeout_ms: Option<u64>,
+
+    /// The backoff of the retried writes and the circuit breaker pausing the writes
+    /// while Bigtable is unavailable.
+    pub retry: Option<RetryConfig>,
+
     /// Indicates whether to store historical data for accounts
     pub store_account_historical_data: Option<bool>,
 
@@ -83,7 +221,40 @@ pub struct GeyserPluginBigtableConfig {
     pub index_token_mint: Option<bool>,
 
     /// Controls whether to perform any writes during start-up phase. The default is true
-    pub write_during_startup: Option<bool>
+    pub write_during_startup: Option<bool>,
+
+    /// The path of the file recording the last slot durably written at the end of startup.
+    /// When set, accounts notified during startup whose slot is not newer than the
+    /// checkpoint are skipped.
+    pub checkpoint_path: Option<String>,
+
+    /// The compression codec and level of each table, keyed by the table name. The entry
+    /// named "*" applies to the tables not listed. The default is to compress each cell
+    /// with all the codecs and keep the smallest output.
+    pub compression: Option<HashMap<String, CompressionConfig>>,
+
+    /// The zstd dictionaries used to compress the accounts of their owner programs. The
+    /// dictionaries can be trained with the `geyser-bigtable dictionary train` command.
+    pub zstd_dictionaries: Option<Vec<ZstdDictionaryConfig>>,
+
+    /// When set, the work items are written as JSON lines to rotating files instead of
+    /// Bigtable, which is not connected to.
+    pub json_lines: Option<JsonLinesConfig>,
+
+    /// The instance, app profile and name of each table, keyed by the table name. The
+    /// tables not listed are in the instance of the top level.
+    pub tables: Option<HashMap<String, TableRoutingConfig>>,
+
+    /// Prepended to the name of every table, so that the data of several clusters can be
+    /// stored in one instance. The default is no prefix.
+    pub table_prefix: Option<String>,
+
+    /// The name of the sink in the logs
+    pub name: Option<String>,
+
+    /// The destinations to fan the notifications out to, each with the options of the
+    /// top level. The options not set by a sink are inherited from the top level.
+    pub sinks: Option<Vec<serde_json::Value>>,
 }
 
 #[derive(Error, Debug)]
@@ -96,6 +267,9 @@ pub enum GeyserPluginBigtableError {
 
     #[error("Error preparing data store schema. Error message: ({msg})")]
     ConfigurationError { msg: String },
+
+    #[error("Error writing the accounts at the end of startup. Error message: ({msg})")]
+    EndOfStartupError { msg: String },
 }
 
 impl GeyserPlugin for GeyserPluginBigtable {
@@ -138,12 +312,104 @@ impl GeyserPlugin for GeyserPluginBigtable {
     /// }
     /// "store_account_historical_data", optional, set it to 'true', to store historical account data to account_audit
     /// table.
-    /// * "threads" optional, specifies the number of worker threads for the plugin. A thread
-    /// maintains a Bigtable connection to the server. The default is '10'.
+    /// * "runtime_threads" optional, specifies the number of threads of the runtime writing to Bigtable.
+    /// The default is '4'.
+    /// * "max_in_flight_requests" optional, specifies the maximum count of concurrent requests to
+    /// Bigtable. The default is the deprecated "threads" if set, '100' otherwise.
+    /// * "threads" optional, deprecated, the count of the workers writing to Bigtable concurrently,
+    /// superseded by "max_in_flight_requests".
+    /// * "priority_in_flight_requests" optional, specifies the maximum count of concurrent requests to
+    /// Bigtable writing slots, blocks and transactions. The default is '16'.
+    /// * "queue_capacity" and "queue_capacity_bytes" optional, the maximum count of account updates and
+    /// the approximate size in bytes of the account updates queued. The defaults are '40960' and '2147483648'.
+    /// * "priority_queue_capacity" optional, the maximum count of slot, block and transaction items
+    /// queued in the priority lane. The default is '8192'.
+    /// * "backpressure_policy" optional, what happens to the notifications once the queue is full:
+    /// "block" the notifying thread, "drop_newest" drop the item, "drop_accounts" drop the account
+    /// updates but block for the other items, or "spill" the account updates to a queue on the local
+    /// disk at "spill_path". The default is "block". With several "sinks", the items it would block
+    /// on are dropped.
     /// * "batch_size" optional, specifies the batch size of bulk insert when the AccountsDb is created
     /// from restoring a snapshot. The default is '10'.
+    /// * "batch_bytes" optional, specifies the maximum encoded size in bytes of a batch of accounts.
+    /// The default is '16777216'.
+    /// * "max_account_cell_bytes" optional, accounts larger than this are stored in chunks in the
+    /// `account_chunk` table. The default is '8388608'.
+    /// * "slot_batch_size" and "slot_batch_interval_ms", optional, the slot status updates are written
+    /// in batches of at most this count of slots, or after this interval. Only the latest status of each
+    /// slot is written. The defaults are '100' slots and '400' milliseconds.
+    /// * "transaction_batch_size" and "transaction_batch_interval_ms", optional, the transactions are
+    /// written in batches of at most this count of transactions, or after this interval. The defaults
+    /// are '100' transactions and '400' milliseconds.
+    /// * "startup_batch_size", "startup_batch_bytes" and "startup_parallel_streams", optional, control
+    /// the batches used to bulk load the snapshot accounts during startup. The defaults are '1000'
+    /// accounts, '33554432' bytes and '4' parallel MutateRows requests.
+    /// * "startup_accounts_estimate", optional, the estimated count of accounts in the snapshot used
+    /// to report the ETA of the startup bulk loading.
     /// * "panic_on_db_errors", optional, contols if to panic when there are errors replicating data to the
-    /// Bigtable database. The default is 'false'.
+    /// Bigtable database. The default is 'false'. It is the default of the "error_policy".
+    /// * "error_policy", optional, what happens to the writes still failing once retried, per table:
+    /// "abort" the validator once the error budget is exhausted, "pause-and-retry" the write, write the
+    /// cells to a file per table under "error_spill_path" with "spill-to-disk", or "skip" it. The error
+    /// budget is exhausted after "max_consecutive_failures" writes failed in a row or the writes failed for
+    /// "max_outage_ms", and by the first failure if neither is set. The writes are retried after
+    /// "pause_ms" (default '1000') until then. For example:
+    /// "error_policy" : {
+    ///     "account" : { "action" : "pause-and-retry" },
+    ///     "slot" : { "action" : "abort", "max_consecutive_failures" : 10, "max_outage_ms" : 300000 },
+    ///     "*" : { "action" : "skip" }
+    /// }
+    /// The default is "abort" if "panic_on_db_errors" is set, "skip" otherwise.
+    /// * "end_of_startup_timeout_ms", optional, how long the end of startup waits for the workers to
+    /// process the work items queued and write out their pending batches before failing. The default is
+    /// '300000'.
+    /// * "shutdown_timeout_ms", optional, how long the shutdown waits for the work items queued and the
+    /// pending batches to be written, the count of the items lost is reported past it. The default is '30000'.
+    /// * "retry", optional, the exponential backoff of the writes failing with a transient error:
+    /// "initial_interval_ms" (default '100'), "max_interval_ms" (default '10000'), "multiplier" (default
+    /// '2'), "randomization_factor" (default '0.5') and "max_elapsed_ms" (default '60000'). Once
+    /// "circuit_breaker_threshold" (default '5') writes failed in a row the writes are paused for
+    /// "circuit_breaker_open_ms" (default '30000'), the notifications are buffered in the queue meanwhile.
+    /// The connection of a worker is rebuilt once "reconnect_threshold" (default '3') requests in a row
+    /// failed with a connection error. The workers also retry connecting with the backoff at startup.
+    /// * "compression", optional, the codec ("none", "gzip", "bzip2", "zstd" or "best") and level used
+    /// for each table, for example:
+    /// "compression" : {
+    ///     "account" : { "codec" : "zstd", "level" : 3 },
+    ///     "*" : { "codec" : "best" }
+    /// }
+    /// * "zstd_dictionaries", optional, the zstd dictionaries to compress the data of the accounts
+    /// owned by the given programs with, for example:
+    /// "zstd_dictionaries" : [
+    ///     { "id" : 1, "owner" : "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA", "path" : "/home/solana/token.dict" }
+    /// ]
+    /// * "json_lines", optional, writes the work items as JSON lines to rotating files in the
+    /// given directory instead of Bigtable, for local development. A file is started every
+    /// `max_file_bytes`, and only the latest `max_files` files of each lane are kept if set:
+    /// "json_lines" : { "path" : "/tmp/geyser", "max_file_bytes" : 67108864, "max_files" : 10 }
+    /// * "tables", optional, routes the tables to their own instance, app profile or table name,
+    /// so that the transactions can be written with another routing policy than the latest state of
+    /// the accounts. The options not set are those of the top level, for example:
+    /// "tables" : {
+    ///     "transaction" : { "instance" : "geyser-history", "app_profile_id" : "batch" },
+    ///     "slot" : { "table" : "slot_v2" }
+    /// }
+    /// * "table_prefix", optional, prepended to the name of every table, the names set in "tables"
+    /// included, to store the data of several clusters in one instance, for example "mainnet_" and
+    /// "devnet_". The reads use the prefix as well.
+    /// * "sinks", optional, the destinations to fan the notifications out to, for example two
+    /// Bigtable instances and a file. Each sink takes the options of the top level, which it
+    /// inherits unless it sets them, and has its own selectors, batching, queue and error
+    /// policies. The sinks must not share "checkpoint_path", "spill_path", "error_spill_path" or
+    /// the "json_lines" path, so these are set by each sink rather than at the top level. With
+    /// several sinks the queues never block, the items the backpressure policy would block on
+    /// are dropped instead, so that a sink whose queue is full does not hold up the others:
+    /// "sinks" : [
+    ///     { "name" : "us", "instance" : "geyser-us" },
+    ///     { "name" : "eu", "instance" : "geyser-eu", "accounts_selector" : { "owners" : \["..."\] } }
+    /// ]
+    /// * "checkpoint_path", optional, the file recording the last slot durably written. At restart,
+    /// accounts from the snapshot whose slot is not newer than the checkpoint are not written again.
     /// * "transaction_selector", optional, controls if and what transaction to store. If this field is missing
     /// None of the transction is stored.
     /// "transaction_selector" : {
@@ -164,7 +430,7 @@ impl GeyserPlugin for GeyserPluginBigtable {
     ///    "libpath": "/home/solana/target/release/libsolana_geyser_plugin_bigtable.so",
     ///    "credential_path": "/home/solana/bigtable_cred.txt",
     ///    "user": "solana",
-    ///    "threads": 10,
+    ///    "max_in_flight_requests": 10,
     ///    "accounts_selector" : {
     ///       "owners" : ["9oT9R5ZyRovSVnt37QvVoBttGpNqR3J7unkb567NP8k3"]
     ///    }
@@ -181,24 +447,29 @@ impl GeyserPlugin for GeyserPluginBigtable {
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
-        let result: serde_json::Value = serde_json::from_str(&contents).unwrap();
-        self.accounts_selector = Some(Self::create_accounts_selector_from_config(&result));
-        self.transaction_selector = Some(Self::create_transaction_selector_from_config(&result));
-
-        let result: serde_json::Result<GeyserPluginBigtableConfig> =
-            serde_json::from_str(&contents);
-        match result {
+        let config: serde_json::Value = match serde_json::from_str(&contents) {
+            Ok(config) => config,
             Err(err) => {
                 return Err(GeyserPluginError::ConfigFileReadError {
-                    msg: format!(
-                        "The config file is not in the JSON format expected: {:?}",
-                        err
-                    ),
+                    msg: format!("The config file is not in the JSON format: {:?}", err),
                 })
             }
-            Ok(config) => {
-                let client = ParallelBigtableClient::new(&config)?;
-                self.client = Some(client);
+        };
+
+        let sink_configs = Self::sink_configs(&config);
+        Self::check_sink_paths(&sink_configs)?;
+        for (index, sink_config) in sink_configs.iter().enumerate() {
+            match Sink::new(Self::sink_name(index, sink_config), sink_config) {
+                Ok(mut sink) => {
+                    // A sink falling behind must not hold up the others
+                    sink.client.set_never_block(sink_configs.len() > 1);
+                    self.sinks.push(sink);
+                }
+                Err(err) => {
+                    // Stop the sinks already started
+                    self.on_unload();
+                    return Err(err);
+                }
             }
         }
 
@@ -208,11 +479,9 @@ impl GeyserPlugin for GeyserPluginBigtable {
     fn on_unload(&mut self) {
         info!("Unloading plugin: {:?}", self.name());
 
-        match &mut self.client {
-            None => {}
-            Some(client) => {
-                client.join().unwrap();
-            }
+        for mut sink in self.sinks.drain(..) {
+            info!("Stopping the sink {}", sink.name);
+            sink.client.join().unwrap();
         }
     }
 
@@ -225,59 +494,10 @@ impl GeyserPlugin for GeyserPluginBigtable {
         let mut measure_all = Measure::start("geyser-plugin-bigtable-update-account-main");
         match account {
             ReplicaAccountInfoVersions::V0_0_1(account) => {
-                let mut measure_select =
-                    Measure::start("geyser-plugin-bigtable-update-account-select");
-                if let Some(accounts_selector) = &self.accounts_selector {
-                    if !accounts_selector.is_account_selected(account.pubkey, account.owner) {
-                        return Ok(());
-                    }
-                } else {
-                    return Ok(());
-                }
-                measure_select.stop();
-                inc_new_counter_debug!(
-                    "geyser-plugin-bigtable-update-account-select-us",
-                    measure_select.as_us() as usize,
-                    100000,
-                    100000
-                );
-
-                debug!(
-                    "Updating account {:?} with owner {:?} at slot {:?} using account selector {:?}",
-                    bs58::encode(account.pubkey).into_string(),
-                    bs58::encode(account.owner).into_string(),
-                    slot,
-                    self.accounts_selector.as_ref().unwrap()
-                );
-
-                match &mut self.client {
-                    None => {
-                        return Err(GeyserPluginError::Custom(Box::new(
-                            GeyserPluginBigtableError::DataStoreConnectionError {
-                                msg: "There is no connection to the Bigtable database.".to_string(),
-                            },
-                        )));
-                    }
-                    Some(client) => {
-                        let mut measure_update =
-                            Measure::start("geyser-plugin-bigtable-update-account-client");
-                        let result = { client.update_account(account, slot, is_startup) };
-                        measure_update.stop();
-
-                        inc_new_counter_debug!(
-                            "geyser-plugin-bigtable-update-account-client-us",
-                            measure_update.as_us() as usize,
-                            100000,
-                            100000
-                        );
-
-                        if let Err(err) = result {
-                            return Err(GeyserPluginError::AccountsUpdateError {
-                                msg: format!("Failed to persist the update of account to the Bigtable database. Error: {:?}", err)
-                            });
-                        }
-                    }
-                }
+                self.update_account_info(account, slot, is_startup)?
+            }
+            ReplicaAccountInfoVersions::V0_0_2(account) => {
+                self.update_account_info(account, slot, is_startup)?
             }
         }
 
@@ -301,49 +521,30 @@ impl GeyserPlugin for GeyserPluginBigtable {
     ) -> Result<()> {
         info!("Updating slot {:?} at with status {:?}", slot, status);
 
-        match &mut self.client {
-            None => {
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginBigtableError::DataStoreConnectionError {
-                        msg: "There is no connection to the Bigtable database.".to_string(),
-                    },
-                )));
-            }
-            Some(client) => {
-                let result = client.update_slot_status(slot, parent, status);
-
-                if let Err(err) = result {
-                    return Err(GeyserPluginError::SlotStatusUpdateError{
-                        msg: format!("Failed to persist the update of slot to the Bigtable database. Error: {:?}", err)
-                    });
-                }
-            }
-        }
-
-        Ok(())
+        Self::fan_out(&mut self.sinks, |sink| {
+            sink.client
+                .update_slot_status(slot, parent, status)
+                .map_err(|err| GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!(
+                        "Failed to persist the update of slot to the sink {}. Error: {:?}",
+                        sink.name, err
+                    ),
+                })
+        })
     }
 
     fn notify_end_of_startup(&mut self) -> Result<()> {
         info!("Notifying the end of startup for accounts notifications");
-        match &mut self.client {
-            None => {
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginBigtableError::DataStoreConnectionError {
-                        msg: "There is no connection to the Bigtable database.".to_string(),
-                    },
-                )));
-            }
-            Some(client) => {
-                let result = client.notify_end_of_startup();
-
-                if let Err(err) = result {
-                    return Err(GeyserPluginError::SlotStatusUpdateError{
-                        msg: format!("Failed to notify the end of startup for accounts notifications. Error: {:?}", err)
-                    });
+        Self::fan_out(&mut self.sinks, |sink| {
+            sink.client.notify_end_of_startup().map_err(|err| {
+                GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!(
+                        "Failed to notify the end of startup to the sink {}. Error: {:?}",
+                        sink.name, err
+                    ),
                 }
-            }
-        }
-        Ok(())
+            })
+        })
     }
 
     fn notify_transaction(
@@ -351,84 +552,75 @@ impl GeyserPlugin for GeyserPluginBigtable {
         transaction_info: ReplicaTransactionInfoVersions,
         slot: u64,
     ) -> Result<()> {
-        match &mut self.client {
-            None => {
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginBigtableError::DataStoreConnectionError {
-                        msg: "There is no connection to the Bigtable database.".to_string(),
-                    },
-                )));
+        // The later versions carry the index of the transaction within the block
+        let (transaction_info, index) = match transaction_info {
+            ReplicaTransactionInfoVersions::V0_0_1(transaction_info) => {
+                (transaction_info.clone(), None)
             }
-            Some(client) => match transaction_info {
-                ReplicaTransactionInfoVersions::V0_0_1(transaction_info) => {
-                    if let Some(transaction_selector) = &self.transaction_selector {
-                        if !transaction_selector.is_transaction_selected(
-                            transaction_info.is_vote,
-                            Box::new(transaction_info.transaction.message().account_keys().iter()),
-                        ) {
-                            return Ok(());
-                        }
-                    } else {
-                        return Ok(());
-                    }
-                    let result = client.log_transaction_info(transaction_info, slot);
-
-                    if let Err(err) = result {
-                        return Err(GeyserPluginError::SlotStatusUpdateError{
-                                msg: format!("Failed to persist the transaction info to the Bigtable database. Error: {:?}", err)
-                            });
-                    }
-                }
-            },
-        }
-
-        Ok(())
+            ReplicaTransactionInfoVersions::V0_0_2(transaction_info) => (
+                ReplicaTransactionInfo {
+                    signature: transaction_info.signature,
+                    is_vote: transaction_info.is_vote,
+                    transaction: transaction_info.transaction,
+                    transaction_status_meta: transaction_info.transaction_status_meta,
+                },
+                Some(transaction_info.index),
+            ),
+        };
+        Self::fan_out(&mut self.sinks, |sink| {
+            if !sink.transaction_selector.is_transaction_selected(
+                transaction_info.is_vote,
+                Box::new(transaction_info.transaction.message().account_keys().iter()),
+            ) {
+                return Ok(());
+            }
+            sink.client
+                .log_transaction_info(&transaction_info, index, slot)
+                .map_err(|err| GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!(
+                        "Failed to persist the transaction info to the sink {}. Error: {:?}",
+                        sink.name, err
+                    ),
+                })
+        })
     }
 
     fn notify_block_metadata(&mut self, block_info: ReplicaBlockInfoVersions) -> Result<()> {
-        match &mut self.client {
-            None => {
-                return Err(GeyserPluginError::Custom(Box::new(
-                    GeyserPluginBigtableError::DataStoreConnectionError {
-                        msg: "There is no connection to the Bigtable database.".to_string(),
-                    },
-                )));
+        match block_info {
+            ReplicaBlockInfoVersions::V0_0_1(block_info) => {
+                Self::fan_out(&mut self.sinks, |sink| {
+                    sink.client.update_block_metadata(block_info).map_err(|err| {
+                        GeyserPluginError::SlotStatusUpdateError {
+                            msg: format!(
+                                "Failed to persist the update of block metadata to the sink {}. Error: {:?}",
+                                sink.name, err
+                            ),
+                        }
+                    })
+                })
             }
-            Some(client) => match block_info {
-                ReplicaBlockInfoVersions::V0_0_1(block_info) => {
-                    let result = client.update_block_metadata(block_info);
-
-                    if let Err(err) = result {
-                        return Err(GeyserPluginError::SlotStatusUpdateError{
-                                msg: format!("Failed to persist the update of block metadata to the Bigtable database. Error: {:?}", err)
-                            });
-                    }
-                }
-            },
         }
-
-        Ok(())
     }
 
     /// Check if the plugin is interested in account data
     /// Default is true -- if the plugin is not interested in
     /// account data, please return false.
     fn account_data_notifications_enabled(&self) -> bool {
-        self.accounts_selector
-            .as_ref()
-            .map_or_else(|| false, |selector| selector.is_enabled())
+        self.sinks
+            .iter()
+            .any(|sink| sink.accounts_selector.is_enabled())
     }
 
     /// Check if the plugin is interested in transaction data
     fn transaction_notifications_enabled(&self) -> bool {
-        self.transaction_selector
-            .as_ref()
-            .map_or_else(|| false, |selector| selector.is_enabled())
+        self.sinks
+            .iter()
+            .any(|sink| sink.transaction_selector.is_enabled())
     }
 }
 
 impl GeyserPluginBigtable {
-    fn create_accounts_selector_from_config(config: &serde_json::Value) -> AccountsSelector {
+    pub fn create_accounts_selector_from_config(config: &serde_json::Value) -> AccountsSelector {
         let accounts_selector = &config["accounts_selector"];
 
         if accounts_selector.is_null() {
@@ -460,7 +652,89 @@ impl GeyserPluginBigtable {
         }
     }
 
-    fn create_transaction_selector_from_config(config: &serde_json::Value) -> TransactionSelector {
+    /// The configs of the sinks, the top level config if no sinks are listed. Each sink
+    /// inherits the options of the top level it does not set.
+    pub fn sink_configs(config: &serde_json::Value) -> Vec<serde_json::Value> {
+        let sinks = match config["sinks"].as_array() {
+            Some(sinks) => sinks,
+            None => return vec![config.clone()],
+        };
+        let mut top_level = config.clone();
+        if let Some(top_level) = top_level.as_object_mut() {
+            top_level.remove("sinks");
+            top_level.remove("name");
+        }
+        sinks
+            .iter()
+            .map(|sink| {
+                let mut sink_config = top_level.clone();
+                if let (Some(sink_config), Some(sink)) =
+                    (sink_config.as_object_mut(), sink.as_object())
+                {
+                    for (key, value) in sink {
+                        sink_config.insert(key.clone(), value.clone());
+                    }
+                }
+                sink_config
+            })
+            .collect()
+    }
+
+    fn sink_name(index: usize, sink_config: &serde_json::Value) -> String {
+        sink_config["name"]
+            .as_str()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("sink-{}", index))
+    }
+
+    /// Reject the sinks sharing a file or directory, which they would overwrite or read
+    /// back the entries of each other from, the paths of the top level being inherited
+    fn check_sink_paths(sink_configs: &[serde_json::Value]) -> Result<()> {
+        let mut paths = HashMap::new();
+        for (index, sink_config) in sink_configs.iter().enumerate() {
+            let name = Self::sink_name(index, sink_config);
+            for option in SINK_PATH_OPTIONS {
+                let path = match sink_config.pointer(option).and_then(|path| path.as_str()) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                if let Some(other) = paths.insert(path.to_string(), name.clone()) {
+                    return Err(GeyserPluginError::ConfigFileReadError {
+                        msg: format!(
+                            "The sinks {} and {} share the path {}, set \"{}\" of each sink \
+                            to its own path",
+                            other,
+                            name,
+                            path,
+                            option.trim_start_matches('/').replace('/', "."),
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the notification to all the sinks in turn. A sink failing does not keep the
+    /// others from being notified, the last error is returned. With several sinks, the
+    /// queues never block, so a sink whose queue is full does not hold up the others.
+    fn fan_out<F>(sinks: &mut [Sink], mut notify: F) -> Result<()>
+    where
+        F: FnMut(&mut Sink) -> Result<()>,
+    {
+        let mut result = Ok(());
+        for sink in sinks.iter_mut() {
+            if let Err(err) = notify(sink) {
+                error!("{}", err);
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    pub fn create_transaction_selector_from_config(
+        config: &serde_json::Value,
+    ) -> TransactionSelector {
         let transaction_selector = &config["transaction_selector"];
 
         if transaction_selector.is_null() {
@@ -484,6 +758,58 @@ impl GeyserPluginBigtable {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Select and persist the account, notified with any of the interface versions
+    fn update_account_info<T: ReadableAccountInfo>(
+        &mut self,
+        account: &T,
+        slot: u64,
+        is_startup: bool,
+    ) -> Result<()> {
+        Self::fan_out(&mut self.sinks, |sink| {
+            let mut measure_select = Measure::start("geyser-plugin-bigtable-update-account-select");
+            if !sink
+                .accounts_selector
+                .is_account_selected(account.pubkey(), account.owner())
+            {
+                return Ok(());
+            }
+            measure_select.stop();
+            inc_new_counter_debug!(
+                "geyser-plugin-bigtable-update-account-select-us",
+                measure_select.as_us() as usize,
+                100000,
+                100000
+            );
+
+            debug!(
+                "Updating account {:?} with owner {:?} at slot {:?} using account selector {:?} of the sink {}",
+                bs58::encode(account.pubkey()).into_string(),
+                bs58::encode(account.owner()).into_string(),
+                slot,
+                sink.accounts_selector,
+                sink.name
+            );
+
+            let mut measure_update = Measure::start("geyser-plugin-bigtable-update-account-client");
+            let result = sink.client.update_account(account, slot, is_startup);
+            measure_update.stop();
+
+            inc_new_counter_debug!(
+                "geyser-plugin-bigtable-update-account-client-us",
+                measure_update.as_us() as usize,
+                100000,
+                100000
+            );
+
+            result.map_err(|err| GeyserPluginError::AccountsUpdateError {
+                msg: format!(
+                    "Failed to persist the update of account to the sink {}. Error: {:?}",
+                    sink.name, err
+                ),
+            })
+        })
+    }
 }
 
 #[no_mangle]
@@ -510,4 +836,76 @@ pub(crate) mod tests {
         let config: serde_json::Value = serde_json::from_str(config).unwrap();
         GeyserPluginBigtable::create_accounts_selector_from_config(&config);
     }
+
+    #[test]
+    fn test_sink_configs() {
+        let config = serde_json::json!({
+            "instance": "geyser",
+            "batch_size": 20,
+        });
+        assert_eq!(GeyserPluginBigtable::sink_configs(&config), vec![config]);
+
+        let config = serde_json::json!({
+            "instance": "geyser",
+            "batch_size": 20,
+            "accounts_selector": { "accounts": ["*"] },
+            "sinks": [
+                { "name": "us", "instance": "geyser-us" },
+                { "json_lines": { "path": "/tmp/geyser" }, "accounts_selector": { "owners": [] } },
+            ],
+        });
+        let sinks = GeyserPluginBigtable::sink_configs(&config);
+        assert_eq!(
+            sinks,
+            vec![
+                serde_json::json!({
+                    "name": "us",
+                    "instance": "geyser-us",
+                    "batch_size": 20,
+                    "accounts_selector": { "accounts": ["*"] },
+                }),
+                serde_json::json!({
+                    "instance": "geyser",
+                    "batch_size": 20,
+                    "accounts_selector": { "owners": [] },
+                    "json_lines": { "path": "/tmp/geyser" },
+                }),
+            ]
+        );
+        let config: GeyserPluginBigtableConfig = serde_json::from_value(sinks[1].clone()).unwrap();
+        assert_eq!(config.batch_size, Some(20));
+        assert!(config.json_lines.is_some());
+        assert!(GeyserPluginBigtable::check_sink_paths(&sinks).is_ok());
+    }
+
+    #[test]
+    fn test_check_sink_paths() {
+        let config = serde_json::json!({
+            "checkpoint_path": "/tmp/checkpoint",
+            "sinks": [
+                { "name": "us", "instance": "geyser-us" },
+                { "name": "eu", "instance": "geyser-eu" },
+            ],
+        });
+        let sinks = GeyserPluginBigtable::sink_configs(&config);
+        assert!(GeyserPluginBigtable::check_sink_paths(&sinks).is_err());
+
+        let config = serde_json::json!({
+            "sinks": [
+                { "name": "us", "checkpoint_path": "/tmp/checkpoint-us", "json_lines": { "path": "/tmp/geyser" } },
+                { "name": "eu", "checkpoint_path": "/tmp/checkpoint-eu", "json_lines": { "path": "/tmp/geyser" } },
+            ],
+        });
+        let sinks = GeyserPluginBigtable::sink_configs(&config);
+        assert!(GeyserPluginBigtable::check_sink_paths(&sinks).is_err());
+
+        let config = serde_json::json!({
+            "sinks": [
+                { "name": "us", "checkpoint_path": "/tmp/checkpoint-us" },
+                { "name": "eu", "checkpoint_path": "/tmp/checkpoint-eu" },
+            ],
+        });
+        let sinks = GeyserPluginBigtable::sink_configs(&config);
+        assert!(GeyserPluginBigtable::check_sink_paths(&sinks).is_ok());
+    }
 }
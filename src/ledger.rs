@@ -0,0 +1,219 @@
+/// Reads the blocks of a local ledger without running a validator, to fill the gaps of
+/// the `block` and `transaction` tables. The blocks are converted to rows the way the
+/// plugin converts the blocks and transactions notified, and the transactions are selected
+/// with the `transaction_selector` of the plugin. Opening the blockstore requires the
+/// `ledger` feature, which builds RocksDB.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            block_metadata::DbBlockInfo,
+            transaction::{build_db_transaction, DbTransaction},
+        },
+        transaction_selector::TransactionSelector,
+    },
+    log::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaBlockInfo, ReplicaTransactionInfo,
+    },
+    solana_sdk::{
+        clock::Slot,
+        transaction::{MessageHash, SanitizedTransaction, SimpleAddressLoader},
+    },
+    solana_transaction_status::VersionedConfirmedBlock,
+};
+
+/// The rows of a block, its metadata and the transactions selected
+pub struct BlockRows {
+    pub block: DbBlockInfo,
+    pub transactions: Vec<DbTransaction>,
+    /// The count of transactions which could not be sanitized, and are not written
+    pub skipped_transactions: usize,
+}
+
+/// Convert the block of the slot to its rows, keeping the transactions selected. The
+/// transactions are indexed by their position in the block, like the notifications of
+/// `ReplicaTransactionInfoV2`.
+pub fn block_rows(
+    slot: Slot,
+    block: VersionedConfirmedBlock,
+    selector: &TransactionSelector,
+) -> BlockRows {
+    let block_info = DbBlockInfo::from(&ReplicaBlockInfo {
+        slot,
+        blockhash: &block.blockhash,
+        rewards: &block.rewards,
+        block_time: block.block_time,
+        block_height: block.block_height,
+    });
+
+    let mut transactions = Vec::new();
+    let mut skipped_transactions = 0;
+    if selector.is_enabled() {
+        for (index, transaction_with_meta) in block.transactions.into_iter().enumerate() {
+            let meta = transaction_with_meta.meta;
+            // The addresses of the lookup tables were loaded when the block was processed
+            let address_loader = SimpleAddressLoader::Enabled(meta.loaded_addresses.clone());
+            let transaction = match SanitizedTransaction::try_create(
+                transaction_with_meta.transaction,
+                MessageHash::Compute,
+                None,
+                address_loader,
+                true,
+            ) {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    warn!(
+                        "Skipping the transaction {} of slot {}: {}",
+                        index, slot, err
+                    );
+                    skipped_transactions += 1;
+                    continue;
+                }
+            };
+
+            let is_vote = transaction.is_simple_vote_transaction();
+            if !selector.is_transaction_selected(
+                is_vote,
+                Box::new(transaction.message().account_keys().iter()),
+            ) {
+                continue;
+            }
+            let transaction_info = ReplicaTransactionInfo {
+                signature: transaction.signature(),
+                is_vote,
+                transaction: &transaction,
+                transaction_status_meta: &meta,
+            };
+            transactions.push(build_db_transaction(slot, &transaction_info, Some(index)));
+        }
+    }
+
+    BlockRows {
+        block: block_info,
+        transactions,
+        skipped_transactions,
+    }
+}
+
+#[cfg(feature = "ledger")]
+pub use blockstore::LedgerReader;
+
+#[cfg(feature = "ledger")]
+mod blockstore {
+    use {
+        solana_ledger::{
+            blockstore::{Blockstore, BlockstoreError},
+            blockstore_options::{AccessType, BlockstoreOptions},
+        },
+        solana_sdk::clock::Slot,
+        solana_transaction_status::VersionedConfirmedBlock,
+        std::path::Path,
+    };
+
+    /// The rooted blocks of a ledger, opened with secondary access so that the ledger of
+    /// a running validator can be read
+    pub struct LedgerReader {
+        blockstore: Blockstore,
+    }
+
+    impl LedgerReader {
+        pub fn open(ledger_path: &Path) -> Result<Self, BlockstoreError> {
+            let blockstore = Blockstore::open_with_options(
+                ledger_path,
+                BlockstoreOptions {
+                    access_type: AccessType::Secondary,
+                    ..BlockstoreOptions::default()
+                },
+            )?;
+            Ok(Self { blockstore })
+        }
+
+        /// The rooted slots within the range, inclusive
+        pub fn rooted_slots(
+            &self,
+            start_slot: Slot,
+            end_slot: Slot,
+        ) -> Result<Vec<Slot>, BlockstoreError> {
+            Ok(self
+                .blockstore
+                .rooted_slot_iterator(start_slot)?
+                .take_while(|slot| *slot <= end_slot)
+                .collect())
+        }
+
+        /// The block of the rooted slot, `None` if it was cleaned up or is not complete
+        pub fn get_block(
+            &self,
+            slot: Slot,
+        ) -> Result<Option<VersionedConfirmedBlock>, BlockstoreError> {
+            match self.blockstore.get_rooted_block(slot, false) {
+                Ok(block) => Ok(Some(block)),
+                Err(
+                    BlockstoreError::SlotCleanedUp
+                    | BlockstoreError::SlotNotRooted
+                    | BlockstoreError::SlotUnavailable,
+                ) => Ok(None),
+                Err(err) => Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            hash::Hash, pubkey::Pubkey, signature::Keypair, signer::Signer, system_transaction,
+        },
+        solana_transaction_status::{TransactionStatusMeta, VersionedTransactionWithStatusMeta},
+    };
+
+    #[test]
+    fn test_block_rows() {
+        let payer = Keypair::new();
+        let recipient = Pubkey::new_unique();
+        let transactions: Vec<VersionedTransactionWithStatusMeta> = (0..3)
+            .map(|lamports| VersionedTransactionWithStatusMeta {
+                transaction: system_transaction::transfer(
+                    &payer,
+                    &Pubkey::new_unique(),
+                    lamports + 1,
+                    Hash::default(),
+                )
+                .into(),
+                meta: TransactionStatusMeta::default(),
+            })
+            .chain(std::iter::once(VersionedTransactionWithStatusMeta {
+                transaction: system_transaction::transfer(&payer, &recipient, 10, Hash::default())
+                    .into(),
+                meta: TransactionStatusMeta::default(),
+            }))
+            .collect();
+        let signature = transactions[3].transaction.signatures[0];
+        let block = VersionedConfirmedBlock {
+            previous_blockhash: Hash::default().to_string(),
+            blockhash: Hash::new_unique().to_string(),
+            parent_slot: 299,
+            transactions,
+            rewards: Vec::default(),
+            block_time: Some(1_660_000_000),
+            block_height: Some(280),
+        };
+
+        let selector = TransactionSelector::new(&[recipient.to_string()]);
+        let rows = block_rows(300, block.clone(), &selector);
+        assert_eq!(rows.block.slot, 300);
+        assert_eq!(rows.block.blockhash, block.blockhash);
+        assert_eq!(rows.block.block_height, Some(280));
+        assert_eq!(rows.skipped_transactions, 0);
+        // Only the transaction mentioning the recipient is selected, keyed by its position
+        assert_eq!(rows.transactions.len(), 1);
+        assert_eq!(rows.transactions[0].signature, signature.as_ref().to_vec());
+        assert_eq!(rows.transactions[0].index, Some(3));
+        assert_eq!(rows.transactions[0].slot, 300);
+
+        let rows = block_rows(300, block, &TransactionSelector::default());
+        assert!(rows.transactions.is_empty());
+    }
+}
@@ -1,4 +1,15 @@
 pub mod accounts_selector;
+pub mod bigtable_reader;
+pub mod checkpoint;
+pub mod compression;
+pub mod dictionary;
 pub mod geyser_plugin_bigtable;
+pub mod ledger;
+pub mod models;
 pub mod parallel_bigtable_client;
+pub mod parquet_export;
+pub mod replay;
+pub mod schema;
+pub mod snapshot;
 pub mod transaction_selector;
+pub mod verifier;
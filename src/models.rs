@@ -0,0 +1,57 @@
+/// The protobuf models of the `account` and `slot` rows. They are wire compatible with the
+/// `account.proto` and `slot.proto` models of `solana-bigtable-geyser-models`, which pins
+/// the 1.10 Solana crates, so that the rows written before keep being decoded. The field
+/// number `schema::SCHEMA_VERSION_TAG` is reserved for the schema version of the cells.
+pub mod accounts {
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Account {
+        #[prost(bytes = "vec", tag = "1")]
+        pub pubkey: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub owner: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub lamports: u64,
+        #[prost(uint64, tag = "4")]
+        pub slot: u64,
+        #[prost(bool, tag = "5")]
+        pub executable: bool,
+        #[prost(uint64, tag = "6")]
+        pub rent_epoch: u64,
+        #[prost(bytes = "vec", tag = "7")]
+        pub data: Vec<u8>,
+        #[prost(uint64, tag = "8")]
+        pub write_version: u64,
+        #[prost(message, optional, tag = "9")]
+        pub updated_on: Option<UnixTimestamp>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct UnixTimestamp {
+        #[prost(int64, tag = "1")]
+        pub timestamp: i64,
+    }
+}
+
+pub mod slots {
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Slot {
+        #[prost(uint64, tag = "1")]
+        pub slot: u64,
+        #[prost(uint64, optional, tag = "4")]
+        pub parent: Option<u64>,
+        #[prost(string, tag = "5")]
+        pub status: String,
+        #[prost(message, optional, tag = "9")]
+        pub updated_on: Option<UnixTimestamp>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct UnixTimestamp {
+        #[prost(int64, tag = "1")]
+        pub timestamp: i64,
+    }
+}
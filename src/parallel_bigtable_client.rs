@@ -1,39 +1,68 @@
 pub mod account;
+pub mod account_chunk;
 pub mod account_index;
 pub mod block_metadata;
+pub mod error_policy;
+pub mod json_lines;
+pub mod pipeline;
+pub mod retry;
+pub mod row_writer;
 pub mod slot;
+pub mod standalone;
+pub mod startup;
+pub mod table_routing;
 pub mod transaction;
+pub mod work_queue;
 
 use {
     crate::{
+        checkpoint::Checkpoint,
+        compression::{CompressedCell, CompressionCodec, CompressionConfig, TableCompression},
+        dictionary::ZstdDictionaries,
         geyser_plugin_bigtable::{GeyserPluginBigtableConfig, GeyserPluginBigtableError},
+        models::accounts,
         parallel_bigtable_client::{
             account::{DbAccountInfo, ReadableAccountInfo, UpdateAccountRequest},
             account_index::TokenSecondaryIndexEntry,
             block_metadata::{DbBlockInfo, UpdateBlockMetadataRequest},
-            transaction::{build_db_transaction, LogTransactionRequest},
+            error_policy::{ErrorDecision, ErrorPolicies},
+            json_lines::JsonLinesWorker,
+            pipeline::{Lane, WritePipeline},
+            retry::{is_connection_error, CircuitBreaker, RetryPolicy},
+            row_writer::{cell_qualifier, RowWriter, TableWriters},
+            startup::StartupProgress,
+            table_routing::TableEndpoint,
+            transaction::{build_db_transaction, LogTransactionRequest, PendingTransactions},
+            work_queue::{QueueOutcome, WorkQueue, WorkReceiver},
         },
+        schema::encode_cell,
     },
-    crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
+    backoff::backoff::Backoff,
+    crossbeam_channel::{bounded, RecvTimeoutError, SendTimeoutError, Sender},
     log::*,
-    slot::UpdateSlotRequest,
-    solana_bigtable_connection::{bigtable::BigTableConnection as Client, CredentialType},
+    prost::Message,
+    slot::{PendingSlots, UpdateSlotRequest},
+    solana_bigtable_connection::{
+        bigtable::{BigTableConnection as Client, Error as BigtableError, RowKey},
+        CredentialType,
+    },
     solana_geyser_plugin_interface::geyser_plugin_interface::{
-        GeyserPluginError, ReplicaAccountInfo, ReplicaBlockInfo, ReplicaTransactionInfo, SlotStatus,
+        GeyserPluginError, ReplicaBlockInfo, ReplicaTransactionInfo, SlotStatus,
     },
     solana_measure::measure::Measure,
     solana_metrics::*,
     solana_sdk::timing::AtomicInterval,
     std::time::SystemTime,
     std::{
+        path::{Path, PathBuf},
         sync::{
             atomic::{AtomicBool, AtomicUsize, Ordering},
-            Arc, Mutex,
+            Arc, RwLock,
         },
         thread::{self, sleep, Builder, JoinHandle},
-        time::Duration,
+        time::{Duration, Instant},
     },
-    tokio::runtime::Runtime,
+    tokio::{runtime::Runtime, sync::oneshot},
 };
 
 pub fn abort() -> ! {
@@ -49,14 +78,32 @@ pub fn abort() -> ! {
     panic!("process::exit(1) is intercepted for friendly test failure...");
 }
 
-#[allow(unused_variables)]
-
-/// The maximum asynchronous requests allowed in the channel to avoid excessive
-/// memory usage. The downside -- calls after this threshold is reached can get blocked.
-const MAX_ASYNC_REQUESTS: usize = 40960;
-const DEFAULT_THREADS_COUNT: usize = 100;
+/// The default maximum count of work items allowed in the queue to avoid excessive
+/// memory usage. What happens to the notifications past this threshold depends on the
+/// backpressure policy, by default they get blocked.
+const DEFAULT_QUEUE_CAPACITY: usize = 40960;
+/// The default maximum size in bytes of the work items in the queue
+const DEFAULT_QUEUE_CAPACITY_BYTES: usize = 2 * 1024 * 1024 * 1024;
+/// The default maximum count of slot, block and transaction items in the priority lane
+const DEFAULT_PRIORITY_QUEUE_CAPACITY: usize = 8192;
+const DEFAULT_RUNTIME_THREADS: usize = 4;
+const DEFAULT_MAX_IN_FLIGHT_REQUESTS: usize = 100;
+/// The maximum delay between two attempts of a worker to connect
+const MAX_CONNECT_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_END_OF_STARTUP_TIMEOUT_MS: u64 = 300_000;
+const DEFAULT_PRIORITY_IN_FLIGHT_REQUESTS: usize = 16;
 const DEFAULT_ACCOUNTS_INSERT_BATCH_SIZE: usize = 10;
+const DEFAULT_ACCOUNTS_INSERT_BATCH_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_ACCOUNT_CELL_BYTES: usize = 8 * 1024 * 1024;
+const DEFAULT_SLOT_BATCH_SIZE: usize = 100;
+const DEFAULT_SLOT_BATCH_INTERVAL_MS: u64 = 400;
+const DEFAULT_TRANSACTION_BATCH_SIZE: usize = 100;
+const DEFAULT_TRANSACTION_BATCH_INTERVAL_MS: u64 = 400;
 const DEFAULT_PANIC_ON_DB_ERROR: bool = false;
+const DEFAULT_STARTUP_BATCH_SIZE: usize = 1000;
+const DEFAULT_STARTUP_BATCH_BYTES: usize = 32 * 1024 * 1024;
+const DEFAULT_STARTUP_PARALLEL_STREAMS: usize = 4;
 
 /// The default bigtable instance name
 pub const DEFAULT_BIGTABLE_INSTANCE: &str = "solana-geyser-plugin-bigtable";
@@ -64,41 +111,374 @@ pub const DEFAULT_APP_PROFILE_ID: &str = "";
 pub const DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA: bool = false;
 
 #[warn(clippy::large_enum_variant)]
-enum DbWorkItem {
+pub(crate) enum DbWorkItem {
     UpdateAccount(Box<UpdateAccountRequest>),
     UpdateSlot(Box<UpdateSlotRequest>),
     LogTransaction(Box<LogTransactionRequest>),
     UpdateBlockMetadata(Box<UpdateBlockMetadataRequest>),
+    /// Sent to each dispatcher at the end of startup, acknowledged once it has written
+    /// out its pending batches
+    EndOfStartup(Sender<()>),
 }
 
 struct BigtableClientWrapper {
-    client: Client,
+    writers: RwLock<TableWriters>,
+    /// To connect again when the connection is rebuilt
+    config: GeyserPluginBigtableConfig,
+    retry: RetryPolicy,
+    circuit_breaker: Arc<CircuitBreaker>,
+    error_policies: Arc<ErrorPolicies>,
+    /// The count of requests failed in a row with a connection error
+    connection_failures: AtomicUsize,
+    reconnect_threshold: usize,
+    /// Held while the connection is rebuilt, so that it is rebuilt once at a time
+    reconnecting: tokio::sync::Mutex<()>,
+    /// If the connection is broken, the worker is then counted in the degraded workers
+    is_degraded: AtomicBool,
+    degraded_workers: Arc<AtomicUsize>,
+}
+
+/// Encode the protobuf cells with the current schema version and compress them with the
+/// codec configured for the table, returning the cells and their size before compression
+pub(crate) fn compress_protobuf_cells<T: Message>(
+    cells: &[(RowKey, T)],
+    compression: &CompressionConfig,
+) -> Result<(Vec<(RowKey, CompressedCell)>, usize), std::io::Error> {
+    let mut raw_size = 0;
+    let mut compressed_cells = Vec::with_capacity(cells.len());
+    for (key, cell) in cells {
+        let data = encode_cell(cell);
+        raw_size += data.len();
+        compressed_cells.push((key.clone(), CompressedCell(compression.compress(&data)?)));
+    }
+    Ok((compressed_cells, raw_size))
+}
+
+impl BigtableClientWrapper {
+    fn new(
+        writers: TableWriters,
+        config: &GeyserPluginBigtableConfig,
+        pipeline: &WritePipeline,
+        degraded_workers: Arc<AtomicUsize>,
+    ) -> Self {
+        let retry_config = config.retry.clone().unwrap_or_default();
+        Self {
+            writers: RwLock::new(writers),
+            config: config.clone(),
+            reconnect_threshold: retry_config.reconnect_threshold(),
+            retry: RetryPolicy::new(retry_config),
+            circuit_breaker: pipeline.circuit_breaker(),
+            error_policies: pipeline.error_policies(),
+            connection_failures: AtomicUsize::default(),
+            reconnecting: tokio::sync::Mutex::default(),
+            is_degraded: AtomicBool::default(),
+            degraded_workers,
+        }
+    }
+
+    /// The writer of the instance of the table and the name of the table in it
+    fn route(&self, table: &str) -> (RowWriter, String) {
+        self.writers.read().unwrap().route(table)
+    }
+
+    /// Write the cells already compressed, retrying the transient errors. The error
+    /// policy of the table decides what happens once the retries are exhausted.
+    async fn put_compressed_cells(
+        &self,
+        table: &str,
+        cells: &[(RowKey, CompressedCell)],
+    ) -> Result<usize, BigtableError> {
+        loop {
+            let result = self
+                .retry
+                .retry(&self.circuit_breaker, || async {
+                    let (writer, table_name) = self.route(table);
+                    let result = writer
+                        .put_cells(&table_name, cell_qualifier(table), cells)
+                        .await;
+                    self.check_connection(&result).await;
+                    result
+                })
+                .await;
+            let err = match result {
+                Ok(written_size) => {
+                    self.error_policies.record_success(table);
+                    return Ok(written_size);
+                }
+                Err(err) => err,
+            };
+
+            match self.error_policies.on_failure(table) {
+                ErrorDecision::Retry(pause) => {
+                    warn!(
+                        "Error writing {} rows to the {} table, retrying in {:?}: {}",
+                        cells.len(),
+                        table,
+                        pause,
+                        err
+                    );
+                    inc_new_counter_info!("geyser-bigtable-paused-writes", 1);
+                    tokio::time::sleep(pause).await;
+                }
+                ErrorDecision::Spill => {
+                    match self.error_policies.spill(table, cells) {
+                        Ok(()) => {
+                            warn!(
+                                "Spilled {} rows of the {} table to disk after error: {}",
+                                cells.len(),
+                                table,
+                                err
+                            );
+                            inc_new_counter_info!("geyser-bigtable-spilled-rows", cells.len());
+                        }
+                        Err(spill_err) => error!(
+                            "Error spilling {} rows of the {} table to disk: {}",
+                            cells.len(),
+                            table,
+                            spill_err
+                        ),
+                    }
+                    return Err(err);
+                }
+                ErrorDecision::Skip => return Err(err),
+                ErrorDecision::Abort => {
+                    error!(
+                        "The error budget of the {} table is exhausted, aborting once the \
+                         queue is drained: {}",
+                        table, err
+                    );
+                    self.error_policies.request_abort();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// The keys of the rows of the table from `start_at` to `end_at`, both included
+    async fn get_row_keys(
+        &self,
+        table: &str,
+        start_at: &RowKey,
+        end_at: &RowKey,
+    ) -> Result<Vec<RowKey>, BigtableError> {
+        self.retry
+            .retry(&self.circuit_breaker, || async {
+                let (client, table_name) = self.writers.read().unwrap().client(table);
+                let result = client
+                    .client()
+                    .get_row_keys(&table_name, Some(start_at.clone()), Some(end_at.clone()), 0)
+                    .await;
+                self.check_connection(&result).await;
+                result
+            })
+            .await
+    }
+
+    /// Delete the rows of the table, retrying the transient errors
+    async fn delete_rows(&self, table: &str, row_keys: &[RowKey]) -> Result<(), BigtableError> {
+        self.retry
+            .retry(&self.circuit_breaker, || async {
+                let (writer, table_name) = self.route(table);
+                let result = writer.delete_rows(&table_name, row_keys).await;
+                self.check_connection(&result).await;
+                result
+            })
+            .await
+    }
+
+    /// Rebuild the connection, with fresh credentials, once `reconnect_threshold` requests
+    /// in a row failed with a connection error
+    async fn check_connection<T>(&self, result: &Result<T, BigtableError>) {
+        match result {
+            Err(err) if is_connection_error(err) => (),
+            _ => {
+                self.connection_failures.store(0, Ordering::Relaxed);
+                self.set_degraded(false);
+                return;
+            }
+        }
+        if self.connection_failures.fetch_add(1, Ordering::Relaxed) + 1 < self.reconnect_threshold {
+            return;
+        }
+        // The other requests go on with the connection being rebuilt
+        let _reconnecting = match self.reconnecting.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        self.set_degraded(true);
+        inc_new_counter_info!("geyser-bigtable-reconnects", 1);
+        match TableWriters::connect(&self.config).await {
+            Ok(writers) => {
+                info!("Rebuilt the connection to Bigtable");
+                *self.writers.write().unwrap() = writers;
+                self.connection_failures.store(0, Ordering::Relaxed);
+            }
+            Err(err) => error!("Error rebuilding the connection to Bigtable: {}", err),
+        }
+    }
+
+    /// The worker stays degraded until a request succeeds
+    fn set_degraded(&self, is_degraded: bool) {
+        if self.is_degraded.swap(is_degraded, Ordering::Relaxed) == is_degraded {
+            return;
+        }
+        if is_degraded {
+            self.degraded_workers.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.degraded_workers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Write the protobuf cells compressed with the codec configured for the table
+    async fn put_protobuf_cells<T: Message>(
+        &self,
+        table: &str,
+        cells: &[(RowKey, T)],
+        compression: &CompressionConfig,
+    ) -> Result<usize, BigtableError> {
+        let (compressed_cells, raw_size) = compress_protobuf_cells(cells, compression)?;
+        let written_size = self.put_compressed_cells(table, &compressed_cells).await?;
+        update_compression_stats(compression.codec, raw_size, written_size);
+        Ok(written_size)
+    }
+
+    /// Write the bincode cells compressed with the codec configured for the table
+    async fn put_bincode_cells<T: serde::Serialize>(
+        &self,
+        table: &str,
+        cells: &[(RowKey, T)],
+        compression: &CompressionConfig,
+    ) -> Result<usize, BigtableError> {
+        let mut raw_size = 0;
+        let mut compressed_cells = Vec::with_capacity(cells.len());
+        for (key, cell) in cells {
+            let data = bincode::serialize(cell).unwrap();
+            raw_size += data.len();
+            compressed_cells.push((key.clone(), CompressedCell(compression.compress(&data)?)));
+        }
+
+        let written_size = self.put_compressed_cells(table, &compressed_cells).await?;
+        update_compression_stats(compression.codec, raw_size, written_size);
+        Ok(written_size)
+    }
+
+    /// Write the account cells, compressing the accounts whose owner has a dictionary
+    /// loaded with it and the others with the codec configured for the table
+    async fn put_account_cells(
+        &self,
+        table: &str,
+        cells: &[(RowKey, accounts::Account)],
+        compression: &TableCompression,
+    ) -> Result<usize, BigtableError> {
+        let (mut raw_size, mut compressed_size) = (0, 0);
+        let (mut dictionary_raw_size, mut dictionary_compressed_size) = (0, 0);
+        let mut compressed_cells = Vec::with_capacity(cells.len());
+        for (key, account) in cells {
+            let data = encode_cell(account);
+            let (compressed, dictionary_id) =
+                compression.compress_owned(table, &account.owner, &data)?;
+            if dictionary_id.is_some() {
+                dictionary_raw_size += data.len();
+                dictionary_compressed_size += compressed.len();
+            } else {
+                raw_size += data.len();
+                compressed_size += compressed.len();
+            }
+            compressed_cells.push((key.clone(), CompressedCell(compressed)));
+        }
+
+        let written_size = self.put_compressed_cells(table, &compressed_cells).await?;
+        update_compression_stats(
+            compression.for_table(table).codec,
+            raw_size,
+            compressed_size,
+        );
+        if dictionary_raw_size > 0 {
+            inc_new_counter_info!(
+                "geyser-bigtable-zstd-dictionary-raw-bytes",
+                dictionary_raw_size,
+                1000000
+            );
+            inc_new_counter_info!(
+                "geyser-bigtable-zstd-dictionary-written-bytes",
+                dictionary_compressed_size,
+                1000000
+            );
+        }
+        Ok(written_size)
+    }
+}
+
+fn update_compression_stats(codec: CompressionCodec, raw_size: usize, written_size: usize) {
+    match codec {
+        CompressionCodec::None => {
+            inc_new_counter_info!("geyser-bigtable-none-raw-bytes", raw_size, 1000000);
+            inc_new_counter_info!("geyser-bigtable-none-written-bytes", written_size, 1000000);
+        }
+        CompressionCodec::Gzip => {
+            inc_new_counter_info!("geyser-bigtable-gzip-raw-bytes", raw_size, 1000000);
+            inc_new_counter_info!("geyser-bigtable-gzip-written-bytes", written_size, 1000000);
+        }
+        CompressionCodec::Bzip2 => {
+            inc_new_counter_info!("geyser-bigtable-bzip2-raw-bytes", raw_size, 1000000);
+            inc_new_counter_info!("geyser-bigtable-bzip2-written-bytes", written_size, 1000000);
+        }
+        CompressionCodec::Zstd => {
+            inc_new_counter_info!("geyser-bigtable-zstd-raw-bytes", raw_size, 1000000);
+            inc_new_counter_info!("geyser-bigtable-zstd-written-bytes", written_size, 1000000);
+        }
+        CompressionCodec::Best => {
+            inc_new_counter_info!("geyser-bigtable-best-raw-bytes", raw_size, 1000000);
+            inc_new_counter_info!("geyser-bigtable-best-written-bytes", written_size, 1000000);
+        }
+    }
 }
 #[allow(dead_code)]
 pub struct BufferedBigtableClient {
-    client: Mutex<BigtableClientWrapper>,
+    client: Arc<BigtableClientWrapper>,
     store_account_historical_data: bool,
     batch_size: usize,
-    pending_account_updates: Vec<DbAccountInfo>,
+    /// The maximum encoded size of the accounts in a batch
+    batch_bytes: usize,
+    /// Accounts whose encoding is larger than this are stored in chunks of this size
+    max_account_cell_bytes: usize,
+    pending_account_updates: Vec<(String, accounts::Account)>,
+    pending_account_bytes: usize,
+    /// The accounts notified during startup are bulk loaded with larger batches
+    startup_batch_size: usize,
+    startup_batch_bytes: usize,
+    startup_parallel_streams: usize,
+    pending_startup_account_cells: Vec<(String, accounts::Account)>,
+    pending_startup_bytes: usize,
     index_token_owner: bool,
     index_token_mint: bool,
     pending_token_owner_index: Vec<TokenSecondaryIndexEntry>,
     pending_token_mint_index: Vec<TokenSecondaryIndexEntry>,
+    /// The latest status of the slots updated since the last slot batch was written
+    pending_slot_updates: PendingSlots,
+    /// Completes once the last slot batch spawned is written, the next batch waits for it
+    /// so that an older status never lands after a newer one
+    previous_slot_write: Option<oneshot::Receiver<()>>,
+    pending_transactions: PendingTransactions,
+    compression: Arc<TableCompression>,
+    pipeline: WritePipeline,
 }
 
 impl BufferedBigtableClient {
     pub async fn connect_to_db(
         config: &GeyserPluginBigtableConfig,
+    ) -> Result<Client, GeyserPluginError> {
+        Self::connect_to_endpoint(config, &TableEndpoint::default_for(config)).await
+    }
+
+    /// Connect to the instance with the app profile of the endpoint
+    pub async fn connect_to_endpoint(
+        config: &GeyserPluginBigtableConfig,
+        endpoint: &TableEndpoint,
     ) -> Result<Client, GeyserPluginError> {
         let result = Client::new(
-            config
-                .instance
-                .as_ref()
-                .unwrap_or(&DEFAULT_BIGTABLE_INSTANCE.to_string()),
-            config
-                .app_profile_id
-                .as_ref()
-                .unwrap_or(&DEFAULT_APP_PROFILE_ID.to_string()),
+            &endpoint.instance,
+            &endpoint.app_profile_id,
             false,
             config.timeout,
             CredentialType::Filepath(config.credential_path.clone()),
@@ -109,8 +489,8 @@ impl BufferedBigtableClient {
             Ok(client) => Ok(client),
             Err(err) => {
                 let msg = format!(
-                    "Error in connecting to Bigtable \"credential_path\": {:?}, : {}",
-                    config.credential_path, err
+                    "Error in connecting to Bigtable \"credential_path\": {:?}, \"instance\": {}, : {}",
+                    config.credential_path, endpoint.instance, err
                 );
                 Err(GeyserPluginError::Custom(Box::new(
                     GeyserPluginBigtableError::DataStoreConnectionError { msg },
@@ -119,9 +499,25 @@ impl BufferedBigtableClient {
         }
     }
 
-    pub async fn new(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
+    /// Load the zstd dictionaries configured
+    pub fn load_dictionaries(
+        config: &GeyserPluginBigtableConfig,
+    ) -> Result<ZstdDictionaries, GeyserPluginError> {
+        let configs = config.zstd_dictionaries.as_deref().unwrap_or_default();
+        ZstdDictionaries::load(configs).map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+                msg: format!("Error loading the zstd dictionaries: {}", err),
+            }))
+        })
+    }
+
+    pub async fn new(
+        config: &GeyserPluginBigtableConfig,
+        pipeline: WritePipeline,
+        degraded_workers: Arc<AtomicUsize>,
+    ) -> Result<Self, GeyserPluginError> {
         info!("Creating SimpleBigtableClient...");
-        let client = Self::connect_to_db(config).await?;
+        let writers = TableWriters::connect(config).await?;
 
         let store_account_historical_data = config
             .store_account_historical_data
@@ -131,95 +527,136 @@ impl BufferedBigtableClient {
             .batch_size
             .unwrap_or(DEFAULT_ACCOUNTS_INSERT_BATCH_SIZE);
 
+        let compression = TableCompression::new(config.compression.clone().unwrap_or_default())
+            .with_dictionaries(Self::load_dictionaries(config)?);
+
         info!("Created SimpleBigtableClient.");
         Ok(Self {
-            client: Mutex::new(BigtableClientWrapper { client }),
+            client: Arc::new(BigtableClientWrapper::new(
+                writers,
+                config,
+                &pipeline,
+                degraded_workers,
+            )),
             batch_size,
+            batch_bytes: config
+                .batch_bytes
+                .unwrap_or(DEFAULT_ACCOUNTS_INSERT_BATCH_BYTES),
+            max_account_cell_bytes: config
+                .max_account_cell_bytes
+                .unwrap_or(DEFAULT_MAX_ACCOUNT_CELL_BYTES),
             pending_account_updates: Vec::with_capacity(batch_size),
+            pending_account_bytes: 0,
+            startup_batch_size: config
+                .startup_batch_size
+                .unwrap_or(DEFAULT_STARTUP_BATCH_SIZE),
+            startup_batch_bytes: config
+                .startup_batch_bytes
+                .unwrap_or(DEFAULT_STARTUP_BATCH_BYTES),
+            startup_parallel_streams: config
+                .startup_parallel_streams
+                .unwrap_or(DEFAULT_STARTUP_PARALLEL_STREAMS),
+            pending_startup_account_cells: Vec::default(),
+            pending_startup_bytes: 0,
             index_token_owner: config.index_token_owner.unwrap_or_default(),
             index_token_mint: config.index_token_mint.unwrap_or(false),
             store_account_historical_data,
             pending_token_owner_index: Vec::with_capacity(batch_size),
             pending_token_mint_index: Vec::with_capacity(batch_size),
+            pending_slot_updates: PendingSlots::new(
+                config.slot_batch_size.unwrap_or(DEFAULT_SLOT_BATCH_SIZE),
+                Duration::from_millis(
+                    config
+                        .slot_batch_interval_ms
+                        .unwrap_or(DEFAULT_SLOT_BATCH_INTERVAL_MS),
+                ),
+            ),
+            previous_slot_write: None,
+            pending_transactions: PendingTransactions::new(
+                config
+                    .transaction_batch_size
+                    .unwrap_or(DEFAULT_TRANSACTION_BATCH_SIZE),
+                Duration::from_millis(
+                    config
+                        .transaction_batch_interval_ms
+                        .unwrap_or(DEFAULT_TRANSACTION_BATCH_INTERVAL_MS),
+                ),
+            ),
+            compression: Arc::new(compression),
+            pipeline,
         })
     }
-}
-
-struct BigtableClientWorker {
-    client: BufferedBigtableClient,
-    /// Indicating if accounts notification during startup is done.
-    is_startup_done: bool,
-    runtime: Arc<Runtime>,
-}
-
-impl BigtableClientWorker {
-    fn new(
-        config: GeyserPluginBigtableConfig,
-        runtime: Arc<Runtime>,
-    ) -> Result<Self, GeyserPluginError> {
-        let result = runtime.block_on(BufferedBigtableClient::new(&config));
-        match result {
-            Ok(client) => Ok(BigtableClientWorker {
-                client,
-                is_startup_done: false,
-                runtime,
-            }),
-            Err(err) => {
-                error!("Error in creating SimpleBigtableClient: {}", err);
-                Err(err)
-            }
-        }
-    }
 
-    fn update_account(
-        &mut self,
-        account: DbAccountInfo,
-        is_startup: bool,
-    ) -> Result<(usize, usize), GeyserPluginError> {
-        self.runtime
-            .block_on(self.client.update_account(account, is_startup))
+    /// The count of the accounts, slots and transactions buffered in the pending batches
+    pub fn pending_count(&self) -> usize {
+        self.pending_account_updates.len()
+            + self.pending_startup_account_cells.len()
+            + self.pending_slot_updates.len()
+            + self.pending_transactions.len()
     }
+}
 
-    fn update_slot_status(
-        &mut self,
-        request: UpdateSlotRequest,
-    ) -> Result<(usize, usize), GeyserPluginError> {
-        info!(
-            "Updating slot {:?} at with status {:?}",
-            request.slot, request.slot_status
-        );
-        self.runtime.block_on(self.client.update_slot(request))
-    }
+/// The destination of the work items of a lane, fed by a dispatcher thread
+pub(crate) trait WorkItemSink {
+    fn update_account(&mut self, account: DbAccountInfo, is_startup: bool);
 
-    fn notify_end_of_startup(&mut self) -> Result<(), GeyserPluginError> {
-        Ok(())
-    }
+    fn update_slot_status(&mut self, request: UpdateSlotRequest);
 
-    #[allow(unused_variables)]
     fn log_transaction(
         &mut self,
         transaction_log_info: LogTransactionRequest,
-    ) -> Result<(), GeyserPluginError> {
-        Ok(())
-    }
+    ) -> Result<(), GeyserPluginError>;
 
-    #[allow(unused_variables)]
     fn update_block_metadata(
         &mut self,
         block_info: UpdateBlockMetadataRequest,
-    ) -> Result<(), GeyserPluginError> {
-        Ok(())
+    ) -> Result<(), GeyserPluginError>;
+
+    /// Write out the items buffered, returning once they are written
+    fn notify_end_of_startup(&mut self);
+
+    /// Called when no work item has been received for a while
+    fn on_idle(&mut self);
+
+    /// How long the work items are left in the queue while the destination is unavailable
+    fn paused_for(&self) -> Option<Duration> {
+        None
+    }
+
+    /// If the validator is to be aborted, the queue is then drained as at exit
+    fn abort_requested(&self) -> bool {
+        false
     }
 
+    /// Write out the items buffered at exit until the deadline. Returns the count of the
+    /// work items lost.
+    fn drain(&mut self, receiver: &WorkReceiver, deadline: Instant) -> usize;
+
     fn do_work(
         &mut self,
-        receiver: Receiver<DbWorkItem>,
+        receiver: WorkReceiver,
         exit_worker: Arc<AtomicBool>,
-        is_startup_done: Arc<AtomicBool>,
-        startup_done_count: Arc<AtomicUsize>,
         panic_on_db_errors: bool,
-    ) -> Result<(), GeyserPluginError> {
-        while !exit_worker.load(Ordering::Relaxed) {
+        shutdown_timeout: Duration,
+    ) -> Result<usize, GeyserPluginError> {
+        // Set once the exit is requested, the queue is drained until then
+        let mut deadline = None;
+        loop {
+            // Stop all the dispatchers, and the notifications, as the plugin is unloaded
+            if self.abort_requested() {
+                exit_worker.store(true, Ordering::Relaxed);
+            }
+            if exit_worker.load(Ordering::Relaxed) {
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + shutdown_timeout);
+                if receiver.is_empty() || Instant::now() >= deadline {
+                    break;
+                }
+            }
+            // Leave the work items in the queue while the destination is unavailable
+            if let Some(remaining) = self.paused_for() {
+                sleep(remaining.min(Duration::from_millis(500)));
+                continue;
+            }
             let mut measure = Measure::start("geyser-plugin-bigtable-worker-recv");
             let work = receiver.recv_timeout(Duration::from_millis(500));
             measure.stop();
@@ -232,25 +669,9 @@ impl BigtableClientWorker {
             match work {
                 Ok(work) => match work {
                     DbWorkItem::UpdateAccount(request) => {
-                        match self.update_account(request.account, request.is_startup) {
-                            Err(err) => {
-                                error!("Failed to update account: ({})", err);
-                                if panic_on_db_errors {
-                                    abort();
-                                }
-                            }
-                            Ok(sizes) => Self::update_size_stats(sizes),
-                        }
+                        self.update_account(request.account, request.is_startup)
                     }
-                    DbWorkItem::UpdateSlot(request) => match self.update_slot_status(*request) {
-                        Err(err) => {
-                            error!("Failed to update slot: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
-                        }
-                        Ok(sizes) => Self::update_size_stats(sizes),
-                    },
+                    DbWorkItem::UpdateSlot(request) => self.update_slot_status(*request),
                     DbWorkItem::LogTransaction(transaction_log_info) => {
                         if let Err(err) = self.log_transaction(*transaction_log_info) {
                             error!("Failed to update transaction: ({})", err);
@@ -267,20 +688,15 @@ impl BigtableClientWorker {
                             }
                         }
                     }
+                    DbWorkItem::EndOfStartup(ack) => {
+                        self.notify_end_of_startup();
+                        // The notifier may have given up waiting
+                        let _ = ack.send(());
+                    }
                 },
                 Err(err) => match err {
                     RecvTimeoutError::Timeout => {
-                        if !self.is_startup_done && is_startup_done.load(Ordering::Relaxed) {
-                            if let Err(err) = self.notify_end_of_startup() {
-                                error!("Error in notifying end of startup: ({})", err);
-                                if panic_on_db_errors {
-                                    abort();
-                                }
-                            }
-                            self.is_startup_done = true;
-                            startup_done_count.fetch_add(1, Ordering::Relaxed);
-                        }
-
+                        self.on_idle();
                         continue;
                     }
                     _ => {
@@ -293,84 +709,332 @@ impl BigtableClientWorker {
                 },
             }
         }
+
+        let deadline = deadline.unwrap_or_else(|| Instant::now() + shutdown_timeout);
+        Ok(self.drain(&receiver, deadline))
+    }
+}
+
+/// Dispatches the work items received to the buffered client, which batches them and
+/// spawns the writes on the runtime
+struct BigtableClientWorker {
+    client: BufferedBigtableClient,
+    runtime: Arc<Runtime>,
+    pipeline: WritePipeline,
+    /// The lane of the work items dispatched, whose writes the worker waits for
+    lane: Lane,
+}
+
+impl BigtableClientWorker {
+    fn new(
+        config: &GeyserPluginBigtableConfig,
+        runtime: Arc<Runtime>,
+        pipeline: WritePipeline,
+        lane: Lane,
+        degraded_workers: Arc<AtomicUsize>,
+    ) -> Result<Self, GeyserPluginError> {
+        let result = runtime.block_on(BufferedBigtableClient::new(
+            config,
+            pipeline.clone(),
+            degraded_workers,
+        ));
+        match result {
+            Ok(client) => Ok(BigtableClientWorker {
+                client,
+                runtime,
+                pipeline,
+                lane,
+            }),
+            Err(err) => {
+                error!("Error in creating SimpleBigtableClient: {}", err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Connect to Bigtable, retrying with backoff until connected. `None` if exiting
+    /// before being connected.
+    fn connect(
+        config: &GeyserPluginBigtableConfig,
+        runtime: Arc<Runtime>,
+        pipeline: WritePipeline,
+        lane: Lane,
+        degraded_workers: Arc<AtomicUsize>,
+        exit_worker: &AtomicBool,
+        panic_on_db_errors: bool,
+    ) -> Option<Self> {
+        let mut backoff = config.retry.clone().unwrap_or_default().connect_backoff();
+        loop {
+            match Self::new(
+                config,
+                runtime.clone(),
+                pipeline.clone(),
+                lane,
+                degraded_workers.clone(),
+            ) {
+                Ok(worker) => {
+                    // The workers are counted as degraded until connected
+                    degraded_workers.fetch_sub(1, Ordering::Relaxed);
+                    return Some(worker);
+                }
+                Err(err) => {
+                    error!("Error when making connection to database: ({})", err);
+                    if panic_on_db_errors {
+                        abort();
+                    }
+                }
+            }
+            let delay = backoff.next_backoff().unwrap_or(MAX_CONNECT_INTERVAL);
+            sleep(delay.min(MAX_CONNECT_INTERVAL));
+            if exit_worker.load(Ordering::Relaxed) {
+                return None;
+            }
+        }
+    }
+}
+
+impl WorkItemSink for BigtableClientWorker {
+    fn update_account(&mut self, account: DbAccountInfo, is_startup: bool) {
+        self.runtime
+            .block_on(self.client.update_account(account, is_startup))
+    }
+
+    fn update_slot_status(&mut self, request: UpdateSlotRequest) {
+        info!(
+            "Updating slot {:?} at with status {:?}",
+            request.slot, request.slot_status
+        );
+        self.runtime.block_on(self.client.update_slot(request))
+    }
+
+    /// Flush the startup accounts and wait for the writes of the lane to complete. The
+    /// priority lane does not wait for the account writes, the end of startup waits for
+    /// both lanes.
+    fn notify_end_of_startup(&mut self) {
+        let (client, pipeline, lane) = (&mut self.client, &self.pipeline, self.lane);
+        self.runtime.block_on(async move {
+            client.flush_account_updates().await;
+            client.flush_slot_updates().await;
+            client.flush_transactions().await;
+            pipeline.wait_for_lane_writes(lane).await;
+        })
+    }
+
+    fn log_transaction(
+        &mut self,
+        transaction_log_info: LogTransactionRequest,
+    ) -> Result<(), GeyserPluginError> {
+        self.runtime
+            .block_on(self.client.log_transaction(transaction_log_info));
+        Ok(())
+    }
+
+    fn update_block_metadata(
+        &mut self,
+        block_info: UpdateBlockMetadataRequest,
+    ) -> Result<(), GeyserPluginError> {
+        self.runtime
+            .block_on(self.client.update_block_metadata(block_info));
         Ok(())
     }
 
-    fn update_size_stats(sizes: (usize, usize)) {
-        let (written_size, raw_size) = sizes;
-        inc_new_counter_info!("geyser-bigtable-written-bytes", written_size, 1000000);
-        inc_new_counter_info!("geyser-bigtable-raw-bytes", raw_size, 1000000);
+    /// Write out the slot updates and transactions due, so that they are written at least
+    /// every `slot_batch_interval_ms` and `transaction_batch_interval_ms`
+    fn on_idle(&mut self) {
+        let client = &mut self.client;
+        self.runtime.block_on(async move {
+            client.flush_due_slot_updates().await;
+            client.flush_due_transactions().await;
+        })
+    }
+
+    fn paused_for(&self) -> Option<Duration> {
+        self.pipeline.circuit_breaker().open_for()
+    }
+
+    fn abort_requested(&self) -> bool {
+        self.pipeline.error_policies().abort_requested()
+    }
+
+    /// Write out the pending batches and let the writes in flight complete before the
+    /// runtime is dropped, until the deadline. Returns the count of the work items lost.
+    fn drain(&mut self, receiver: &WorkReceiver, deadline: Instant) -> usize {
+        let queued = receiver.len();
+        let pending = self.client.pending_count();
+        let (client, pipeline, lane) = (&mut self.client, &self.pipeline, self.lane);
+        let (flushed, written) = self.runtime.block_on(async move {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let flush = async {
+                client.flush_account_updates().await;
+                client.flush_slot_updates().await;
+                client.flush_transactions().await;
+            };
+            if tokio::time::timeout(timeout, flush).await.is_err() {
+                return (false, false);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let wait = tokio::time::timeout(timeout, pipeline.wait_for_lane_writes(lane)).await;
+            (true, wait.is_ok())
+        });
+
+        if queued > 0 {
+            error!(
+                "{} work items left in the queue at the shutdown deadline",
+                queued
+            );
+        }
+        if !flushed {
+            error!(
+                "Up to {} pending items not written by the shutdown deadline",
+                pending
+            );
+        } else if !written {
+            error!(
+                "{} requests still in flight at the shutdown deadline",
+                self.pipeline.lane_in_flight(self.lane)
+            );
+        }
+        queued + if flushed { 0 } else { pending }
     }
 }
 
 pub struct ParallelBigtableClient {
-    workers: Vec<JoinHandle<Result<(), GeyserPluginError>>>,
+    /// The dispatcher threads, resolving to the count of the work items they lost at exit
+    workers: Vec<JoinHandle<Result<usize, GeyserPluginError>>>,
     exit_worker: Arc<AtomicBool>,
-    is_startup_done: Arc<AtomicBool>,
-    startup_done_count: Arc<AtomicUsize>,
-    initialized_worker_count: Arc<AtomicUsize>,
-    sender: Sender<DbWorkItem>,
+    /// Indicating if accounts notification during startup is done.
+    is_startup_done: bool,
+    /// How long the end of startup waits for the dispatchers to write out their batches
+    end_of_startup_timeout: Duration,
+    /// The count of workers not connected to Bigtable or whose connection is broken
+    degraded_workers: Arc<AtomicUsize>,
+    /// The count of account writes failed during startup, the checkpoint is not advanced
+    /// if there is any
+    startup_write_failures: Arc<AtomicUsize>,
+    startup_progress: Arc<StartupProgress>,
+    pipeline: WritePipeline,
+    work_queue: WorkQueue,
     last_report: AtomicInterval,
     do_work_on_startup: bool,
+    checkpoint_path: Option<PathBuf>,
+    /// The slot loaded from the checkpoint, startup accounts at or below it are skipped
+    checkpoint_slot: Option<u64>,
+    /// The highest slot of the accounts notified during startup
+    highest_startup_slot: u64,
 }
 
 impl ParallelBigtableClient {
     pub fn new(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
         info!("Creating ParallelBigtableClient...");
-        let (sender, receiver) = bounded(MAX_ASYNC_REQUESTS);
+        let checkpoint_path = config.checkpoint_path.as_ref().map(PathBuf::from);
+        let checkpoint_slot = match &checkpoint_path {
+            Some(path) => Self::load_checkpoint(path)?,
+            None => None,
+        };
+        let (work_queue, receivers) = WorkQueue::new(
+            config.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY),
+            config
+                .priority_queue_capacity
+                .unwrap_or(DEFAULT_PRIORITY_QUEUE_CAPACITY),
+            config
+                .queue_capacity_bytes
+                .unwrap_or(DEFAULT_QUEUE_CAPACITY_BYTES),
+            config.backpressure_policy.unwrap_or_default(),
+            config.spill_path.as_ref().map(Path::new),
+        )
+        .map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+                msg: format!("Failed to create the work queue: {}", err),
+            }))
+        })?;
         let exit_worker = Arc::new(AtomicBool::new(false));
         let mut workers = Vec::default();
-        let is_startup_done = Arc::new(AtomicBool::new(false));
-        let startup_done_count = Arc::new(AtomicUsize::new(0));
-        let worker_count = config.threads.unwrap_or(DEFAULT_THREADS_COUNT);
-        let initialized_worker_count = Arc::new(AtomicUsize::new(0));
-        let thread_per_runtime = 2;
+        let startup_write_failures = Arc::new(AtomicUsize::new(0));
+        let startup_progress = Arc::new(StartupProgress::new(config.startup_accounts_estimate));
+        let panic_on_db_errors = config
+            .panic_on_db_errors
+            .unwrap_or(DEFAULT_PANIC_ON_DB_ERROR);
+        let pipeline = Self::create_pipeline(
+            config,
+            startup_progress.clone(),
+            startup_write_failures.clone(),
+        )?;
         let runtime = Arc::new(
             tokio::runtime::Builder::new_multi_thread()
-                .worker_threads(config.threads.unwrap_or(thread_per_runtime))
+                .worker_threads(config.runtime_threads.unwrap_or(DEFAULT_RUNTIME_THREADS))
                 .thread_name("sol-acountsdb-plugin-bigtable")
                 .enable_all()
                 .build()
                 .expect("Runtime"),
         );
 
-        for i in 0..worker_count {
-            let cloned_receiver = receiver.clone();
+        // A dispatcher per lane batches the work items, the writes run concurrently as
+        // tasks on the runtime.
+        let lanes = [
+            ("bigtable-accounts", Lane::Accounts, receivers.accounts),
+            ("bigtable-priority", Lane::Priority, receivers.priority),
+        ];
+        let degraded_workers = Arc::new(AtomicUsize::new(lanes.len()));
+        // The last dispatcher to exit aborts the validator if requested, once all of them
+        // drained the queue
+        let running_workers = Arc::new(AtomicUsize::new(lanes.len()));
+        let shutdown_timeout = Duration::from_millis(
+            config
+                .shutdown_timeout_ms
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+        );
+        for (name, lane, receiver) in lanes {
+            // Created here so that the errors opening the files fail the load
+            let json_lines_worker = match &config.json_lines {
+                Some(json_lines) => {
+                    Some(JsonLinesWorker::new(json_lines, name, panic_on_db_errors)?)
+                }
+                None => None,
+            };
             let exit_clone = exit_worker.clone();
-            let is_startup_done_clone = is_startup_done.clone();
-            let startup_done_count_clone = startup_done_count.clone();
-            let initialized_worker_count_clone = initialized_worker_count.clone();
             let config = config.clone();
             let runtime = runtime.clone();
+            let pipeline = pipeline.clone();
+            let degraded_workers = degraded_workers.clone();
+            let running_workers = running_workers.clone();
+            let error_policies = pipeline.error_policies();
+            let dispatch = move || -> Result<usize, GeyserPluginError> {
+                if let Some(mut worker) = json_lines_worker {
+                    degraded_workers.fetch_sub(1, Ordering::Relaxed);
+                    return worker.do_work(
+                        receiver,
+                        exit_clone,
+                        panic_on_db_errors,
+                        shutdown_timeout,
+                    );
+                }
+                let worker = BigtableClientWorker::connect(
+                    &config,
+                    runtime,
+                    pipeline,
+                    lane,
+                    degraded_workers,
+                    &exit_clone,
+                    panic_on_db_errors,
+                );
+
+                match worker {
+                    Some(mut worker) => {
+                        worker.do_work(receiver, exit_clone, panic_on_db_errors, shutdown_timeout)
+                    }
+                    None => Ok(receiver.len()),
+                }
+            };
             let worker = Builder::new()
-                .name(format!("worker-{}", i))
-                .spawn(move || -> Result<(), GeyserPluginError> {
-                    let panic_on_db_errors = *config
-                        .panic_on_db_errors
-                        .as_ref()
-                        .unwrap_or(&DEFAULT_PANIC_ON_DB_ERROR);
-                    let result = BigtableClientWorker::new(config, runtime);
-
-                    match result {
-                        Ok(mut worker) => {
-                            initialized_worker_count_clone.fetch_add(1, Ordering::Relaxed);
-                            worker.do_work(
-                                cloned_receiver,
-                                exit_clone,
-                                is_startup_done_clone,
-                                startup_done_count_clone,
-                                panic_on_db_errors,
-                            )?;
-                            Ok(())
-                        }
-                        Err(err) => {
-                            error!("Error when making connection to database: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
-                            Err(err)
-                        }
+                .name(name.to_string())
+                .spawn(move || {
+                    let result = dispatch();
+                    let last = running_workers.fetch_sub(1, Ordering::Relaxed) == 1;
+                    if last && error_policies.abort_requested() {
+                        error!("Aborting as the error budget of a table is exhausted");
+                        abort();
                     }
+                    result
                 })
                 .unwrap();
 
@@ -382,45 +1046,215 @@ impl ParallelBigtableClient {
             last_report: AtomicInterval::default(),
             workers,
             exit_worker,
-            is_startup_done,
-            startup_done_count,
-            initialized_worker_count,
-            sender,
+            is_startup_done: false,
+            end_of_startup_timeout: Duration::from_millis(
+                config
+                    .end_of_startup_timeout_ms
+                    .unwrap_or(DEFAULT_END_OF_STARTUP_TIMEOUT_MS),
+            ),
+            degraded_workers,
+            startup_write_failures,
+            startup_progress,
+            pipeline,
+            work_queue,
             do_work_on_startup: config.write_during_startup.unwrap_or(true),
+            checkpoint_path,
+            checkpoint_slot,
+            highest_startup_slot: 0,
         })
     }
 
+    /// Create the pipeline of the writes with the limits and the error policies configured
+    pub(crate) fn create_pipeline(
+        config: &GeyserPluginBigtableConfig,
+        startup_progress: Arc<StartupProgress>,
+        startup_write_failures: Arc<AtomicUsize>,
+    ) -> Result<WritePipeline, GeyserPluginError> {
+        let panic_on_db_errors = config
+            .panic_on_db_errors
+            .unwrap_or(DEFAULT_PANIC_ON_DB_ERROR);
+        let error_policies = ErrorPolicies::new(
+            config.error_policy.clone().unwrap_or_default(),
+            panic_on_db_errors,
+            config.error_spill_path.as_deref().map(Path::new),
+        )
+        .map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+                msg: format!("Failed to set up the error policies: {}", err),
+            }))
+        })?;
+        // The workers of the former thread pool were each writing one request at a time
+        if config.threads.is_some() {
+            warn!(
+                "The \"threads\" option is deprecated, use \"max_in_flight_requests\" and \
+                 \"runtime_threads\" instead"
+            );
+        }
+        Ok(WritePipeline::new(
+            config
+                .max_in_flight_requests
+                .or(config.threads)
+                .unwrap_or(DEFAULT_MAX_IN_FLIGHT_REQUESTS),
+            config
+                .priority_in_flight_requests
+                .unwrap_or(DEFAULT_PRIORITY_IN_FLIGHT_REQUESTS),
+            startup_progress,
+            startup_write_failures,
+            Arc::new(error_policies),
+            Arc::new(config.retry.clone().unwrap_or_default().circuit_breaker()),
+        ))
+    }
+
+    fn load_checkpoint(path: &Path) -> Result<Option<u64>, GeyserPluginError> {
+        match Checkpoint::load(path) {
+            Ok(checkpoint) => {
+                info!("Loaded checkpoint {:?} from {:?}", checkpoint, path);
+                Ok(checkpoint.map(|checkpoint| checkpoint.slot))
+            }
+            Err(err) => Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginBigtableError::ConfigurationError {
+                    msg: format!("Failed to load the checkpoint from {:?}: {}", path, err),
+                },
+            ))),
+        }
+    }
+
+    fn store_checkpoint(&mut self) -> Result<(), GeyserPluginError> {
+        let path = match &self.checkpoint_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let failures = self.startup_write_failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            warn!(
+                "Not storing the checkpoint as {} account writes failed during startup",
+                failures
+            );
+            return Ok(());
+        }
+        let slot = self.checkpoint_slot.unwrap_or_default();
+        if self.highest_startup_slot <= slot {
+            return Ok(());
+        }
+
+        let checkpoint = Checkpoint {
+            slot: self.highest_startup_slot,
+        };
+        if let Err(err) = checkpoint.store(path) {
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginBigtableError::ConfigurationError {
+                    msg: format!("Failed to store the checkpoint to {:?}: {}", path, err),
+                },
+            )));
+        }
+        info!("Stored checkpoint {:?} to {:?}", checkpoint, path);
+        self.checkpoint_slot = Some(checkpoint.slot);
+        Ok(())
+    }
+
+    /// Check if the account notified during startup has already been durably written
+    /// before the last restart
+    fn is_checkpointed(&self, slot: u64) -> bool {
+        self.checkpoint_slot
+            .map_or(false, |checkpoint_slot| slot <= checkpoint_slot)
+    }
+
+    /// The count of workers not connected to Bigtable, they reconnect in the background
+    pub fn degraded_worker_count(&self) -> usize {
+        self.degraded_workers.load(Ordering::Relaxed)
+    }
+
+    /// If all the workers are connected to Bigtable
+    pub fn is_healthy(&self) -> bool {
+        self.degraded_worker_count() == 0
+    }
+
+    /// Drop the notifications the backpressure policy would block on instead, so that
+    /// a client falling behind does not hold up the other clients notified by the thread
+    pub fn set_never_block(&mut self, never_block: bool) {
+        self.work_queue.set_never_block(never_block);
+    }
+
+    /// Stop the workers once they have drained the queue and written the pending
+    /// batches, or once the shutdown timeout has elapsed
     pub fn join(&mut self) -> thread::Result<()> {
         self.exit_worker.store(true, Ordering::Relaxed);
+        let mut lost = 0;
         while !self.workers.is_empty() {
             let worker = self.workers.pop();
             if worker.is_none() {
                 break;
             }
             let worker = worker.unwrap();
-            let result = worker.join().unwrap();
-            if result.is_err() {
-                error!("The worker thread has failed: {:?}", result);
+            match worker.join().unwrap() {
+                Ok(worker_lost) => lost += worker_lost,
+                Err(err) => error!("The worker thread has failed: {:?}", err),
             }
         }
 
+        if lost > 0 {
+            error!("{} work items were lost at shutdown", lost);
+        } else {
+            info!("Drained the work queue at shutdown");
+        }
+        datapoint_info!(
+            "geyser-plugin-bigtable-shutdown",
+            ("lost-work-items", lost as i64, i64),
+        );
         Ok(())
     }
 
-    pub fn update_account(
+    pub fn update_account<T: ReadableAccountInfo>(
         &mut self,
-        account: &ReplicaAccountInfo,
+        account: &T,
         slot: u64,
         is_startup: bool,
     ) -> Result<(), GeyserPluginError> {
         if self.should_skip_work() {
             return Ok(());
         }
+        if is_startup {
+            self.startup_progress.maybe_report();
+            self.highest_startup_slot = self.highest_startup_slot.max(slot);
+            if self.is_checkpointed(slot) {
+                inc_new_counter_debug!("geyser-plugin-bigtable-skipped-checkpointed-accounts", 1);
+                return Ok(());
+            }
+        }
         if self.last_report.should_update(30000) {
             datapoint_debug!(
                 "bigtable-plugin-stats",
-                ("message-queue-length", self.sender.len() as i64, i64),
+                ("message-queue-length", self.work_queue.len() as i64, i64),
+                (
+                    "message-queue-bytes",
+                    self.work_queue.queued_bytes() as i64,
+                    i64
+                ),
+                ("in-flight-requests", self.pipeline.in_flight() as i64, i64),
+                (
+                    "priority-queue-length",
+                    self.work_queue.priority_len() as i64,
+                    i64
+                ),
+                (
+                    "priority-in-flight-requests",
+                    self.pipeline.priority_in_flight() as i64,
+                    i64
+                ),
+                (
+                    "circuit-breaker-open",
+                    self.pipeline.circuit_breaker().is_open(),
+                    bool
+                ),
+                ("degraded-workers", self.degraded_worker_count() as i64, i64),
             );
+            if !self.is_healthy() {
+                warn!(
+                    "{} of the {} Bigtable workers are not connected",
+                    self.degraded_worker_count(),
+                    self.workers.len()
+                );
+            }
         }
         let mut measure = Measure::start("geyser-plugin-bigtable-create-work-item");
         let wrk_item = DbWorkItem::UpdateAccount(Box::new(UpdateAccountRequest {
@@ -439,14 +1273,21 @@ impl ParallelBigtableClient {
 
         let mut measure = Measure::start("geyser-plugin-bigtable-send-msg");
 
-        if let Err(err) = self.sender.send(wrk_item) {
-            return Err(GeyserPluginError::AccountsUpdateError {
-                msg: format!(
-                    "Failed to update the account {:?}, error: {:?}",
-                    bs58::encode(account.pubkey()).into_string(),
-                    err
-                ),
-            });
+        match self.work_queue.send(wrk_item) {
+            Err(err) => {
+                return Err(GeyserPluginError::AccountsUpdateError {
+                    msg: format!(
+                        "Failed to update the account {:?}, error: {:?}",
+                        bs58::encode(account.pubkey()).into_string(),
+                        err
+                    ),
+                });
+            }
+            // The checkpoint must not cover the startup accounts dropped
+            Ok(QueueOutcome::Dropped) if is_startup => {
+                self.startup_write_failures.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {}
         }
 
         measure.stop();
@@ -469,14 +1310,14 @@ impl ParallelBigtableClient {
         if self.should_skip_work() {
             return Ok(());
         }
-        if let Err(err) = self
-            .sender
-            .send(DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
-                slot,
-                parent,
-                slot_status: status,
-                updated_since_epoch: SystemTime::UNIX_EPOCH.elapsed().unwrap(),
-            })))
+        if let Err(err) =
+            self.work_queue
+                .send(DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
+                    slot,
+                    parent,
+                    slot_status: status,
+                    updated_since_epoch: SystemTime::UNIX_EPOCH.elapsed().unwrap(),
+                })))
         {
             return Err(GeyserPluginError::SlotStatusUpdateError {
                 msg: format!("Failed to update the slot {:?}, error: {:?}", slot, err),
@@ -492,11 +1333,14 @@ impl ParallelBigtableClient {
         if self.should_skip_work() {
             return Ok(());
         }
-        if let Err(err) = self.sender.send(DbWorkItem::UpdateBlockMetadata(Box::new(
-            UpdateBlockMetadataRequest {
-                block_info: DbBlockInfo::from(block_info),
-            },
-        ))) {
+        if let Err(err) = self
+            .work_queue
+            .send(DbWorkItem::UpdateBlockMetadata(Box::new(
+                UpdateBlockMetadataRequest {
+                    block_info: DbBlockInfo::from(block_info),
+                },
+            )))
+        {
             return Err(GeyserPluginError::SlotStatusUpdateError {
                 msg: format!(
                     "Failed to update the block metadata at slot {:?}, error: {:?}",
@@ -507,26 +1351,58 @@ impl ParallelBigtableClient {
         Ok(())
     }
 
+    /// Send the end of startup barrier to each dispatcher, which writes out its pending
+    /// batches once it has processed the work items queued before it, and wait for all the
+    /// dispatchers to acknowledge it
     pub fn notify_end_of_startup(&mut self) -> Result<(), GeyserPluginError> {
         info!("Notifying the end of startup");
-        // Ensure all items in the queue has been received by the workers
-        while !self.sender.is_empty() {
-            sleep(Duration::from_millis(100));
+        self.is_startup_done = true;
+        let deadline = Instant::now() + self.end_of_startup_timeout;
+        let error = |msg: String| {
+            error!("{}", msg);
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::EndOfStartupError {
+                msg,
+            }))
+        };
+
+        // The spilled accounts are read back once the queue in memory is drained, the
+        // barrier must not overtake them
+        while self.work_queue.spilled_len() > 0 {
+            if Instant::now() >= deadline {
+                return Err(error(format!(
+                    "{} spilled account updates not processed by the end of startup timeout",
+                    self.work_queue.spilled_len()
+                )));
+            }
+            sleep(Duration::from_millis(10));
         }
-        self.is_startup_done.store(true, Ordering::Relaxed);
 
-        // Wait for all worker threads to be done with flushing
-        while self.startup_done_count.load(Ordering::Relaxed)
-            != self.initialized_worker_count.load(Ordering::Relaxed)
-        {
-            info!(
-                "Startup done count: {}, good worker thread count: {}",
-                self.startup_done_count.load(Ordering::Relaxed),
-                self.initialized_worker_count.load(Ordering::Relaxed)
-            );
-            sleep(Duration::from_millis(100));
+        let (ack_sender, ack_receiver) = bounded(self.workers.len());
+        let lanes = self
+            .work_queue
+            .send_to_all_lanes(|| DbWorkItem::EndOfStartup(ack_sender.clone()), deadline)
+            .map_err(|err| {
+                error(match err {
+                    SendTimeoutError::Timeout(_) => {
+                        "The end of startup timed out queueing the barrier".to_string()
+                    }
+                    SendTimeoutError::Disconnected(_) => {
+                        "A Bigtable worker has exited before the end of startup".to_string()
+                    }
+                })
+            })?;
+        for acknowledged in 0..lanes {
+            if ack_receiver.recv_deadline(deadline).is_err() {
+                return Err(error(format!(
+                    "Only {} of the {} Bigtable workers have written their batches by the end \
+                    of startup timeout",
+                    acknowledged, lanes
+                )));
+            }
         }
 
+        self.startup_progress.report();
+        self.store_checkpoint()?;
         info!("Done with notifying the end of startup");
         Ok(())
     }
@@ -534,15 +1410,18 @@ impl ParallelBigtableClient {
     fn build_transaction_request(
         slot: u64,
         transaction_info: &ReplicaTransactionInfo,
+        index: Option<usize>,
     ) -> LogTransactionRequest {
         LogTransactionRequest {
-            transaction_info: build_db_transaction(slot, transaction_info),
+            transaction_info: build_db_transaction(slot, transaction_info, index),
         }
     }
 
+    /// Log the transaction, along with its index within the block when notified
     pub fn log_transaction_info(
         &mut self,
         transaction_info: &ReplicaTransactionInfo,
+        index: Option<usize>,
         slot: u64,
     ) -> Result<(), GeyserPluginError> {
         if self.should_skip_work() {
@@ -551,9 +1430,10 @@ impl ParallelBigtableClient {
         let wrk_item = DbWorkItem::LogTransaction(Box::new(Self::build_transaction_request(
             slot,
             transaction_info,
+            index,
         )));
 
-        if let Err(err) = self.sender.send(wrk_item) {
+        if let Err(err) = self.work_queue.send(wrk_item) {
             return Err(GeyserPluginError::SlotStatusUpdateError {
                 msg: format!("Failed to update the transaction, error: {:?}", err),
             });
@@ -562,6 +1442,8 @@ impl ParallelBigtableClient {
     }
 
     fn should_skip_work(&self) -> bool {
-        !self.do_work_on_startup && !self.is_startup_done.load(Ordering::Relaxed)
+        // No more work items are accepted once shutting down
+        self.exit_worker.load(Ordering::Relaxed)
+            || (!self.do_work_on_startup && !self.is_startup_done)
     }
 }
@@ -1,18 +1,24 @@
 use {
-    crate::{parallel_bigtable_client::BufferedBigtableClient},
+    crate::{
+        models::accounts,
+        parallel_bigtable_client::{pipeline::WriteKind, BufferedBigtableClient},
+    },
     log::*,
     prost::Message,
-    solana_bigtable_geyser_models::models::{accounts},
+    serde_derive::{Deserialize, Serialize},
     solana_geyser_plugin_interface::geyser_plugin_interface::{
-        GeyserPluginError, ReplicaAccountInfo,
+        GeyserPluginError, ReplicaAccountInfo, ReplicaAccountInfoV2,
     },
     solana_sdk::pubkey::Pubkey,
     std::time::{Duration, SystemTime},
 };
 
+/// The table storing the latest state of the accounts
+pub const ACCOUNT_TABLE: &str = "account";
+
 impl Eq for DbAccountInfo {}
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct DbAccountInfo {
     pub pubkey: Vec<u8>,
     pub lamports: u64,
@@ -23,8 +29,11 @@ pub struct DbAccountInfo {
     pub slot: u64,
     pub write_version: u64,
     pub updated_since_epoch: Duration,
+    /// The signature of the transaction which caused the update, if notified
+    pub txn_signature: Option<Vec<u8>>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct UpdateAccountRequest {
     pub account: DbAccountInfo,
     pub is_startup: bool,
@@ -43,6 +52,7 @@ impl DbAccountInfo {
             slot,
             write_version: account.write_version(),
             updated_since_epoch: SystemTime::UNIX_EPOCH.elapsed().unwrap(),
+            txn_signature: account.txn_signature().map(|signature| signature.to_vec()),
         }
     }
 }
@@ -75,6 +85,10 @@ impl ReadableAccountInfo for DbAccountInfo {
     fn write_version(&self) -> u64 {
         self.write_version
     }
+
+    fn txn_signature(&self) -> Option<&[u8]> {
+        self.txn_signature.as_deref()
+    }
 }
 
 impl<'a> ReadableAccountInfo for ReplicaAccountInfo<'a> {
@@ -105,6 +119,44 @@ impl<'a> ReadableAccountInfo for ReplicaAccountInfo<'a> {
     fn write_version(&self) -> u64 {
         self.write_version
     }
+
+    fn txn_signature(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl<'a> ReadableAccountInfo for ReplicaAccountInfoV2<'a> {
+    fn pubkey(&self) -> &[u8] {
+        self.pubkey
+    }
+
+    fn owner(&self) -> &[u8] {
+        self.owner
+    }
+
+    fn lamports(&self) -> u64 {
+        self.lamports
+    }
+
+    fn executable(&self) -> bool {
+        self.executable
+    }
+
+    fn rent_epoch(&self) -> u64 {
+        self.rent_epoch
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn write_version(&self) -> u64 {
+        self.write_version
+    }
+
+    fn txn_signature(&self) -> Option<&[u8]> {
+        self.txn_signature.map(|signature| signature.as_ref())
+    }
 }
 
 pub trait ReadableAccountInfo: Sized {
@@ -115,6 +167,7 @@ pub trait ReadableAccountInfo: Sized {
     fn rent_epoch(&self) -> u64;
     fn data(&self) -> &[u8];
     fn write_version(&self) -> u64;
+    fn txn_signature(&self) -> Option<&[u8]>;
 }
 
 impl From<&DbAccountInfo> for accounts::Account {
@@ -135,50 +188,186 @@ impl From<&DbAccountInfo> for accounts::Account {
     }
 }
 
+/// Remove the cells of the account, returning their encoded size
+fn discard_account_cells(account_cells: &mut Vec<(String, accounts::Account)>, key: &str) -> usize {
+    let mut discarded_bytes = 0;
+    account_cells.retain(|(pending_key, account)| {
+        if pending_key != key {
+            return true;
+        }
+        discarded_bytes += account.encoded_len();
+        false
+    });
+    discarded_bytes
+}
+
 impl BufferedBigtableClient {
     /// Update or insert a single account
-    pub async fn update_account(
+    pub async fn update_account(&mut self, account: DbAccountInfo, is_startup: bool) {
+        let account_cell = (
+            Pubkey::new(account.pubkey()).to_string(),
+            accounts::Account::from(&account),
+        );
+        let encoded_len = account_cell.1.encoded_len();
+        if encoded_len > self.max_account_cell_bytes {
+            return self.write_chunked_account(account_cell, is_startup).await;
+        }
+        if is_startup {
+            return self.bulk_load_account(account_cell, encoded_len).await;
+        }
+
+        // The batch is written first if the account would take it past `batch_bytes`
+        if !self.pending_account_updates.is_empty()
+            && self.pending_account_bytes + encoded_len > self.batch_bytes
+        {
+            self.write_pending_account_updates().await;
+        }
+        self.pending_account_bytes += encoded_len;
+        self.pending_account_updates.push(account_cell);
+        if self.pending_account_updates.len() < self.batch_size
+            && self.pending_account_bytes < self.batch_bytes
+        {
+            return;
+        }
+        self.write_pending_account_updates().await
+    }
+
+    /// Drop the updates of the account buffered in the pending batches, superseded by a
+    /// newer update written on its own
+    pub(crate) fn discard_pending_account(&mut self, key: &str) {
+        let discarded_bytes = discard_account_cells(&mut self.pending_account_updates, key);
+        self.pending_account_bytes = self.pending_account_bytes.saturating_sub(discarded_bytes);
+        let discarded_bytes = discard_account_cells(&mut self.pending_startup_account_cells, key);
+        self.pending_startup_bytes = self.pending_startup_bytes.saturating_sub(discarded_bytes);
+    }
+
+    /// Write out all the account updates still buffered in the pending batches
+    pub async fn flush_account_updates(&mut self) {
+        self.write_pending_startup_account_cells().await;
+        if !self.pending_account_updates.is_empty() {
+            self.write_pending_account_updates().await;
+        }
+    }
+
+    /// Buffer an account notified during startup. The snapshot accounts are written
+    /// in larger batches bounded by both count and encoded size, using parallel streams.
+    async fn bulk_load_account(
         &mut self,
-        account: DbAccountInfo,
-        _is_startup: bool,
-    ) -> Result<(usize, usize), GeyserPluginError> {
-        let account_cells = {
-            self.pending_account_updates.push(account);
-
-            if self.pending_account_updates.len() == self.batch_size {
-                self.pending_account_updates
-                    .drain(..)
-                    .map(|account| {
-                        (
-                            Pubkey::new(account.pubkey()).to_string(),
-                            accounts::Account::from(&account),
-                        )
-                    })
-                    .collect::<Vec<(String, accounts::Account)>>()
-            } else {
-                return Ok((0, 0));
-            }
+        account_cell: (String, accounts::Account),
+        encoded_len: usize,
+    ) {
+        if self.pending_startup_bytes + encoded_len > self.startup_batch_bytes {
+            self.write_pending_startup_account_cells().await;
+        }
+        self.pending_startup_bytes += encoded_len;
+        self.pending_startup_account_cells.push(account_cell);
+
+        if self.pending_startup_account_cells.len() < self.startup_batch_size
+            && self.pending_startup_bytes < self.startup_batch_bytes
+        {
+            return;
+        }
+        self.write_pending_startup_account_cells().await
+    }
+
+    async fn write_pending_startup_account_cells(&mut self) {
+        if self.pending_startup_account_cells.is_empty() {
+            return;
+        }
+        let mut account_cells = std::mem::take(&mut self.pending_startup_account_cells);
+        self.pending_startup_bytes = 0;
+
+        // Each of the streams is a separate request running concurrently
+        let streams = self.startup_parallel_streams.max(1);
+        let chunk_size = (account_cells.len() + streams - 1) / streams;
+        while !account_cells.is_empty() {
+            let chunk: Vec<_> = account_cells
+                .drain(..chunk_size.min(account_cells.len()))
+                .collect();
+            let raw_size = chunk.iter().map(|(_, account)| account.encoded_len()).sum();
+            let client = self.client.clone();
+            let compression = self.compression.clone();
+            let kind = WriteKind::Accounts {
+                count: chunk.len(),
+                is_startup: true,
+            };
+            self.pipeline
+                .spawn(kind, async move {
+                    match client
+                        .put_account_cells(ACCOUNT_TABLE, &chunk, &compression)
+                        .await
+                    {
+                        Ok(written_size) => Ok((written_size, raw_size)),
+                        Err(err) => {
+                            // Logging each of the accounts would flood the log when bulk loading.
+                            error!(
+                                "Error bulk loading a batch of {} accounts into the database: {}",
+                                chunk.len(),
+                                err
+                            );
+                            Err(GeyserPluginError::Custom(Box::new(err)))
+                        }
+                    }
+                })
+                .await;
+        }
+    }
+
+    async fn write_pending_account_updates(&mut self) {
+        let account_cells = std::mem::take(&mut self.pending_account_updates);
+        let raw_size = self.pending_account_bytes;
+        self.pending_account_bytes = 0;
+
+        let client = self.client.clone();
+        let compression = self.compression.clone();
+        let kind = WriteKind::Accounts {
+            count: account_cells.len(),
+            is_startup: false,
         };
-        let raw_size = account_cells.iter().map(|(_, m)| m.encoded_len()).sum();
-
-        let client = self.client.lock().unwrap();
-        let result = client
-            .client
-            .put_protobuf_cells_with_retry::<accounts::Account>("account", &account_cells, true)
-            .await;
-        match result {
-            Ok(written_size) => Ok((written_size, raw_size)),
-            Err(err) => {
-                error!("Error persisting into the database: {}", err);
-                for (key, account) in account_cells.iter() {
-                    error!(
-                        "Error persisting into the database: pubkey: {}, len: {} ",
-                        key,
-                        account.data.len()
-                    );
+        self.pipeline
+            .spawn(kind, async move {
+                let result = client
+                    .put_account_cells(ACCOUNT_TABLE, &account_cells, &compression)
+                    .await;
+                match result {
+                    Ok(written_size) => Ok((written_size, raw_size)),
+                    Err(err) => {
+                        error!("Error persisting into the database: {}", err);
+                        for (key, account) in account_cells.iter() {
+                            error!(
+                                "Error persisting into the database: pubkey: {}, len: {} ",
+                                key,
+                                account.data.len()
+                            );
+                        }
+                        Err(GeyserPluginError::Custom(Box::new(err)))
+                    }
                 }
-                Err(GeyserPluginError::Custom(Box::new(err)))
-            }
-        }
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discard_account_cells() {
+        let account = |write_version| accounts::Account {
+            write_version,
+            ..accounts::Account::default()
+        };
+        let mut account_cells = vec![
+            ("a".to_string(), account(1)),
+            ("b".to_string(), account(2)),
+            ("a".to_string(), account(3)),
+        ];
+        let discarded_bytes = discard_account_cells(&mut account_cells, "a");
+        assert_eq!(
+            discarded_bytes,
+            account(1).encoded_len() + account(3).encoded_len()
+        );
+        assert_eq!(account_cells, vec![("b".to_string(), account(2))]);
     }
 }
@@ -0,0 +1,305 @@
+/// Accounts whose encoding exceeds the cell size limit are stored in chunks. The account
+/// row keeps the metadata along with the count of chunks, while the data is split into
+/// the rows of the `account_chunk` table keyed by the pubkey, write version and index.
+use {
+    crate::{
+        models::accounts,
+        parallel_bigtable_client::{
+            account::ACCOUNT_TABLE, pipeline::WriteKind, BufferedBigtableClient,
+        },
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+/// The table storing the data chunks of the accounts too large for a single cell
+pub const ACCOUNT_CHUNK_TABLE: &str = "account_chunk";
+
+/// The account row written for an account whose data is stored in chunks. The fields are
+/// wire compatible with `accounts::Account` with the `data` left empty, so that readers
+/// unaware of the chunking still decode the account metadata.
+#[derive(Clone, PartialEq, Message)]
+pub struct ChunkedAccount {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pubkey: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub owner: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub lamports: u64,
+    #[prost(uint64, tag = "4")]
+    pub slot: u64,
+    #[prost(bool, tag = "5")]
+    pub executable: bool,
+    #[prost(uint64, tag = "6")]
+    pub rent_epoch: u64,
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: Vec<u8>,
+    #[prost(uint64, tag = "8")]
+    pub write_version: u64,
+    #[prost(message, optional, tag = "9")]
+    pub updated_on: Option<accounts::UnixTimestamp>,
+    /// The count of chunks the data is split into, 0 if the data is stored inline
+    #[prost(uint32, tag = "100")]
+    pub data_chunks: u32,
+    /// The total length of the account data
+    #[prost(uint64, tag = "101")]
+    pub data_len: u64,
+}
+
+/// A piece of the data of a chunked account
+#[derive(Clone, PartialEq, Message)]
+pub struct AccountDataChunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pubkey: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub write_version: u64,
+    #[prost(uint32, tag = "3")]
+    pub index: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub data: Vec<u8>,
+}
+
+pub fn account_chunk_row_key(pubkey: &str, write_version: u64, index: u32) -> String {
+    format!("{}/{:016x}/{:08x}", pubkey, write_version, index)
+}
+
+/// The range of the chunk rows of the versions of the account older than `write_version`,
+/// both ends included. The write versions are fixed width in the keys, so the keys of the
+/// versions sort in the order of the versions.
+pub fn older_chunks_range(pubkey: &str, write_version: u64) -> (String, String) {
+    (
+        format!("{}/", pubkey),
+        format!("{}/{:016x}", pubkey, write_version),
+    )
+}
+
+/// Split the account into the account row and the data chunks of at most `chunk_size` bytes
+pub fn split_account(
+    key: &str,
+    account: accounts::Account,
+    chunk_size: usize,
+) -> (ChunkedAccount, Vec<(String, AccountDataChunk)>) {
+    let chunks: Vec<(String, AccountDataChunk)> = account
+        .data
+        .chunks(chunk_size.max(1))
+        .enumerate()
+        .map(|(index, data)| {
+            (
+                account_chunk_row_key(key, account.write_version, index as u32),
+                AccountDataChunk {
+                    pubkey: account.pubkey.clone(),
+                    write_version: account.write_version,
+                    index: index as u32,
+                    data: data.to_vec(),
+                },
+            )
+        })
+        .collect();
+
+    let chunked_account = ChunkedAccount {
+        pubkey: account.pubkey,
+        owner: account.owner,
+        lamports: account.lamports,
+        slot: account.slot,
+        executable: account.executable,
+        rent_epoch: account.rent_epoch,
+        data: Vec::default(),
+        write_version: account.write_version,
+        updated_on: account.updated_on,
+        data_chunks: chunks.len() as u32,
+        data_len: account.data.len() as u64,
+    };
+    (chunked_account, chunks)
+}
+
+/// Rebuild the account from the account row and its data chunks
+pub fn reassemble_account(
+    chunked_account: ChunkedAccount,
+    mut chunks: Vec<AccountDataChunk>,
+) -> Result<accounts::Account, String> {
+    chunks.sort_by_key(|chunk| chunk.index);
+    if chunks.len() != chunked_account.data_chunks as usize {
+        return Err(format!(
+            "expected {} chunks, found {}",
+            chunked_account.data_chunks,
+            chunks.len()
+        ));
+    }
+
+    let mut data = Vec::with_capacity(chunked_account.data_len as usize);
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        if chunk.index as usize != index
+            || chunk.write_version != chunked_account.write_version
+            || chunk.pubkey != chunked_account.pubkey
+        {
+            return Err(format!(
+                "unexpected chunk {} of write version {}",
+                chunk.index, chunk.write_version
+            ));
+        }
+        data.extend_from_slice(&chunk.data);
+    }
+    if data.len() as u64 != chunked_account.data_len {
+        return Err(format!(
+            "expected {} bytes of data, found {}",
+            chunked_account.data_len,
+            data.len()
+        ));
+    }
+
+    Ok(accounts::Account {
+        data,
+        ..accounts::Account::from(chunked_account)
+    })
+}
+
+impl From<ChunkedAccount> for accounts::Account {
+    fn from(account: ChunkedAccount) -> Self {
+        accounts::Account {
+            pubkey: account.pubkey,
+            owner: account.owner,
+            lamports: account.lamports,
+            slot: account.slot,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: account.data,
+            write_version: account.write_version,
+            updated_on: account.updated_on,
+        }
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Write an account too large for a single cell. The chunks are written before the
+    /// account row so that readers never find an account row without its chunks, the chunks
+    /// of the older versions are deleted once the account row no longer refers to them.
+    /// An account shrinking back below the cell size limit is written inline, its last
+    /// chunks are left to the garbage collection policy of the table.
+    pub(crate) async fn write_chunked_account(
+        &mut self,
+        account_cell: (String, accounts::Account),
+        is_startup: bool,
+    ) {
+        let (key, account) = account_cell;
+        // The older updates of the account still buffered would overwrite the account
+        // row once written
+        self.discard_pending_account(&key);
+        let raw_size = account.encoded_len();
+        let write_version = account.write_version;
+        let (chunked_account, chunks) = split_account(&key, account, self.max_account_cell_bytes);
+        debug!(
+            "Writing account {} of {} bytes in {} chunks",
+            key,
+            chunked_account.data_len,
+            chunks.len()
+        );
+
+        let client = self.client.clone();
+        let compression = self.compression.clone();
+        let kind = WriteKind::Accounts {
+            count: 1,
+            is_startup,
+        };
+        self.pipeline
+            .spawn(kind, async move {
+                let mut written_size = 0;
+                for chunk in &chunks {
+                    written_size += client
+                        .put_protobuf_cells(
+                            ACCOUNT_CHUNK_TABLE,
+                            std::slice::from_ref(chunk),
+                            compression.for_table(ACCOUNT_CHUNK_TABLE),
+                        )
+                        .await
+                        .map_err(|err| {
+                            error!("Error persisting the chunks of account {}: {}", key, err);
+                            GeyserPluginError::Custom(Box::new(err))
+                        })?;
+                }
+                written_size += client
+                    .put_protobuf_cells(
+                        ACCOUNT_TABLE,
+                        &[(key.clone(), chunked_account)],
+                        compression.for_table(ACCOUNT_TABLE),
+                    )
+                    .await
+                    .map_err(|err| {
+                        error!("Error persisting the chunked account {}: {}", key, err);
+                        GeyserPluginError::Custom(Box::new(err))
+                    })?;
+
+                // Only the older versions are deleted, a newer version written concurrently
+                // keeps its chunks
+                let (start_at, end_at) = older_chunks_range(&key, write_version);
+                let result = match client
+                    .get_row_keys(ACCOUNT_CHUNK_TABLE, &start_at, &end_at)
+                    .await
+                {
+                    Ok(row_keys) if row_keys.is_empty() => Ok(()),
+                    Ok(row_keys) => client.delete_rows(ACCOUNT_CHUNK_TABLE, &row_keys).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    warn!(
+                        "Error deleting the chunks of the older versions of account {}: {}",
+                        key, err
+                    );
+                }
+                Ok((written_size, raw_size))
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, solana_sdk::pubkey::Pubkey};
+
+    #[test]
+    fn test_split_and_reassemble_account() {
+        let pubkey = Pubkey::new_unique();
+        let account = accounts::Account {
+            pubkey: pubkey.to_bytes().to_vec(),
+            owner: Pubkey::new_unique().to_bytes().to_vec(),
+            lamports: 1234,
+            slot: 12345,
+            executable: false,
+            rent_epoch: 0,
+            data: (0..1000).map(|i| i as u8).collect(),
+            write_version: 7,
+            updated_on: None,
+        };
+
+        let (chunked_account, chunks) = split_account(&pubkey.to_string(), account.clone(), 300);
+        assert_eq!(chunked_account.data_chunks, 4);
+        assert!(chunked_account.data.is_empty());
+        assert_eq!(
+            chunks[3].0,
+            account_chunk_row_key(&pubkey.to_string(), 7, 3)
+        );
+
+        // Readers unaware of the chunking still decode the account metadata
+        let decoded = accounts::Account::decode(&chunked_account.encode_to_vec()[..]).unwrap();
+        assert_eq!(decoded.lamports, account.lamports);
+        assert!(decoded.data.is_empty());
+
+        let mut chunks: Vec<AccountDataChunk> =
+            chunks.into_iter().map(|(_, chunk)| chunk).collect();
+        chunks.reverse();
+        assert_eq!(
+            reassemble_account(chunked_account.clone(), chunks.clone()).unwrap(),
+            account
+        );
+
+        chunks.pop();
+        assert!(reassemble_account(chunked_account, chunks).is_err());
+
+        // The range covers the chunks of the older versions only
+        let (start_at, end_at) = older_chunks_range(&pubkey.to_string(), 7);
+        for (write_version, is_older) in [(0, true), (6, true), (7, false), (0x10, false)] {
+            let key = account_chunk_row_key(&pubkey.to_string(), write_version, 3);
+            assert_eq!(start_at <= key && key <= end_at, is_older);
+        }
+    }
+}
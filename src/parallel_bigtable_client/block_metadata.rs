@@ -1,9 +1,24 @@
 use {
-    crate::parallel_bigtable_client::transaction::DbReward,
-    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaBlockInfo,
+    crate::parallel_bigtable_client::{
+        pipeline::WriteKind, transaction::DbReward, BufferedBigtableClient,
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, ReplicaBlockInfo,
+    },
 };
 
-#[derive(Clone, Debug)]
+/// The table storing the block metadata, keyed by slot
+pub const BLOCK_TABLE: &str = "block";
+
+/// The row key of a block, the slot in fixed width hexadecimal like the transaction rows
+/// so that the blocks are stored in the order of their slots
+pub fn block_row_key(slot: u64) -> String {
+    format!("{:016x}", slot)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbBlockInfo {
     pub slot: i64,
     pub blockhash: String,
@@ -29,3 +44,46 @@ impl<'a> From<&ReplicaBlockInfo<'a>> for DbBlockInfo {
 pub struct UpdateBlockMetadataRequest {
     pub block_info: DbBlockInfo,
 }
+
+impl BufferedBigtableClient {
+    /// Write the metadata of a block. A block is notified once per slot, so each is written
+    /// by its own request.
+    pub async fn update_block_metadata(&mut self, request: UpdateBlockMetadataRequest) {
+        let block_info = request.block_info;
+        let key = block_row_key(block_info.slot as u64);
+        let raw_size = bincode::serialized_size(&block_info).unwrap() as usize;
+
+        let client = self.client.clone();
+        let compression = self.compression.clone();
+        self.pipeline
+            .spawn(WriteKind::Blocks { count: 1 }, async move {
+                let result = client
+                    .put_bincode_cells(
+                        BLOCK_TABLE,
+                        &[(key.clone(), block_info)],
+                        compression.for_table(BLOCK_TABLE),
+                    )
+                    .await;
+                match result {
+                    Ok(written_size) => Ok((written_size, raw_size)),
+                    Err(err) => {
+                        error!("Error persisting the block {}: {}", key, err);
+                        Err(GeyserPluginError::Custom(Box::new(err)))
+                    }
+                }
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_row_key() {
+        assert_eq!(block_row_key(300), "000000000000012c");
+        // The blocks are ordered by slot
+        assert!(block_row_key(0xff) < block_row_key(0x100));
+    }
+}
@@ -0,0 +1,373 @@
+/// What happens to a write which still fails once retried, configured per table. The
+/// writes can abort the validator once the error budget of the table is exhausted, be
+/// paused and retried until they succeed, be spilled to the local disk to be replayed
+/// by the `replay-failed` command, or be skipped.
+use {
+    crate::compression::CompressedCell,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::RowKey,
+    std::{
+        collections::HashMap,
+        fs::{self, File, OpenOptions},
+        io::{self, BufWriter, Read, Write},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+const DEFAULT_PAUSE_MS: u64 = 1000;
+
+/// The key of the error policy applying to the tables not configured
+const DEFAULT_TABLE_KEY: &str = "*";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorAction {
+    /// Abort the validator once the error budget is exhausted, pausing and retrying the
+    /// write until then. The queue is drained until the shutdown deadline before exiting.
+    Abort,
+    /// Pause and retry the write until it succeeds, the queue fills up meanwhile
+    PauseAndRetry,
+    /// Append the cells to a file on the local disk, to be replayed by `replay-failed`
+    SpillToDisk,
+    /// Log the error and go on
+    Skip,
+}
+
+/// The error policy of a table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorPolicyConfig {
+    pub action: ErrorAction,
+
+    /// The count of writes failing in a row exhausting the error budget
+    pub max_consecutive_failures: Option<usize>,
+
+    /// How long in milliseconds the writes can fail before the error budget is exhausted
+    pub max_outage_ms: Option<u64>,
+
+    /// The pause in milliseconds before a write is retried. The default is 1000.
+    pub pause_ms: Option<u64>,
+}
+
+impl ErrorPolicyConfig {
+    fn new(action: ErrorAction) -> Self {
+        Self {
+            action,
+            max_consecutive_failures: None,
+            max_outage_ms: None,
+            pause_ms: None,
+        }
+    }
+}
+
+/// What to do with a write which failed
+#[derive(Debug, PartialEq, Eq)]
+pub enum ErrorDecision {
+    Retry(Duration),
+    Spill,
+    Skip,
+    Abort,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    consecutive_failures: usize,
+    failing_since: Option<Instant>,
+}
+
+struct TablePolicy {
+    config: ErrorPolicyConfig,
+    budget: Mutex<BudgetState>,
+}
+
+impl TablePolicy {
+    fn new(config: ErrorPolicyConfig) -> Self {
+        Self {
+            config,
+            budget: Mutex::default(),
+        }
+    }
+
+    /// Record the failure, returning if the error budget is exhausted. Without any limit
+    /// configured, the budget is exhausted by the first failure.
+    fn record_failure(&self) -> bool {
+        let mut budget = self.budget.lock().unwrap();
+        budget.consecutive_failures += 1;
+        let failing_since = *budget.failing_since.get_or_insert_with(Instant::now);
+        match (
+            self.config.max_consecutive_failures,
+            self.config.max_outage_ms,
+        ) {
+            (None, None) => true,
+            (max_failures, max_outage_ms) => {
+                max_failures.map_or(false, |max| budget.consecutive_failures >= max)
+                    || max_outage_ms.map_or(false, |max| {
+                        failing_since.elapsed() >= Duration::from_millis(max)
+                    })
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        *self.budget.lock().unwrap() = BudgetState::default();
+    }
+
+    fn pause(&self) -> Duration {
+        Duration::from_millis(self.config.pause_ms.unwrap_or(DEFAULT_PAUSE_MS))
+    }
+}
+
+/// The cells of the failed writes, appended to a file per table
+struct FailedWrites {
+    dir: PathBuf,
+    writers: Mutex<HashMap<String, BufWriter<File>>>,
+}
+
+impl FailedWrites {
+    fn new(dir: &Path) -> Result<Self, io::Error> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            writers: Mutex::default(),
+        })
+    }
+
+    fn append(&self, table: &str, cells: &[(RowKey, CompressedCell)]) -> Result<(), io::Error> {
+        let mut writers = self.writers.lock().unwrap();
+        let writer = match writers.get_mut(table) {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(failed_writes_path(&self.dir, table))?;
+                writers
+                    .entry(table.to_string())
+                    .or_insert(BufWriter::new(file))
+            }
+        };
+        for (key, cell) in cells {
+            write_failed_write(&mut *writer, key, cell)?;
+        }
+        writer.flush()
+    }
+}
+
+/// The file the failed writes to the table are appended to
+pub fn failed_writes_path(dir: &Path, table: &str) -> PathBuf {
+    dir.join(format!("{}.failed", table))
+}
+
+/// Append the record of a failed write, the bincode encoding of the row key and the
+/// compressed cell
+pub fn write_failed_write<W: Write>(
+    writer: W,
+    key: &RowKey,
+    cell: &CompressedCell,
+) -> Result<(), io::Error> {
+    bincode::serialize_into(writer, &(key, &cell.0))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// Read the next record of a failed write, `None` at the end of the file. A record cut
+/// short by a crash while it was appended is ignored.
+pub fn read_failed_write<R: Read>(
+    reader: R,
+) -> Result<Option<(RowKey, CompressedCell)>, io::Error> {
+    match bincode::deserialize_from::<_, (RowKey, Vec<u8>)>(reader) {
+        Ok((key, cell)) => Ok(Some((key, CompressedCell(cell)))),
+        Err(err) => match *err {
+            bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            err => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        },
+    }
+}
+
+/// The error policies of the tables, shared by all the writes
+pub struct ErrorPolicies {
+    tables: HashMap<String, TablePolicy>,
+    default: TablePolicy,
+    failed_writes: Option<FailedWrites>,
+    /// Set once the error budget of a table is exhausted, the dispatchers then drain the
+    /// queue before the validator is aborted
+    abort_requested: AtomicBool,
+}
+
+impl ErrorPolicies {
+    /// The "*" key configures the tables not listed. Without it, the errors abort right
+    /// away if `panic_on_db_errors` is set, and are skipped otherwise.
+    pub fn new(
+        mut configs: HashMap<String, ErrorPolicyConfig>,
+        panic_on_db_errors: bool,
+        spill_path: Option<&Path>,
+    ) -> Result<Self, io::Error> {
+        let default = configs.remove(DEFAULT_TABLE_KEY).unwrap_or_else(|| {
+            ErrorPolicyConfig::new(if panic_on_db_errors {
+                ErrorAction::Abort
+            } else {
+                ErrorAction::Skip
+            })
+        });
+        let spills = default.action == ErrorAction::SpillToDisk
+            || configs
+                .values()
+                .any(|config| config.action == ErrorAction::SpillToDisk);
+        let failed_writes = match spill_path {
+            Some(path) if spills => Some(FailedWrites::new(path)?),
+            None if spills => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the spill-to-disk error policy requires \"error_spill_path\"",
+                ))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            tables: configs
+                .into_iter()
+                .map(|(table, config)| (table, TablePolicy::new(config)))
+                .collect(),
+            default: TablePolicy::new(default),
+            failed_writes,
+            abort_requested: AtomicBool::new(false),
+        })
+    }
+
+    fn for_table(&self, table: &str) -> &TablePolicy {
+        self.tables.get(table).unwrap_or(&self.default)
+    }
+
+    pub fn record_success(&self, table: &str) {
+        self.for_table(table).record_success()
+    }
+
+    /// Have the validator aborted once the dispatchers drained the queue
+    pub fn request_abort(&self) {
+        self.abort_requested.store(true, Ordering::Relaxed);
+    }
+
+    pub fn abort_requested(&self) -> bool {
+        self.abort_requested.load(Ordering::Relaxed)
+    }
+
+    /// Record the failure of a write to the table, deciding what to do with it
+    pub fn on_failure(&self, table: &str) -> ErrorDecision {
+        let policy = self.for_table(table);
+        match policy.config.action {
+            ErrorAction::Abort => {
+                if policy.record_failure() {
+                    ErrorDecision::Abort
+                } else {
+                    ErrorDecision::Retry(policy.pause())
+                }
+            }
+            ErrorAction::PauseAndRetry => ErrorDecision::Retry(policy.pause()),
+            ErrorAction::SpillToDisk => ErrorDecision::Spill,
+            ErrorAction::Skip => ErrorDecision::Skip,
+        }
+    }
+
+    /// Append the cells of the failed write to the file of the table. Each record is the
+    /// bincode encoding of the row key and the compressed cell.
+    pub fn spill(&self, table: &str, cells: &[(RowKey, CompressedCell)]) -> Result<(), io::Error> {
+        match &self.failed_writes {
+            Some(failed_writes) => failed_writes.append(table, cells),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no \"error_spill_path\" configured",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn config(action: ErrorAction) -> ErrorPolicyConfig {
+        ErrorPolicyConfig {
+            pause_ms: Some(10),
+            ..ErrorPolicyConfig::new(action)
+        }
+    }
+
+    #[test]
+    fn test_error_policies() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "slot".to_string(),
+            ErrorPolicyConfig {
+                max_consecutive_failures: Some(2),
+                ..config(ErrorAction::Abort)
+            },
+        );
+        configs.insert("account".to_string(), config(ErrorAction::PauseAndRetry));
+        let policies = ErrorPolicies::new(configs, false, None).unwrap();
+
+        let pause = Duration::from_millis(10);
+        assert_eq!(policies.on_failure("slot"), ErrorDecision::Retry(pause));
+        policies.record_success("slot");
+        assert_eq!(policies.on_failure("slot"), ErrorDecision::Retry(pause));
+        assert_eq!(policies.on_failure("slot"), ErrorDecision::Abort);
+
+        assert_eq!(policies.on_failure("account"), ErrorDecision::Retry(pause));
+        assert_eq!(policies.on_failure("tx"), ErrorDecision::Skip);
+
+        // Without limits, the first failure aborts
+        let policies = ErrorPolicies::new(HashMap::new(), true, None).unwrap();
+        assert_eq!(policies.on_failure("tx"), ErrorDecision::Abort);
+    }
+
+    #[test]
+    fn test_error_budget_outage() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            DEFAULT_TABLE_KEY.to_string(),
+            ErrorPolicyConfig {
+                max_outage_ms: Some(20),
+                ..config(ErrorAction::Abort)
+            },
+        );
+        let policies = ErrorPolicies::new(configs, false, None).unwrap();
+        assert!(matches!(
+            policies.on_failure("slot"),
+            ErrorDecision::Retry(_)
+        ));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(policies.on_failure("slot"), ErrorDecision::Abort);
+    }
+
+    #[test]
+    fn test_spill_failed_writes() {
+        let mut configs = HashMap::new();
+        configs.insert("account".to_string(), config(ErrorAction::SpillToDisk));
+        assert!(ErrorPolicies::new(configs.clone(), false, None).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let policies = ErrorPolicies::new(configs, false, Some(dir.path())).unwrap();
+        assert_eq!(policies.on_failure("account"), ErrorDecision::Spill);
+        let cells = vec![
+            ("a".to_string(), CompressedCell(vec![1, 2])),
+            ("b".to_string(), CompressedCell(vec![3])),
+        ];
+        policies.spill("account", &cells).unwrap();
+        policies.spill("account", &cells[1..]).unwrap();
+
+        let mut file = File::open(failed_writes_path(dir.path(), "account")).unwrap();
+        let mut records = vec![];
+        while let Some(record) = read_failed_write(&mut file).unwrap() {
+            records.push(record);
+        }
+        assert_eq!(records, [&cells[..], &cells[1..]].concat());
+
+        // A record cut short is ignored
+        let mut data = vec![];
+        write_failed_write(&mut data, &cells[0].0, &cells[0].1).unwrap();
+        assert_eq!(read_failed_write(&data[..data.len() - 1]).unwrap(), None);
+    }
+}
@@ -0,0 +1,299 @@
+/// With `json_lines` configured, the work items are written as JSON lines to rotating files
+/// instead of Bigtable, for local development without a Bigtable instance. The selectors
+/// and the work queue apply as usual, and each lane writes its own files named
+/// `<lane>-<index>.jsonl`, so the files are a record of what the plugin would write.
+use {
+    crate::parallel_bigtable_client::{
+        abort,
+        account::DbAccountInfo,
+        block_metadata::{DbBlockInfo, UpdateBlockMetadataRequest},
+        slot::UpdateSlotRequest,
+        transaction::{DbTransaction, LogTransactionRequest},
+        work_queue::WorkReceiver,
+        WorkItemSink,
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{
+        collections::VecDeque,
+        fs::{self, File},
+        io::{self, BufWriter, Write},
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
+};
+
+/// The default size of a file before the next one is started
+pub const DEFAULT_JSON_LINES_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonLinesConfig {
+    /// The directory the files are written to
+    pub path: String,
+
+    /// The size of a file before the next one is started
+    pub max_file_bytes: Option<u64>,
+
+    /// The count of files kept for each lane, the oldest are removed. All the files are
+    /// kept if not set.
+    pub max_files: Option<usize>,
+}
+
+/// A line of the files, tagged with the kind of the work item
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    Account {
+        is_startup: bool,
+        account: &'a DbAccountInfo,
+    },
+    Slot {
+        slot: u64,
+        parent: Option<u64>,
+        status: &'a str,
+        updated_since_epoch: Duration,
+    },
+    Transaction {
+        transaction: &'a DbTransaction,
+    },
+    BlockMetadata {
+        block_info: &'a DbBlockInfo,
+    },
+    EndOfStartup,
+}
+
+/// Files of JSON lines named `<prefix>-<index>.jsonl`, starting the next file once the
+/// current one is larger than `max_file_bytes`. The numbering continues after the files
+/// already in the directory, so restarting does not overwrite them.
+pub struct RotatingFiles {
+    dir: PathBuf,
+    prefix: String,
+    max_file_bytes: u64,
+    max_files: Option<usize>,
+    file: Option<BufWriter<File>>,
+    file_bytes: u64,
+    next_index: u64,
+    /// The files written, from the oldest
+    files: VecDeque<PathBuf>,
+}
+
+impl RotatingFiles {
+    pub fn new(
+        dir: &Path,
+        prefix: &str,
+        max_file_bytes: u64,
+        max_files: Option<usize>,
+    ) -> Result<Self, io::Error> {
+        fs::create_dir_all(dir)?;
+        let mut existing = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let index = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(prefix)?.strip_prefix('-'))
+                .and_then(|name| name.strip_suffix(".jsonl"))
+                .and_then(|index| index.parse::<u64>().ok());
+            if let Some(index) = index {
+                existing.push((index, path));
+            }
+        }
+        existing.sort_unstable();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_string(),
+            max_file_bytes: max_file_bytes.max(1),
+            max_files,
+            file: None,
+            file_bytes: 0,
+            next_index: existing.last().map_or(0, |(index, _)| index + 1),
+            files: existing.into_iter().map(|(_, path)| path).collect(),
+        })
+    }
+
+    pub fn write_line<T: serde::Serialize>(&mut self, value: &T) -> Result<(), io::Error> {
+        if self.file.is_none() || self.file_bytes >= self.max_file_bytes {
+            self.rotate()?;
+        }
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.file.as_mut().unwrap().write_all(&line)?;
+        self.file_bytes += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        self.flush()?;
+        let path = self
+            .dir
+            .join(format!("{}-{:06}.jsonl", self.prefix, self.next_index));
+        self.file = Some(BufWriter::new(File::create(&path)?));
+        self.file_bytes = 0;
+        self.next_index += 1;
+        self.files.push_back(path);
+
+        if let Some(max_files) = self.max_files {
+            while self.files.len() > max_files.max(1) {
+                let oldest = self.files.pop_front().unwrap();
+                if let Err(err) = fs::remove_file(&oldest) {
+                    warn!("Failed to remove the file {:?}: {}", oldest, err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the work items of a lane to its files
+pub(crate) struct JsonLinesWorker {
+    files: RotatingFiles,
+    panic_on_db_errors: bool,
+}
+
+impl JsonLinesWorker {
+    pub fn new(
+        config: &JsonLinesConfig,
+        lane: &str,
+        panic_on_db_errors: bool,
+    ) -> Result<Self, GeyserPluginError> {
+        let files = RotatingFiles::new(
+            Path::new(&config.path),
+            lane,
+            config
+                .max_file_bytes
+                .unwrap_or(DEFAULT_JSON_LINES_MAX_FILE_BYTES),
+            config.max_files,
+        )
+        .map_err(|err| GeyserPluginError::Custom(Box::new(err)))?;
+        info!("Writing the {} work items to {:?}", lane, config.path);
+        Ok(Self {
+            files,
+            panic_on_db_errors,
+        })
+    }
+
+    fn write(&mut self, line: &JsonLine) -> Result<(), GeyserPluginError> {
+        self.files
+            .write_line(line)
+            .map_err(|err| GeyserPluginError::Custom(Box::new(err)))
+    }
+
+    /// Write the line, the errors are handled like the errors writing to Bigtable
+    fn write_or_abort(&mut self, line: &JsonLine) {
+        if let Err(err) = self.write(line) {
+            error!("Failed to write the work item to the file: ({})", err);
+            if self.panic_on_db_errors {
+                abort();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.files.flush() {
+            error!("Failed to flush the file: ({})", err);
+        }
+    }
+}
+
+impl WorkItemSink for JsonLinesWorker {
+    fn update_account(&mut self, account: DbAccountInfo, is_startup: bool) {
+        self.write_or_abort(&JsonLine::Account {
+            is_startup,
+            account: &account,
+        })
+    }
+
+    fn update_slot_status(&mut self, request: UpdateSlotRequest) {
+        self.write_or_abort(&JsonLine::Slot {
+            slot: request.slot,
+            parent: request.parent,
+            status: request.slot_status.as_str(),
+            updated_since_epoch: request.updated_since_epoch,
+        })
+    }
+
+    fn log_transaction(
+        &mut self,
+        transaction_log_info: LogTransactionRequest,
+    ) -> Result<(), GeyserPluginError> {
+        self.write(&JsonLine::Transaction {
+            transaction: &transaction_log_info.transaction_info,
+        })
+    }
+
+    fn update_block_metadata(
+        &mut self,
+        block_info: UpdateBlockMetadataRequest,
+    ) -> Result<(), GeyserPluginError> {
+        self.write(&JsonLine::BlockMetadata {
+            block_info: &block_info.block_info,
+        })
+    }
+
+    fn notify_end_of_startup(&mut self) {
+        self.write_or_abort(&JsonLine::EndOfStartup);
+        self.flush();
+    }
+
+    fn on_idle(&mut self) {
+        self.flush();
+    }
+
+    /// The lines are written as they are received, only the buffered file is flushed
+    fn drain(&mut self, receiver: &WorkReceiver, _deadline: Instant) -> usize {
+        self.flush();
+        let queued = receiver.len();
+        if queued > 0 {
+            error!(
+                "{} work items left in the queue at the shutdown deadline",
+                queued
+            );
+        }
+        queued
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, serde_json::Value};
+
+    fn read_lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotating_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = RotatingFiles::new(dir.path(), "bigtable-accounts", 16, Some(2)).unwrap();
+        for i in 0..3 {
+            files.write_line(&serde_json::json!({ "i": i })).unwrap();
+            files.write_line(&serde_json::json!({ "i": i })).unwrap();
+        }
+        files.flush().unwrap();
+
+        // Each file is started once the previous one holds two lines of 8 bytes, and the
+        // oldest is removed
+        assert!(!dir.path().join("bigtable-accounts-000000.jsonl").exists());
+        let lines = read_lines(&dir.path().join("bigtable-accounts-000002.jsonl"));
+        assert_eq!(lines, vec![serde_json::json!({ "i": 2 }); 2]);
+
+        // The numbering continues after the files already written
+        let mut files = RotatingFiles::new(dir.path(), "bigtable-accounts", 16, None).unwrap();
+        files.write_line(&JsonLine::EndOfStartup).unwrap();
+        files.flush().unwrap();
+        let lines = read_lines(&dir.path().join("bigtable-accounts-000003.jsonl"));
+        assert_eq!(lines, vec![serde_json::json!({ "type": "end_of_startup" })]);
+    }
+}
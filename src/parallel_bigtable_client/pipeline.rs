@@ -0,0 +1,313 @@
+/// The writes to Bigtable run as tasks on the shared runtime. The dispatcher threads only
+/// batch the work items and spawn a task per `MutateRows` request, waiting when the
+/// maximum count of requests are in flight so that the queue fills up and the
+/// notifications get blocked instead of the memory growing unbounded. The account and
+/// the priority writes have separate budgets of requests in flight, so that the slot
+/// writes never wait for the bulk of the account writes.
+use {
+    crate::parallel_bigtable_client::{
+        error_policy::ErrorPolicies, retry::CircuitBreaker, startup::StartupProgress,
+    },
+    log::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::*,
+    std::{
+        future::Future,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+    tokio::sync::Semaphore,
+};
+
+/// What a write task persists, used to account for its result
+#[derive(Clone, Copy, Debug)]
+pub enum WriteKind {
+    Accounts { count: usize, is_startup: bool },
+    Slots { count: usize },
+    Transactions { count: usize },
+    Blocks { count: usize },
+}
+
+/// The lane of the writes, each with its own budget of requests in flight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lane {
+    Accounts,
+    Priority,
+}
+
+impl WriteKind {
+    fn lane(&self) -> Lane {
+        match self {
+            WriteKind::Accounts { .. } => Lane::Accounts,
+            WriteKind::Slots { .. } | WriteKind::Transactions { .. } | WriteKind::Blocks { .. } => {
+                Lane::Priority
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WritePipeline {
+    in_flight: Arc<Semaphore>,
+    max_in_flight: usize,
+    priority_in_flight: Arc<Semaphore>,
+    max_priority_in_flight: usize,
+    startup_progress: Arc<StartupProgress>,
+    /// The count of account writes failed during startup
+    startup_write_failures: Arc<AtomicUsize>,
+    /// Shared by all the writes, deciding what happens to the writes which failed
+    error_policies: Arc<ErrorPolicies>,
+    /// Shared by all the writes, pausing them while Bigtable is unavailable
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+impl WritePipeline {
+    pub fn new(
+        max_in_flight: usize,
+        max_priority_in_flight: usize,
+        startup_progress: Arc<StartupProgress>,
+        startup_write_failures: Arc<AtomicUsize>,
+        error_policies: Arc<ErrorPolicies>,
+        circuit_breaker: Arc<CircuitBreaker>,
+    ) -> Self {
+        let max_in_flight = max_in_flight.max(1);
+        let max_priority_in_flight = max_priority_in_flight.max(1);
+        Self {
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            priority_in_flight: Arc::new(Semaphore::new(max_priority_in_flight)),
+            max_priority_in_flight,
+            startup_progress,
+            startup_write_failures,
+            error_policies,
+            circuit_breaker,
+        }
+    }
+
+    /// Spawn the write once fewer than the maximum count of requests of its lane are in
+    /// flight. The write resolves to the written and the raw sizes.
+    pub async fn spawn<F>(&self, kind: WriteKind, write: F)
+    where
+        F: Future<Output = Result<(usize, usize), GeyserPluginError>> + Send + 'static,
+    {
+        let (in_flight, _) = self.lane_semaphore(kind.lane());
+        let permit = in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the in flight semaphore is never closed");
+        let pipeline = self.clone();
+        tokio::spawn(async move {
+            let result = write.await;
+            // Account for the result before releasing the permit, so that the failures
+            // are known once the writes are waited for
+            pipeline.handle_result(kind, result);
+            drop(permit);
+        });
+    }
+
+    fn lane_semaphore(&self, lane: Lane) -> (&Arc<Semaphore>, usize) {
+        match lane {
+            Lane::Accounts => (&self.in_flight, self.max_in_flight),
+            Lane::Priority => (&self.priority_in_flight, self.max_priority_in_flight),
+        }
+    }
+
+    /// Wait for all the writes spawned so far to complete
+    pub async fn wait_for_writes(&self) {
+        self.wait_for_lane_writes(Lane::Accounts).await;
+        self.wait_for_lane_writes(Lane::Priority).await;
+    }
+
+    /// Wait for the writes of the lane spawned so far to complete, regardless of the writes
+    /// of the other lane
+    pub async fn wait_for_lane_writes(&self, lane: Lane) {
+        let (in_flight, max_in_flight) = self.lane_semaphore(lane);
+        let _permits = in_flight
+            .acquire_many(max_in_flight as u32)
+            .await
+            .expect("the in flight semaphore is never closed");
+    }
+
+    /// The count of the requests of the lane currently in flight
+    pub fn lane_in_flight(&self, lane: Lane) -> usize {
+        let (in_flight, max_in_flight) = self.lane_semaphore(lane);
+        max_in_flight - in_flight.available_permits()
+    }
+
+    pub fn error_policies(&self) -> Arc<ErrorPolicies> {
+        self.error_policies.clone()
+    }
+
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.circuit_breaker.clone()
+    }
+
+    /// The count of account requests currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.lane_in_flight(Lane::Accounts)
+    }
+
+    /// The count of priority requests currently in flight
+    pub fn priority_in_flight(&self) -> usize {
+        self.lane_in_flight(Lane::Priority)
+    }
+
+    fn handle_result(&self, kind: WriteKind, result: Result<(usize, usize), GeyserPluginError>) {
+        match (kind, result) {
+            (WriteKind::Accounts { count, is_startup }, Ok(sizes)) => {
+                if is_startup {
+                    self.startup_progress.record(count as u64, sizes.0 as u64);
+                }
+                Self::update_size_stats(sizes);
+            }
+            (
+                WriteKind::Slots { .. } | WriteKind::Transactions { .. } | WriteKind::Blocks { .. },
+                Ok(sizes),
+            ) => Self::update_size_stats(sizes),
+            (WriteKind::Accounts { count, is_startup }, Err(err)) => {
+                error!("Failed to update {} accounts: ({})", count, err);
+                if is_startup {
+                    self.startup_write_failures
+                        .fetch_add(count, Ordering::Relaxed);
+                }
+            }
+            (WriteKind::Slots { count }, Err(err)) => {
+                error!("Failed to update {} slots: ({})", count, err);
+            }
+            (WriteKind::Transactions { count }, Err(err)) => {
+                error!("Failed to log {} transactions: ({})", count, err);
+            }
+            (WriteKind::Blocks { count }, Err(err)) => {
+                error!("Failed to update {} blocks: ({})", count, err);
+            }
+        }
+    }
+
+    fn update_size_stats(sizes: (usize, usize)) {
+        let (written_size, raw_size) = sizes;
+        inc_new_counter_info!("geyser-bigtable-written-bytes", written_size, 1000000);
+        inc_new_counter_info!("geyser-bigtable-raw-bytes", raw_size, 1000000);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        std::{collections::HashMap, time::Duration},
+    };
+
+    #[test]
+    fn test_write_pipeline_bounds_in_flight_requests() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let startup_write_failures = Arc::new(AtomicUsize::default());
+        let pipeline = WritePipeline::new(
+            2,
+            1,
+            Arc::new(StartupProgress::new(None)),
+            startup_write_failures.clone(),
+            Arc::new(ErrorPolicies::new(HashMap::new(), false, None).unwrap()),
+            Arc::new(CircuitBreaker::new(1, Duration::from_secs(1))),
+        );
+
+        let running = Arc::new(AtomicUsize::default());
+        let max_running = Arc::new(AtomicUsize::default());
+        let completed = Arc::new(AtomicUsize::default());
+        runtime.block_on(async {
+            for i in 0..10 {
+                let (running, max_running, completed) =
+                    (running.clone(), max_running.clone(), completed.clone());
+                let kind = WriteKind::Accounts {
+                    count: 3,
+                    is_startup: true,
+                };
+                pipeline
+                    .spawn(kind, async move {
+                        let count = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(count, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        completed.fetch_add(1, Ordering::SeqCst);
+                        if i == 0 {
+                            return Err(GeyserPluginError::AccountsUpdateError {
+                                msg: "failed".to_string(),
+                            });
+                        }
+                        Ok((0, 0))
+                    })
+                    .await;
+            }
+            pipeline.wait_for_writes().await;
+        });
+
+        assert_eq!(completed.load(Ordering::SeqCst), 10);
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+        assert_eq!(pipeline.in_flight(), 0);
+        assert_eq!(startup_write_failures.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_priority_writes_not_blocked_by_account_writes() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let pipeline = WritePipeline::new(
+            1,
+            1,
+            Arc::new(StartupProgress::new(None)),
+            Arc::new(AtomicUsize::default()),
+            Arc::new(ErrorPolicies::new(HashMap::new(), false, None).unwrap()),
+            Arc::new(CircuitBreaker::new(1, Duration::from_secs(1))),
+        );
+
+        runtime.block_on(async {
+            // The account lane is saturated by a write which never completes in this test
+            let (release, released) = tokio::sync::oneshot::channel::<()>();
+            let kind = WriteKind::Accounts {
+                count: 1,
+                is_startup: true,
+            };
+            pipeline
+                .spawn(kind, async move {
+                    let _ = released.await;
+                    Ok((0, 0))
+                })
+                .await;
+            assert_eq!(pipeline.lane_in_flight(Lane::Accounts), 1);
+
+            let written = Arc::new(AtomicUsize::default());
+            for kind in [
+                WriteKind::Slots { count: 1 },
+                WriteKind::Transactions { count: 1 },
+                WriteKind::Blocks { count: 1 },
+            ] {
+                let written = written.clone();
+                let spawn = pipeline.spawn(kind, async move {
+                    written.fetch_add(1, Ordering::SeqCst);
+                    Ok((0, 0))
+                });
+                tokio::time::timeout(Duration::from_secs(5), spawn)
+                    .await
+                    .expect("the priority write is spawned");
+            }
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                pipeline.wait_for_lane_writes(Lane::Priority),
+            )
+            .await
+            .expect("the priority writes complete");
+            assert_eq!(written.load(Ordering::SeqCst), 3);
+
+            // The account lane still waits for its own write
+            assert!(tokio::time::timeout(
+                Duration::from_millis(50),
+                pipeline.wait_for_lane_writes(Lane::Accounts)
+            )
+            .await
+            .is_err());
+            release.send(()).unwrap();
+            pipeline.wait_for_writes().await;
+        });
+    }
+}
@@ -0,0 +1,438 @@
+/// Retries of the writes to Bigtable. A write failing with a transient error is retried
+/// with an exponential backoff with jitter until the maximum elapsed time, the other
+/// errors fail it right away. Once several writes in a row failed, Bigtable is considered
+/// unavailable and the circuit breaker opens: the dispatchers stop taking work items off
+/// the queue and the writes in flight wait, so the notifications buffer in the queue
+/// instead of every batch failing. After a while a single write is let through to probe
+/// Bigtable, the others waiting until it succeeds and closes the circuit breaker.
+use {
+    backoff::{backoff::Backoff, ExponentialBackoff, ExponentialBackoffBuilder},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_metrics::*,
+    std::{
+        future::Future,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    tonic::Code,
+};
+
+const DEFAULT_INITIAL_INTERVAL_MS: u64 = 100;
+const DEFAULT_MAX_INTERVAL_MS: u64 = 10_000;
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_RANDOMIZATION_FACTOR: f64 = 0.5;
+const DEFAULT_MAX_ELAPSED_MS: u64 = 60_000;
+const DEFAULT_CIRCUIT_BREAKER_THRESHOLD: usize = 5;
+const DEFAULT_CIRCUIT_BREAKER_OPEN_MS: u64 = 30_000;
+const DEFAULT_RECONNECT_THRESHOLD: usize = 3;
+/// How often the writes waiting for a probe check the circuit breaker
+const MIN_CIRCUIT_BREAKER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The retry settings of the writes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// The delay before the first retry
+    pub initial_interval_ms: Option<u64>,
+
+    /// The maximum delay between two retries
+    pub max_interval_ms: Option<u64>,
+
+    /// The factor the delay grows by after each retry
+    pub multiplier: Option<f64>,
+
+    /// The jitter, the delay is randomized within this fraction of it
+    pub randomization_factor: Option<f64>,
+
+    /// The time after which a write is no longer retried
+    pub max_elapsed_ms: Option<u64>,
+
+    /// The count of writes failing in a row opening the circuit breaker
+    pub circuit_breaker_threshold: Option<usize>,
+
+    /// How long the circuit breaker stays open before a write probes Bigtable again
+    pub circuit_breaker_open_ms: Option<u64>,
+
+    /// The count of requests failing in a row with a connection error after which the
+    /// connection is rebuilt
+    pub reconnect_threshold: Option<usize>,
+}
+
+impl RetryConfig {
+    fn backoff(&self) -> ExponentialBackoff {
+        self.backoff_builder()
+            .with_max_elapsed_time(Some(Duration::from_millis(
+                self.max_elapsed_ms.unwrap_or(DEFAULT_MAX_ELAPSED_MS),
+            )))
+            .build()
+    }
+
+    /// The backoff of the attempts to connect, which go on until connected
+    pub fn connect_backoff(&self) -> ExponentialBackoff {
+        self.backoff_builder().with_max_elapsed_time(None).build()
+    }
+
+    fn backoff_builder(&self) -> ExponentialBackoffBuilder {
+        let mut builder = ExponentialBackoffBuilder::new();
+        builder
+            .with_initial_interval(Duration::from_millis(
+                self.initial_interval_ms
+                    .unwrap_or(DEFAULT_INITIAL_INTERVAL_MS),
+            ))
+            .with_max_interval(Duration::from_millis(
+                self.max_interval_ms.unwrap_or(DEFAULT_MAX_INTERVAL_MS),
+            ))
+            .with_multiplier(self.multiplier.unwrap_or(DEFAULT_MULTIPLIER))
+            .with_randomization_factor(
+                self.randomization_factor
+                    .unwrap_or(DEFAULT_RANDOMIZATION_FACTOR),
+            );
+        builder
+    }
+
+    pub fn reconnect_threshold(&self) -> usize {
+        self.reconnect_threshold
+            .unwrap_or(DEFAULT_RECONNECT_THRESHOLD)
+            .max(1)
+    }
+
+    pub fn circuit_breaker(&self) -> CircuitBreaker {
+        CircuitBreaker::new(
+            self.circuit_breaker_threshold
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_THRESHOLD),
+            Duration::from_millis(
+                self.circuit_breaker_open_ms
+                    .unwrap_or(DEFAULT_CIRCUIT_BREAKER_OPEN_MS),
+            ),
+        )
+    }
+}
+
+/// If the error is transient and the write can be retried. The errors such as an
+/// invalid argument or a missing table would fail again.
+pub fn is_retryable(err: &BigtableError) -> bool {
+    match err {
+        BigtableError::Rpc(status) => matches!(
+            status.code(),
+            Code::Unavailable
+                | Code::DeadlineExceeded
+                | Code::ResourceExhausted
+                | Code::Aborted
+                | Code::Internal
+                | Code::Unknown
+                | Code::Cancelled
+                // The connection is rebuilt with fresh credentials
+                | Code::Unauthenticated
+        ),
+        BigtableError::AccessToken(_)
+        | BigtableError::Io(_)
+        | BigtableError::Transport(_)
+        | BigtableError::RowWriteFailed
+        | BigtableError::Timeout => true,
+        _ => false,
+    }
+}
+
+/// If the error means the connection itself is broken, rather than the request failing
+pub fn is_connection_error(err: &BigtableError) -> bool {
+    match err {
+        BigtableError::Rpc(status) => {
+            matches!(status.code(), Code::Unavailable | Code::Unauthenticated)
+        }
+        BigtableError::AccessToken(_) | BigtableError::Transport(_) => true,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BreakerState {
+    Closed,
+    /// The writes wait until the open duration elapsed
+    Open(Instant),
+    /// A single write probes Bigtable since the given instant, the others wait for it
+    HalfOpen(Instant),
+}
+
+struct CircuitBreakerState {
+    consecutive_failures: usize,
+    state: BreakerState,
+}
+
+/// How a write was let through by the circuit breaker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Admission {
+    /// The write probes Bigtable, its result closes or opens the circuit breaker
+    pub is_probe: bool,
+    /// The write waited for the circuit breaker
+    pub waited: bool,
+}
+
+/// Shared by all the writes, opens once `threshold` writes failed in a row. Once the open
+/// duration elapsed, it is half open: a single write is let through to probe Bigtable,
+/// closing the circuit breaker if it succeeds and opening it again if it fails.
+pub struct CircuitBreaker {
+    state: Mutex<CircuitBreakerState>,
+    threshold: usize,
+    open_duration: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize, open_duration: Duration) -> Self {
+        Self {
+            state: Mutex::new(CircuitBreakerState {
+                consecutive_failures: 0,
+                state: BreakerState::Closed,
+            }),
+            threshold: threshold.max(1),
+            open_duration,
+        }
+    }
+
+    /// How long the writes remain paused, `None` if a write can go through. While a probe
+    /// is in flight, this is the time left before another write may probe.
+    pub fn open_for(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        match state.state {
+            BreakerState::Closed => None,
+            BreakerState::Open(since) | BreakerState::HalfOpen(since) => self
+                .open_duration
+                .checked_sub(since.elapsed())
+                .filter(|remaining| !remaining.is_zero()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open_for().is_some()
+    }
+
+    /// Let the write through if the circuit breaker is closed, or as the probe if it is
+    /// half open. A probe taking longer than the open duration is presumed lost and
+    /// another write takes its place.
+    fn try_admit(&self) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        match state.state {
+            BreakerState::Closed => Some(false),
+            BreakerState::Open(since) | BreakerState::HalfOpen(since)
+                if since.elapsed() >= self.open_duration =>
+            {
+                debug!("Probing Bigtable with a write");
+                state.state = BreakerState::HalfOpen(Instant::now());
+                Some(true)
+            }
+            BreakerState::Open(_) | BreakerState::HalfOpen(_) => None,
+        }
+    }
+
+    /// Wait until the circuit breaker lets the write through
+    pub async fn wait_until_admitted(&self) -> Admission {
+        let mut waited = false;
+        loop {
+            if let Some(is_probe) = self.try_admit() {
+                return Admission { is_probe, waited };
+            }
+            waited = true;
+            let remaining = self.open_for().unwrap_or_default();
+            tokio::time::sleep(remaining.max(MIN_CIRCUIT_BREAKER_POLL_INTERVAL)).await;
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.state != BreakerState::Closed {
+            info!("Bigtable is available again, closing the circuit breaker");
+            state.state = BreakerState::Closed;
+        }
+        state.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        match state.state {
+            // The probe failed
+            BreakerState::HalfOpen(_) => {
+                debug!(
+                    "The probe failed, pausing the writes for {:?}",
+                    self.open_duration
+                );
+                state.state = BreakerState::Open(Instant::now());
+            }
+            BreakerState::Closed if state.consecutive_failures >= self.threshold => {
+                warn!(
+                    "Bigtable is unavailable after {} failed writes, pausing the writes for {:?}",
+                    state.consecutive_failures, self.open_duration
+                );
+                state.state = BreakerState::Open(Instant::now());
+                inc_new_counter_info!("geyser-bigtable-circuit-breaker-opened", 1);
+            }
+            BreakerState::Closed | BreakerState::Open(_) => {}
+        }
+    }
+}
+
+pub struct RetryPolicy {
+    config: RetryConfig,
+}
+
+impl RetryPolicy {
+    pub fn new(config: RetryConfig) -> Self {
+        Self { config }
+    }
+
+    /// Run the operation until it succeeds, fails with an error which cannot be retried
+    /// or the maximum elapsed time is reached. The time spent waiting for the circuit
+    /// breaker does not count towards the maximum elapsed time.
+    pub async fn retry<T, F, Fut>(
+        &self,
+        circuit_breaker: &CircuitBreaker,
+        mut operation: F,
+    ) -> Result<T, BigtableError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BigtableError>>,
+    {
+        let mut backoff = self.config.backoff();
+        loop {
+            let admission = circuit_breaker.wait_until_admitted().await;
+            if admission.waited || admission.is_probe {
+                backoff.reset();
+            }
+            let err = match operation().await {
+                Ok(result) => {
+                    circuit_breaker.record_success();
+                    return Ok(result);
+                }
+                Err(err) if is_retryable(&err) => err,
+                Err(err) => {
+                    // Bigtable answered the probe, the write itself is at fault
+                    if admission.is_probe {
+                        circuit_breaker.record_success();
+                    }
+                    return Err(err);
+                }
+            };
+            circuit_breaker.record_failure();
+            if circuit_breaker.is_open() {
+                continue;
+            }
+            match backoff.next_backoff() {
+                Some(delay) => {
+                    debug!("Retrying the write in {:?} after error: {}", delay, err);
+                    inc_new_counter_debug!("geyser-bigtable-write-retries", 1);
+                    tokio::time::sleep(delay).await;
+                }
+                None => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        std::sync::atomic::{AtomicUsize, Ordering},
+        tonic::Status,
+    };
+
+    fn fast_retries() -> RetryConfig {
+        RetryConfig {
+            initial_interval_ms: Some(1),
+            max_interval_ms: Some(2),
+            max_elapsed_ms: Some(1000),
+            circuit_breaker_threshold: Some(3),
+            circuit_breaker_open_ms: Some(20),
+            ..RetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&BigtableError::Rpc(Status::unavailable(""))));
+        assert!(is_retryable(&BigtableError::Rpc(
+            Status::deadline_exceeded("")
+        )));
+        assert!(is_retryable(&BigtableError::Timeout));
+        assert!(!is_retryable(&BigtableError::Rpc(
+            Status::invalid_argument("")
+        )));
+        assert!(!is_retryable(&BigtableError::Rpc(Status::not_found(""))));
+        assert!(!is_retryable(&BigtableError::RowNotFound));
+
+        assert!(is_connection_error(&BigtableError::Rpc(
+            Status::unauthenticated("")
+        )));
+        assert!(!is_connection_error(&BigtableError::Rpc(
+            Status::deadline_exceeded("")
+        )));
+    }
+
+    #[test]
+    fn test_retry() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = fast_retries();
+        let policy = RetryPolicy::new(config.clone());
+        let circuit_breaker = config.circuit_breaker();
+        let attempts = AtomicUsize::default();
+
+        // The transient errors are retried
+        let result = runtime.block_on(policy.retry(&circuit_breaker, || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(BigtableError::Rpc(Status::unavailable("")))
+            } else {
+                Ok(1)
+            }
+        }));
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        // The other errors are not
+        let result: Result<(), _> = runtime.block_on(policy.retry(&circuit_breaker, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(BigtableError::Rpc(Status::invalid_argument("")))
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(!circuit_breaker.is_open());
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let circuit_breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        circuit_breaker.record_failure();
+        assert!(!circuit_breaker.is_open());
+        circuit_breaker.record_failure();
+        assert!(circuit_breaker.is_open());
+
+        assert_eq!(circuit_breaker.try_admit(), None);
+
+        // Let a single write through once the open duration elapsed, a failure opens it
+        // again
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!circuit_breaker.is_open());
+        assert_eq!(circuit_breaker.try_admit(), Some(true));
+        assert!(circuit_breaker.is_open());
+        assert_eq!(circuit_breaker.try_admit(), None);
+        circuit_breaker.record_failure();
+        assert!(circuit_breaker.is_open());
+        assert_eq!(circuit_breaker.try_admit(), None);
+
+        // The writes waiting are let through once the probe succeeded
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(circuit_breaker.try_admit(), Some(true));
+        assert_eq!(circuit_breaker.try_admit(), None);
+        circuit_breaker.record_success();
+        assert!(!circuit_breaker.is_open());
+        assert_eq!(circuit_breaker.try_admit(), Some(false));
+        circuit_breaker.record_failure();
+        assert!(!circuit_breaker.is_open());
+
+        // A probe lost is replaced once the open duration elapsed
+        circuit_breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(circuit_breaker.try_admit(), Some(true));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(circuit_breaker.try_admit(), Some(true));
+    }
+}
@@ -0,0 +1,538 @@
+/// Writes the cells prepared by the plugin to Bigtable as they are. The protobuf cells API
+/// of the connection compresses each cell itself, the cells already compressed with the
+/// codec of their table, or with a zstd dictionary, are then written through MutateRows
+/// directly. The cells overwrite the previous value of the row, like the cells of the
+/// connection, in the "x" family and the "proto" or "bin" column the readers look them up in.
+use {
+    crate::{
+        compression::CompressedCell,
+        geyser_plugin_bigtable::{GeyserPluginBigtableConfig, GeyserPluginBigtableError},
+        parallel_bigtable_client::{
+            block_metadata::BLOCK_TABLE,
+            table_routing::{resolve_table_routes, TableEndpoint, TableRoutes},
+            transaction::{TRANSACTION_BY_SIGNATURE_TABLE, TRANSACTION_TABLE},
+        },
+    },
+    log::*,
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        bigtable::{BigTableConnection as Client, Error as BigtableError, RowKey},
+        root_ca_certificate, CredentialType,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{collections::HashMap, str::FromStr, time::Duration},
+    tonic::{
+        codec::ProstCodec,
+        codegen::http::{self, uri::PathAndQuery},
+        transport::{Channel, ClientTlsConfig},
+        Request,
+    },
+};
+
+pub const CELL_FAMILY: &str = "x";
+pub const PROTOBUF_QUALIFIER: &str = "proto";
+pub const BINCODE_QUALIFIER: &str = "bin";
+
+const MUTATE_ROWS_PATH: &str = "/google.bigtable.v2.Bigtable/MutateRows";
+
+/// The messages of `google.bigtable.v2` used to write the rows, wire compatible with
+/// `bigtable.proto`
+pub mod v2 {
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct MutateRowsRequest {
+        #[prost(string, tag = "1")]
+        pub table_name: String,
+        #[prost(string, tag = "3")]
+        pub app_profile_id: String,
+        #[prost(message, repeated, tag = "2")]
+        pub entries: Vec<Entry>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Entry {
+        #[prost(bytes = "vec", tag = "1")]
+        pub row_key: Vec<u8>,
+        #[prost(message, repeated, tag = "2")]
+        pub mutations: Vec<Mutation>,
+    }
+
+    /// The `set_cell` and `delete_from_row` members of the `mutation` oneof, the mutations
+    /// written. Only one of them is set.
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Mutation {
+        #[prost(message, optional, tag = "1")]
+        pub set_cell: Option<SetCell>,
+        #[prost(message, optional, tag = "4")]
+        pub delete_from_row: Option<DeleteFromRow>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct SetCell {
+        #[prost(string, tag = "1")]
+        pub family_name: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub column_qualifier: Vec<u8>,
+        /// 0 overwrites the value of the cell, -1 lets the server assign the timestamp
+        #[prost(int64, tag = "3")]
+        pub timestamp_micros: i64,
+        #[prost(bytes = "vec", tag = "4")]
+        pub value: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct DeleteFromRow {}
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct MutateRowsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub entries: Vec<ResponseEntry>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ResponseEntry {
+        #[prost(int64, tag = "1")]
+        pub index: i64,
+        #[prost(message, optional, tag = "2")]
+        pub status: Option<Status>,
+    }
+
+    /// `google.rpc.Status`
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Status {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(string, tag = "2")]
+        pub message: String,
+    }
+}
+
+/// The column of the cells of the table, named like the columns written by the connection.
+/// The transactions, their signature index and the blocks are bincode encoded, the other
+/// tables hold protobuf messages.
+pub fn cell_qualifier(table: &str) -> &'static str {
+    match table {
+        TRANSACTION_TABLE | TRANSACTION_BY_SIGNATURE_TABLE | BLOCK_TABLE => BINCODE_QUALIFIER,
+        _ => PROTOBUF_QUALIFIER,
+    }
+}
+
+/// The MutateRows entries overwriting the cell of each row in the column `qualifier` with
+/// the prepared cell
+pub fn mutate_rows_entries(qualifier: &str, cells: &[(RowKey, CompressedCell)]) -> Vec<v2::Entry> {
+    cells
+        .iter()
+        .map(|(row_key, cell)| v2::Entry {
+            row_key: row_key.clone().into_bytes(),
+            mutations: vec![v2::Mutation {
+                set_cell: Some(v2::SetCell {
+                    family_name: CELL_FAMILY.to_string(),
+                    column_qualifier: qualifier.as_bytes().to_vec(),
+                    // overwriting the existing cell
+                    // https://cloud.google.com/bigtable/docs/gc-latest-value
+                    timestamp_micros: 0,
+                    value: cell.0.clone(),
+                }),
+                delete_from_row: None,
+            }],
+        })
+        .collect()
+}
+
+/// The connection to an instance, with the app profile the rows are written with
+#[derive(Clone)]
+pub struct RowWriter {
+    access_token: Option<AccessToken>,
+    channel: Channel,
+    table_prefix: String,
+    app_profile_id: String,
+}
+
+impl RowWriter {
+    /// Connect like the connection of `solana-bigtable-connection`, to the emulator if
+    /// `BIGTABLE_EMULATOR_HOST` is set, and through the proxy of `BIGTABLE_PROXY` if set
+    pub async fn new(
+        instance_name: &str,
+        app_profile_id: &str,
+        timeout: Option<Duration>,
+        credential_type: CredentialType,
+    ) -> Result<Self, BigtableError> {
+        if let Ok(endpoint) = std::env::var("BIGTABLE_EMULATOR_HOST") {
+            return Ok(Self {
+                access_token: None,
+                channel: Channel::from_shared(format!("http://{}", endpoint))
+                    .map_err(|err| BigtableError::InvalidUri(endpoint, err.to_string()))?
+                    .connect_lazy(),
+                table_prefix: format!("projects/emulator/instances/{}/tables/", instance_name),
+                app_profile_id: app_profile_id.to_string(),
+            });
+        }
+
+        let access_token = AccessToken::new(Scope::BigTableData, credential_type)
+            .await
+            .map_err(BigtableError::AccessToken)?;
+        let table_prefix = format!(
+            "projects/{}/instances/{}/tables/",
+            access_token.project(),
+            instance_name
+        );
+
+        let endpoint = Channel::from_static("https://bigtable.googleapis.com").tls_config(
+            ClientTlsConfig::new()
+                .ca_certificate(root_ca_certificate::load().map_err(BigtableError::Certificate)?)
+                .domain_name("bigtable.googleapis.com"),
+        )?;
+        let endpoint = match timeout {
+            Some(timeout) => endpoint.timeout(timeout),
+            None => endpoint,
+        };
+
+        let mut http = hyper::client::HttpConnector::new();
+        http.enforce_http(false);
+        let channel = match std::env::var("BIGTABLE_PROXY") {
+            Ok(proxy_uri) => {
+                let proxy = hyper_proxy::Proxy::new(
+                    hyper_proxy::Intercept::All,
+                    proxy_uri
+                        .parse::<http::Uri>()
+                        .map_err(|err| BigtableError::InvalidUri(proxy_uri, err.to_string()))?,
+                );
+                let mut proxy_connector = hyper_proxy::ProxyConnector::from_proxy(http, proxy)?;
+                // tonic handles TLS as a separate layer
+                proxy_connector.set_tls(None);
+                endpoint.connect_with_connector_lazy(proxy_connector)
+            }
+            Err(_) => endpoint.connect_with_connector_lazy(http),
+        };
+
+        Ok(Self {
+            access_token: Some(access_token),
+            channel,
+            table_prefix,
+            app_profile_id: app_profile_id.to_string(),
+        })
+    }
+
+    /// Overwrite the cell of the rows in the column `qualifier` with the prepared cells,
+    /// returning the size written
+    pub async fn put_cells(
+        &self,
+        table_name: &str,
+        qualifier: &str,
+        cells: &[(RowKey, CompressedCell)],
+    ) -> Result<usize, BigtableError> {
+        self.mutate_rows(
+            table_name,
+            mutate_rows_entries(qualifier, cells),
+            BigtableError::RowWriteFailed,
+        )
+        .await?;
+        Ok(cells.iter().map(|(_, cell)| cell.0.len()).sum())
+    }
+
+    /// Delete the rows entirely
+    pub async fn delete_rows(
+        &self,
+        table_name: &str,
+        row_keys: &[RowKey],
+    ) -> Result<(), BigtableError> {
+        let entries = row_keys
+            .iter()
+            .map(|row_key| v2::Entry {
+                row_key: row_key.clone().into_bytes(),
+                mutations: vec![v2::Mutation {
+                    set_cell: None,
+                    delete_from_row: Some(v2::DeleteFromRow::default()),
+                }],
+            })
+            .collect();
+        self.mutate_rows(table_name, entries, BigtableError::RowDeleteFailed)
+            .await
+    }
+
+    /// Apply the mutations of the entries, failing with `entry_error` if any of the entries
+    /// failed
+    async fn mutate_rows(
+        &self,
+        table_name: &str,
+        entries: Vec<v2::Entry>,
+        entry_error: BigtableError,
+    ) -> Result<(), BigtableError> {
+        let mut request = Request::new(v2::MutateRowsRequest {
+            table_name: format!("{}{}", self.table_prefix, table_name),
+            app_profile_id: self.app_profile_id.clone(),
+            entries,
+        });
+        if let Some(access_token) = &self.access_token {
+            access_token.refresh().await;
+            match FromStr::from_str(&access_token.get()) {
+                Ok(authorization_header) => {
+                    request
+                        .metadata_mut()
+                        .insert("authorization", authorization_header);
+                }
+                Err(err) => warn!("Failed to set authorization header: {}", err),
+            }
+        }
+
+        let mut grpc = tonic::client::Grpc::new(self.channel.clone());
+        grpc.ready().await?;
+        let mut response = grpc
+            .server_streaming(
+                request,
+                PathAndQuery::from_static(MUTATE_ROWS_PATH),
+                ProstCodec::<v2::MutateRowsRequest, v2::MutateRowsResponse>::default(),
+            )
+            .await?
+            .into_inner();
+        while let Some(response) = response.message().await? {
+            for entry in response.entries {
+                if let Some(status) = entry.status.filter(|status| status.code != 0) {
+                    warn!(
+                        "Error mutating the row {} of {}: {} {}",
+                        entry.index, table_name, status.code, status.message
+                    );
+                    return Err(entry_error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The writers of the tables, one for each of the endpoints the tables are routed to
+#[derive(Clone)]
+pub struct TableWriters {
+    /// The names of the tables in their instance
+    routes: TableRoutes,
+    default: RowWriter,
+    writers: HashMap<String, RowWriter>,
+}
+
+impl TableWriters {
+    pub async fn connect(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
+        let routes = TableRoutes::connect(config).await?;
+        let default_endpoint = TableEndpoint::default_for(config);
+        let default = Self::connect_to_endpoint(config, &default_endpoint).await?;
+
+        let mut endpoints = HashMap::new();
+        endpoints.insert(default_endpoint, default.clone());
+        let mut writers = HashMap::new();
+        for (table, (endpoint, _)) in resolve_table_routes(config) {
+            if !endpoints.contains_key(&endpoint) {
+                let writer = Self::connect_to_endpoint(config, &endpoint).await?;
+                endpoints.insert(endpoint.clone(), writer);
+            }
+            writers.insert(table, endpoints[&endpoint].clone());
+        }
+        Ok(Self {
+            routes,
+            default,
+            writers,
+        })
+    }
+
+    async fn connect_to_endpoint(
+        config: &GeyserPluginBigtableConfig,
+        endpoint: &TableEndpoint,
+    ) -> Result<RowWriter, GeyserPluginError> {
+        RowWriter::new(
+            &endpoint.instance,
+            &endpoint.app_profile_id,
+            config.timeout,
+            CredentialType::Filepath(config.credential_path.clone()),
+        )
+        .await
+        .map_err(|err| {
+            GeyserPluginError::Custom(Box::new(
+                GeyserPluginBigtableError::DataStoreConnectionError {
+                    msg: format!(
+                        "Error in connecting to Bigtable \"credential_path\": {:?}, \"instance\": {}, : {}",
+                        config.credential_path, endpoint.instance, err
+                    ),
+                },
+            ))
+        })
+    }
+
+    /// The writer of the table and the name of the table in its instance
+    pub fn route(&self, table: &str) -> (RowWriter, String) {
+        let writer = self.writers.get(table).unwrap_or(&self.default);
+        (writer.clone(), self.routes.table(table))
+    }
+
+    /// The connection to the instance of the table and the name of the table in it, for
+    /// the reads of the write path
+    pub fn client(&self, table: &str) -> (Client, String) {
+        (self.routes.client(table), self.routes.table(table))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            bigtable_reader::{decode_bincode_cell, decode_protobuf_cell, BigtableReaderError},
+            compression::{decompress, CompressionConfig},
+            dictionary::ZstdDictionaries,
+            models::accounts,
+            parallel_bigtable_client::{
+                block_metadata::{block_row_key, DbBlockInfo},
+                compress_protobuf_cells,
+            },
+            schema::{encode_cell, schema_version, SchemaError, SchemaVersion, SCHEMA_VERSION_TAG},
+        },
+        prost::{
+            encoding::{encode_key, encode_varint, WireType},
+            Message,
+        },
+        solana_bigtable_connection::bigtable::RowData,
+    };
+
+    /// The row data as the readers get it back for the entries, the cells set by each entry
+    fn entry_row_data(entry: &v2::Entry) -> (RowKey, RowData) {
+        let row_data = entry
+            .mutations
+            .iter()
+            .filter_map(|mutation| mutation.set_cell.as_ref())
+            .map(|set_cell| {
+                (
+                    String::from_utf8_lossy(&set_cell.column_qualifier).to_string(),
+                    set_cell.value.clone(),
+                )
+            })
+            .collect();
+        (
+            String::from_utf8_lossy(&entry.row_key).to_string(),
+            row_data,
+        )
+    }
+
+    #[test]
+    fn test_written_cells_read_back() {
+        let account = accounts::Account {
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            lamports: 42,
+            slot: 7,
+            data: vec![3; 1024],
+            write_version: 9,
+            ..accounts::Account::default()
+        };
+        for codec in ["none", "gzip", "bzip2", "zstd"] {
+            let compression: CompressionConfig =
+                serde_json::from_value(serde_json::json!({ "codec": codec })).unwrap();
+            let cell = CompressedCell(compression.compress(&encode_cell(&account)).unwrap());
+            let entries =
+                mutate_rows_entries(PROTOBUF_QUALIFIER, &[("key".to_string(), cell.clone())]);
+
+            assert_eq!(entries.len(), 1);
+            let set_cell = entries[0].mutations[0].set_cell.as_ref().unwrap();
+            assert_eq!(set_cell.family_name, CELL_FAMILY);
+            assert_eq!(set_cell.timestamp_micros, 0);
+            // The prepared cell is written as is
+            assert_eq!(set_cell.value, cell.0);
+
+            let (key, row_data) = entry_row_data(&entries[0]);
+            let read: accounts::Account =
+                decode_protobuf_cell(&key, &row_data, &ZstdDictionaries::default()).unwrap();
+            assert_eq!(read, account);
+        }
+    }
+
+    #[test]
+    fn test_written_bincode_cells_read_back() {
+        let block = DbBlockInfo {
+            slot: 300,
+            blockhash: "blockhash".to_string(),
+            rewards: Vec::default(),
+            block_time: Some(1_660_000_000),
+            block_height: Some(280),
+        };
+        let compression: CompressionConfig =
+            serde_json::from_value(serde_json::json!({ "codec": "zstd" })).unwrap();
+        let cell = CompressedCell(
+            compression
+                .compress(&bincode::serialize(&block).unwrap())
+                .unwrap(),
+        );
+        let entries =
+            mutate_rows_entries(cell_qualifier(BLOCK_TABLE), &[(block_row_key(300), cell)]);
+
+        // The cell is found in the column the readers look the bincode cells up in
+        let (key, row_data) = entry_row_data(&entries[0]);
+        let read: DbBlockInfo =
+            decode_bincode_cell(&key, &row_data, &ZstdDictionaries::default()).unwrap();
+        assert_eq!(read.blockhash, block.blockhash);
+        assert_eq!(read.block_height, block.block_height);
+        assert!(decode_protobuf_cell::<accounts::Account>(
+            &key,
+            &row_data,
+            &ZstdDictionaries::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_schema_versions_read_back() {
+        let account = accounts::Account {
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            lamports: 42,
+            slot: 7,
+            data: vec![3; 64],
+            write_version: 9,
+            ..accounts::Account::default()
+        };
+        let compression: CompressionConfig =
+            serde_json::from_value(serde_json::json!({ "codec": "zstd" })).unwrap();
+        let dictionaries = ZstdDictionaries::default();
+
+        // The cells written by the plugin hold the current version
+        let (cells, _) =
+            compress_protobuf_cells(&[("current".to_string(), account.clone())], &compression)
+                .unwrap();
+        let entries = mutate_rows_entries(PROTOBUF_QUALIFIER, &cells);
+        let (key, row_data) = entry_row_data(&entries[0]);
+        assert_eq!(
+            schema_version(&decompress(&row_data[0].1).unwrap()).unwrap(),
+            SchemaVersion::CURRENT
+        );
+        let read: accounts::Account = decode_protobuf_cell(&key, &row_data, &dictionaries).unwrap();
+        assert_eq!(read, account);
+
+        // The cells written before the versioning
+        let unversioned = CompressedCell(compression.compress(&account.encode_to_vec()).unwrap());
+        let entries = mutate_rows_entries(
+            PROTOBUF_QUALIFIER,
+            &[("unversioned".to_string(), unversioned)],
+        );
+        let (key, row_data) = entry_row_data(&entries[0]);
+        assert_eq!(
+            schema_version(&decompress(&row_data[0].1).unwrap()).unwrap(),
+            SchemaVersion::Unversioned
+        );
+        let read: accounts::Account = decode_protobuf_cell(&key, &row_data, &dictionaries).unwrap();
+        assert_eq!(read, account);
+
+        // A version the reader does not know of is reported rather than misread
+        let mut data = account.encode_to_vec();
+        encode_key(SCHEMA_VERSION_TAG, WireType::Varint, &mut data);
+        encode_varint(200, &mut data);
+        let unsupported = CompressedCell(compression.compress(&data).unwrap());
+        let entries = mutate_rows_entries(
+            PROTOBUF_QUALIFIER,
+            &[("unsupported".to_string(), unsupported)],
+        );
+        let (key, row_data) = entry_row_data(&entries[0]);
+        assert!(matches!(
+            decode_protobuf_cell::<accounts::Account>(&key, &row_data, &dictionaries),
+            Err(BigtableReaderError::SchemaError {
+                err: SchemaError::UnsupportedVersion(200),
+                ..
+            })
+        ));
+    }
+}
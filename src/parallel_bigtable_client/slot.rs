@@ -1,10 +1,22 @@
 use {
-    crate::parallel_bigtable_client::BufferedBigtableClient, log::*, prost::Message,
-    solana_bigtable_geyser_models::models::slots,
+    crate::{
+        models::slots,
+        parallel_bigtable_client::{pipeline::WriteKind, BufferedBigtableClient},
+    },
+    log::*,
+    prost::Message,
     solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
-    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus, std::time::Duration,
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus,
+    std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    },
+    tokio::sync::oneshot,
 };
 
+/// The table storing the status of the slots
+pub const SLOT_TABLE: &str = "slot";
+
 pub struct UpdateSlotRequest {
     pub slot: u64,
     pub parent: Option<u64>,
@@ -12,36 +24,168 @@ pub struct UpdateSlotRequest {
     pub updated_since_epoch: Duration,
 }
 
-impl BufferedBigtableClient {
-    /// Update or insert a single account
-    pub async fn update_slot(
-        &mut self,
-        request: UpdateSlotRequest,
-    ) -> Result<(usize, usize), GeyserPluginError> {
-        let slot_cells = vec![(
-            request.slot.to_string(),
+/// The slot updates buffered until the batch is written. Only the latest status of a
+/// slot is kept, so a slot going through several statuses within a batch is written once.
+pub struct PendingSlots {
+    slots: BTreeMap<u64, slots::Slot>,
+    /// When the oldest of the updates buffered was received
+    since: Option<Instant>,
+    batch_size: usize,
+    batch_interval: Duration,
+}
+
+impl PendingSlots {
+    pub fn new(batch_size: usize, batch_interval: Duration) -> Self {
+        Self {
+            slots: BTreeMap::default(),
+            since: None,
+            batch_size: batch_size.max(1),
+            batch_interval,
+        }
+    }
+
+    pub fn insert(&mut self, request: UpdateSlotRequest) {
+        let parent = self.slots.get(&request.slot).and_then(|slot| slot.parent);
+        self.slots.insert(
+            request.slot,
             slots::Slot {
                 slot: request.slot,
-                parent: request.parent,
+                // The parent is not known for all the statuses, keep the one notified earlier
+                parent: request.parent.or(parent),
                 status: request.slot_status.as_str().to_string(),
                 updated_on: Some(slots::UnixTimestamp {
                     timestamp: request.updated_since_epoch.as_millis() as i64,
                 }),
             },
-        )];
+        );
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    /// If the batch is to be written, either full or buffered for longer than the interval
+    pub fn is_due(&self) -> bool {
+        self.slots.len() >= self.batch_size
+            || self
+                .since
+                .map_or(false, |since| since.elapsed() >= self.batch_interval)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<(String, slots::Slot)> {
+        self.since = None;
+        std::mem::take(&mut self.slots)
+            .into_iter()
+            .map(|(slot, cell)| (slot.to_string(), cell))
+            .collect()
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Buffer the status of a slot, writing the batch once it is due
+    pub async fn update_slot(&mut self, request: UpdateSlotRequest) {
+        self.pending_slot_updates.insert(request);
+        self.flush_due_slot_updates().await
+    }
+
+    /// Write the slot updates buffered if the batch is full or the interval has elapsed
+    pub async fn flush_due_slot_updates(&mut self) {
+        if self.pending_slot_updates.is_due() {
+            self.flush_slot_updates().await
+        }
+    }
+
+    /// Write out all the slot updates buffered. The batches are written one after the other,
+    /// as a slot may go through several statuses in consecutive batches.
+    pub async fn flush_slot_updates(&mut self) {
+        if self.pending_slot_updates.is_empty() {
+            return;
+        }
+        let slot_cells = self.pending_slot_updates.take();
         let raw_size = slot_cells.iter().map(|(_, m)| m.encoded_len()).sum();
 
-        let client = self.client.lock().unwrap();
-        let result = client
-            .client
-            .put_protobuf_cells_with_retry::<slots::Slot>("slot", &slot_cells, true)
-            .await;
-        match result {
-            Ok(written_size) => Ok((written_size, raw_size)),
-            Err(err) => {
-                error!("Error persisting into the database: {}", err);
-                Err(GeyserPluginError::Custom(Box::new(err)))
-            }
+        let client = self.client.clone();
+        let compression = self.compression.clone();
+        let kind = WriteKind::Slots {
+            count: slot_cells.len(),
+        };
+        // Dropped once the batch is written or has failed, releasing the next batch
+        let (written, written_receiver) = oneshot::channel::<()>();
+        let previous_slot_write = self.previous_slot_write.replace(written_receiver);
+        self.pipeline
+            .spawn(kind, async move {
+                let _written = written;
+                if let Some(previous_slot_write) = previous_slot_write {
+                    // Resolves with an error once the sender is dropped
+                    let _ = previous_slot_write.await;
+                }
+                let result = client
+                    .put_protobuf_cells(SLOT_TABLE, &slot_cells, compression.for_table(SLOT_TABLE))
+                    .await;
+                match result {
+                    Ok(written_size) => Ok((written_size, raw_size)),
+                    Err(err) => {
+                        error!("Error persisting into the database: {}", err);
+                        Err(GeyserPluginError::Custom(Box::new(err)))
+                    }
+                }
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn request(slot: u64, parent: Option<u64>, slot_status: SlotStatus) -> UpdateSlotRequest {
+        UpdateSlotRequest {
+            slot,
+            parent,
+            slot_status,
+            updated_since_epoch: Duration::from_millis(slot),
         }
     }
+
+    #[test]
+    fn test_pending_slots_keep_latest_status() {
+        let mut pending = PendingSlots::new(3, Duration::from_secs(60));
+        pending.insert(request(10, Some(9), SlotStatus::Processed));
+        pending.insert(request(11, Some(10), SlotStatus::Processed));
+        pending.insert(request(10, None, SlotStatus::Confirmed));
+        pending.insert(request(10, None, SlotStatus::Rooted));
+        assert_eq!(pending.len(), 2);
+        assert!(!pending.is_due());
+
+        pending.insert(request(12, Some(11), SlotStatus::Processed));
+        assert!(pending.is_due());
+
+        let cells = pending.take();
+        assert!(pending.is_empty());
+        assert!(!pending.is_due());
+        assert_eq!(
+            cells
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["10", "11", "12"]
+        );
+        assert_eq!(cells[0].1.status, SlotStatus::Rooted.as_str());
+        assert_eq!(cells[0].1.parent, Some(9));
+    }
+
+    #[test]
+    fn test_pending_slots_due_on_interval() {
+        let mut pending = PendingSlots::new(100, Duration::from_millis(10));
+        assert!(!pending.is_due());
+        pending.insert(request(10, Some(9), SlotStatus::Processed));
+        assert!(!pending.is_due());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(pending.is_due());
+    }
 }
@@ -0,0 +1,117 @@
+/// Writes accounts to Bigtable outside of a validator, for the tools loading or repairing
+/// the accounts of a snapshot, backfilling the blocks of a ledger or replaying the failed
+/// writes. The accounts go through the
+/// batched write path of the startup accounts notified by a validator, and the writes run
+/// as tasks on the runtime the writer is used from.
+use {
+    crate::{
+        compression::CompressedCell,
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        ledger::BlockRows,
+        parallel_bigtable_client::{
+            account::DbAccountInfo,
+            block_metadata::{block_row_key, BLOCK_TABLE},
+            pipeline::WritePipeline,
+            startup::StartupProgress,
+            BufferedBigtableClient, ParallelBigtableClient,
+        },
+    },
+    solana_bigtable_connection::bigtable::{Error as BigtableError, RowKey},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+pub struct StandaloneWriter {
+    client: BufferedBigtableClient,
+    pipeline: WritePipeline,
+    progress: Arc<StartupProgress>,
+    write_failures: Arc<AtomicUsize>,
+}
+
+impl StandaloneWriter {
+    /// Connect to Bigtable, `accounts_estimate` is used to report the progress
+    pub async fn new(
+        config: &GeyserPluginBigtableConfig,
+        accounts_estimate: Option<u64>,
+    ) -> Result<Self, GeyserPluginError> {
+        let progress = Arc::new(StartupProgress::new(accounts_estimate));
+        let write_failures = Arc::new(AtomicUsize::default());
+        let pipeline = ParallelBigtableClient::create_pipeline(
+            config,
+            progress.clone(),
+            write_failures.clone(),
+        )?;
+        let client =
+            BufferedBigtableClient::new(config, pipeline.clone(), Arc::new(AtomicUsize::default()))
+                .await?;
+        Ok(Self {
+            client,
+            pipeline,
+            progress,
+            write_failures,
+        })
+    }
+
+    /// Buffer the account, writing the batch once it is full
+    pub async fn write_account(&mut self, account: DbAccountInfo) {
+        self.progress.maybe_report();
+        self.client.update_account(account, true).await
+    }
+
+    /// Write out the accounts buffered
+    pub async fn flush(&mut self) {
+        self.client.flush_account_updates().await
+    }
+
+    /// Write the cells already compressed to the table right away, returning the size
+    /// written
+    pub async fn write_cells(
+        &self,
+        table: &str,
+        cells: &[(RowKey, CompressedCell)],
+    ) -> Result<usize, BigtableError> {
+        self.client.client.put_compressed_cells(table, cells).await
+    }
+
+    /// Write the transactions of a block right away, then the block, so that a block stored
+    /// has its transactions stored. Returns the size written.
+    pub async fn write_block_rows(&self, rows: BlockRows) -> Result<usize, BigtableError> {
+        let compression = &self.client.compression;
+        let transaction_cells: Vec<_> = rows
+            .transactions
+            .into_iter()
+            .map(|transaction| (transaction.row_key(), transaction))
+            .collect();
+        let mut written_size = 0;
+        if !transaction_cells.is_empty() {
+            written_size += self
+                .client
+                .client
+                .put_transactions(&transaction_cells, compression)
+                .await?;
+        }
+        let block_cell = (block_row_key(rows.block.slot as u64), rows.block);
+        written_size += self
+            .client
+            .client
+            .put_bincode_cells(
+                BLOCK_TABLE,
+                &[block_cell],
+                compression.for_table(BLOCK_TABLE),
+            )
+            .await?;
+        Ok(written_size)
+    }
+
+    /// Write out the accounts buffered and wait for all the writes to complete, returning
+    /// the count of accounts which failed to be written
+    pub async fn finish(mut self) -> usize {
+        self.client.flush_account_updates().await;
+        self.pipeline.wait_for_writes().await;
+        self.progress.report();
+        self.write_failures.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,76 @@
+use {
+    log::*,
+    solana_metrics::*,
+    solana_sdk::timing::AtomicInterval,
+    std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Instant,
+    },
+};
+
+/// The interval in ms between two progress reports of the startup bulk loading
+const PROGRESS_REPORT_INTERVAL_MS: u64 = 10000;
+
+/// Tracks the progress of bulk loading the snapshot accounts during startup
+pub struct StartupProgress {
+    start: Instant,
+    accounts: AtomicU64,
+    bytes: AtomicU64,
+    /// The estimated count of accounts in the snapshot, used to compute the ETA
+    accounts_estimate: Option<u64>,
+    last_report: AtomicInterval,
+}
+
+impl StartupProgress {
+    pub fn new(accounts_estimate: Option<u64>) -> Self {
+        Self {
+            start: Instant::now(),
+            accounts: AtomicU64::default(),
+            bytes: AtomicU64::default(),
+            accounts_estimate,
+            last_report: AtomicInterval::default(),
+        }
+    }
+
+    /// Record the accounts processed and the bytes written to Bigtable
+    pub fn record(&self, accounts: u64, bytes: u64) {
+        self.accounts.fetch_add(accounts, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Log the progress if the report interval has elapsed since the last report
+    pub fn maybe_report(&self) {
+        if self.last_report.should_update(PROGRESS_REPORT_INTERVAL_MS) {
+            self.report();
+        }
+    }
+
+    pub fn report(&self) {
+        let elapsed = self.start.elapsed().as_secs_f64().max(f64::EPSILON);
+        let accounts = self.accounts.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let accounts_per_second = accounts as f64 / elapsed;
+        let eta = match self.accounts_estimate {
+            Some(estimate) if accounts_per_second > 0.0 => format!(
+                "{:.0}s",
+                estimate.saturating_sub(accounts) as f64 / accounts_per_second
+            ),
+            _ => "unknown".to_string(),
+        };
+        info!(
+            "Startup bulk loading: {} accounts, {} bytes in {:.0}s, {:.0} accounts/s, {:.0} bytes/s, ETA: {}",
+            accounts,
+            bytes,
+            elapsed,
+            accounts_per_second,
+            bytes as f64 / elapsed,
+            eta
+        );
+        datapoint_info!(
+            "geyser-plugin-bigtable-startup-progress",
+            ("accounts", accounts as i64, i64),
+            ("bytes", bytes as i64, i64),
+            ("accounts-per-second", accounts_per_second as i64, i64),
+        );
+    }
+}
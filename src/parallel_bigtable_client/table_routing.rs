@@ -0,0 +1,194 @@
+/// Routing of the tables to their Bigtable instance, app profile and table name. The
+/// tables are known by the names used in the code, "account", "account_chunk",
+/// "transaction", "block" and "slot", which are also the keys of the compression and
+/// error policies. The tables not routed use the instance and app profile of the top level.
+/// With `table_prefix`, the name of each table in its instance starts with the prefix, so
+/// that several clusters can share an instance.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            BufferedBigtableClient, DEFAULT_APP_PROFILE_ID, DEFAULT_BIGTABLE_INSTANCE,
+        },
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::BigTableConnection as Client,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::collections::HashMap,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableRoutingConfig {
+    /// The instance of the table. The instance of the top level if not set.
+    pub instance: Option<String>,
+
+    /// The app profile the table is written and read with. The app profile of the top
+    /// level if not set.
+    pub app_profile_id: Option<String>,
+
+    /// The name of the table in the instance. The name used in the code if not set.
+    pub table: Option<String>,
+}
+
+/// Where a table is written to
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TableEndpoint {
+    pub instance: String,
+    pub app_profile_id: String,
+}
+
+impl TableEndpoint {
+    /// The endpoint of the tables not routed
+    pub fn default_for(config: &GeyserPluginBigtableConfig) -> Self {
+        Self {
+            instance: config
+                .instance
+                .clone()
+                .unwrap_or_else(|| DEFAULT_BIGTABLE_INSTANCE.to_string()),
+            app_profile_id: config
+                .app_profile_id
+                .clone()
+                .unwrap_or_else(|| DEFAULT_APP_PROFILE_ID.to_string()),
+        }
+    }
+}
+
+/// Resolve the endpoint and the name of each table routed, the options not set are
+/// inherited from the top level. The names include the table prefix.
+pub fn resolve_table_routes(
+    config: &GeyserPluginBigtableConfig,
+) -> HashMap<String, (TableEndpoint, String)> {
+    let default = TableEndpoint::default_for(config);
+    let prefix = config.table_prefix.as_deref().unwrap_or_default();
+    config
+        .tables
+        .iter()
+        .flatten()
+        .map(|(table, routing)| {
+            let endpoint = TableEndpoint {
+                instance: routing
+                    .instance
+                    .clone()
+                    .unwrap_or_else(|| default.instance.clone()),
+                app_profile_id: routing
+                    .app_profile_id
+                    .clone()
+                    .unwrap_or_else(|| default.app_profile_id.clone()),
+            };
+            let name = format!("{}{}", prefix, routing.table.as_ref().unwrap_or(table));
+            (table.clone(), (endpoint, name))
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct TableRoute {
+    client: Client,
+    table: String,
+}
+
+/// The connections to the tables, one for each of the endpoints
+#[derive(Clone)]
+pub struct TableRoutes {
+    default: Client,
+    /// Prepended to the names of the tables not routed
+    prefix: String,
+    routes: HashMap<String, TableRoute>,
+}
+
+impl TableRoutes {
+    /// Connect to the endpoint of the top level and to each of the endpoints the tables
+    /// are routed to
+    pub async fn connect(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
+        let default_endpoint = TableEndpoint::default_for(config);
+        let default =
+            BufferedBigtableClient::connect_to_endpoint(config, &default_endpoint).await?;
+
+        let mut clients = HashMap::new();
+        clients.insert(default_endpoint, default.clone());
+        let mut routes = HashMap::new();
+        for (table, (endpoint, name)) in resolve_table_routes(config) {
+            if !clients.contains_key(&endpoint) {
+                let client = BufferedBigtableClient::connect_to_endpoint(config, &endpoint).await?;
+                clients.insert(endpoint.clone(), client);
+            }
+            routes.insert(
+                table,
+                TableRoute {
+                    client: clients[&endpoint].clone(),
+                    table: name,
+                },
+            );
+        }
+        Ok(Self {
+            default,
+            prefix: config.table_prefix.clone().unwrap_or_default(),
+            routes,
+        })
+    }
+
+    /// The tables are all in the instance of the client
+    pub fn from_client(client: Client) -> Self {
+        Self {
+            default: client,
+            prefix: String::default(),
+            routes: HashMap::default(),
+        }
+    }
+
+    /// The connection to the instance of the table
+    pub fn client(&self, table: &str) -> Client {
+        self.routes
+            .get(table)
+            .map_or(&self.default, |route| &route.client)
+            .clone()
+    }
+
+    /// The name of the table in its instance
+    pub fn table(&self, table: &str) -> String {
+        match self.routes.get(table) {
+            Some(route) => route.table.clone(),
+            None => format!("{}{}", self.prefix, table),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_table_routes() {
+        let config: GeyserPluginBigtableConfig = serde_json::from_value(serde_json::json!({
+            "instance": "geyser",
+            "app_profile_id": "latest",
+            "table_prefix": "mainnet_",
+            "tables": {
+                "transaction": { "instance": "geyser-history", "app_profile_id": "batch" },
+                "slot": { "table": "slot_v2" }
+            }
+        }))
+        .unwrap();
+        let routes = resolve_table_routes(&config);
+
+        assert_eq!(routes.len(), 2);
+        assert_eq!(
+            routes["transaction"],
+            (
+                TableEndpoint {
+                    instance: "geyser-history".to_string(),
+                    app_profile_id: "batch".to_string(),
+                },
+                "mainnet_transaction".to_string()
+            )
+        );
+        assert_eq!(
+            routes["slot"],
+            (
+                TableEndpoint::default_for(&config),
+                "mainnet_slot_v2".to_string()
+            )
+        );
+        assert_eq!(TableEndpoint::default_for(&config).app_profile_id, "latest");
+    }
+}
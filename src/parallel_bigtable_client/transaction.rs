@@ -1,5 +1,16 @@
 use {
-    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaTransactionInfo,
+    crate::{
+        compression::TableCompression,
+        parallel_bigtable_client::{
+            pipeline::WriteKind, BigtableClientWrapper, BufferedBigtableClient,
+        },
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, ReplicaTransactionInfo,
+    },
     solana_runtime::bank::RewardType,
     solana_sdk::{
         instruction::CompiledInstruction,
@@ -12,28 +23,36 @@ use {
     solana_transaction_status::{
         InnerInstructions, Reward, TransactionStatusMeta, TransactionTokenBalance,
     },
+    std::time::{Duration, Instant},
 };
 
 const MAX_TRANSACTION_STATUS_LEN: usize = 256;
 
+/// The table storing the transactions, keyed by slot and index within the block
+pub const TRANSACTION_TABLE: &str = "transaction";
+
+/// The table indexing the transactions by signature, each row holding the row key of the
+/// transaction in the `transaction` table
+pub const TRANSACTION_BY_SIGNATURE_TABLE: &str = "transaction_by_signature";
+
 pub struct LogTransactionRequest {
     pub transaction_info: DbTransaction,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbCompiledInstruction {
     pub program_id_index: i16,
     pub accounts: Vec<i16>,
     pub data: Vec<u8>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbInnerInstructions {
     pub index: i16,
     pub instructions: Vec<DbCompiledInstruction>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionTokenBalance {
     pub account_index: i16,
     pub mint: String,
@@ -41,7 +60,7 @@ pub struct DbTransactionTokenBalance {
     pub owner: String,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum DbRewardType {
     Fee,
     Rent,
@@ -49,7 +68,7 @@ pub enum DbRewardType {
     Voting,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbReward {
     pub pubkey: String,
     pub lamports: i64,
@@ -58,7 +77,7 @@ pub struct DbReward {
     pub commission: Option<i16>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionStatusMeta {
     pub error: Option<DbTransactionError>,
     pub fee: i64,
@@ -71,14 +90,14 @@ pub struct DbTransactionStatusMeta {
     pub rewards: Option<Vec<DbReward>>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionMessageHeader {
     pub num_required_signatures: i16,
     pub num_readonly_signed_accounts: i16,
     pub num_readonly_unsigned_accounts: i16,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionMessage {
     pub header: DbTransactionMessageHeader,
     pub account_keys: Vec<Vec<u8>>,
@@ -86,14 +105,14 @@ pub struct DbTransactionMessage {
     pub instructions: Vec<DbCompiledInstruction>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionMessageAddressTableLookup {
     pub account_key: Vec<u8>,
     pub writable_indexes: Vec<i16>,
     pub readonly_indexes: Vec<i16>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbTransactionMessageV0 {
     pub header: DbTransactionMessageHeader,
     pub account_keys: Vec<Vec<u8>>,
@@ -102,18 +121,19 @@ pub struct DbTransactionMessageV0 {
     pub address_table_lookups: Vec<DbTransactionMessageAddressTableLookup>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbLoadedAddresses {
     pub writable: Vec<Vec<u8>>,
     pub readonly: Vec<Vec<u8>>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbLoadedMessageV0 {
     pub message: DbTransactionMessageV0,
     pub loaded_addresses: DbLoadedAddresses,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DbTransaction {
     pub signature: Vec<u8>,
     pub is_vote: bool,
@@ -124,6 +144,152 @@ pub struct DbTransaction {
     pub message_hash: Vec<u8>,
     pub meta: DbTransactionStatusMeta,
     pub signatures: Vec<Vec<u8>>,
+    /// The index of the transaction within the block, if notified
+    pub index: Option<u64>,
+}
+
+/// The row key of a transaction, ordering the transactions of a slot by their index within
+/// the block. Without the index, the transactions of the slot are keyed by signature.
+pub fn transaction_row_key(slot: u64, index: Option<u64>, signature: &[u8]) -> String {
+    match index {
+        Some(index) => format!("{:016x}/{:08x}", slot, index),
+        None => format!("{:016x}/{}", slot, bs58::encode(signature).into_string()),
+    }
+}
+
+impl DbTransaction {
+    pub fn row_key(&self) -> String {
+        transaction_row_key(self.slot as u64, self.index, &self.signature)
+    }
+
+    /// The row of the transaction in the signature index, pointing to its row key
+    pub fn signature_index_cell(&self) -> (String, String) {
+        (bs58::encode(&self.signature).into_string(), self.row_key())
+    }
+}
+
+/// The transactions buffered until the batch is written
+pub struct PendingTransactions {
+    transactions: Vec<(String, DbTransaction)>,
+    /// When the oldest of the transactions buffered was received
+    since: Option<Instant>,
+    batch_size: usize,
+    batch_interval: Duration,
+}
+
+impl PendingTransactions {
+    pub fn new(batch_size: usize, batch_interval: Duration) -> Self {
+        Self {
+            transactions: Vec::default(),
+            since: None,
+            batch_size: batch_size.max(1),
+            batch_interval,
+        }
+    }
+
+    pub fn push(&mut self, transaction: DbTransaction) {
+        self.transactions.push((transaction.row_key(), transaction));
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    /// If the batch is to be written, either full or buffered for longer than the interval
+    pub fn is_due(&self) -> bool {
+        self.transactions.len() >= self.batch_size
+            || self
+                .since
+                .map_or(false, |since| since.elapsed() >= self.batch_interval)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<(String, DbTransaction)> {
+        self.since = None;
+        std::mem::take(&mut self.transactions)
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Buffer the transaction, writing the batch once it is due
+    pub async fn log_transaction(&mut self, request: LogTransactionRequest) {
+        self.pending_transactions.push(request.transaction_info);
+        self.flush_due_transactions().await
+    }
+
+    /// Write the transactions buffered if the batch is full or the interval has elapsed
+    pub async fn flush_due_transactions(&mut self) {
+        if self.pending_transactions.is_due() {
+            self.flush_transactions().await
+        }
+    }
+
+    /// Write out all the transactions buffered. The rows of the transactions are distinct,
+    /// so the batches are written concurrently.
+    pub async fn flush_transactions(&mut self) {
+        if self.pending_transactions.is_empty() {
+            return;
+        }
+        let transaction_cells = self.pending_transactions.take();
+        let raw_size = transaction_cells
+            .iter()
+            .map(|(_, transaction)| bincode::serialized_size(transaction).unwrap() as usize)
+            .sum();
+
+        let client = self.client.clone();
+        let compression = self.compression.clone();
+        let kind = WriteKind::Transactions {
+            count: transaction_cells.len(),
+        };
+        self.pipeline
+            .spawn(kind, async move {
+                match client
+                    .put_transactions(&transaction_cells, &compression)
+                    .await
+                {
+                    Ok(written_size) => Ok((written_size, raw_size)),
+                    Err(err) => {
+                        error!("Error persisting the transactions: {}", err);
+                        Err(GeyserPluginError::Custom(Box::new(err)))
+                    }
+                }
+            })
+            .await
+    }
+}
+
+impl BigtableClientWrapper {
+    /// Write the transactions, then their signature index so that it never points to a
+    /// transaction not written. Returns the size written.
+    pub(crate) async fn put_transactions(
+        &self,
+        transaction_cells: &[(String, DbTransaction)],
+        compression: &TableCompression,
+    ) -> Result<usize, BigtableError> {
+        let index_cells: Vec<(String, String)> = transaction_cells
+            .iter()
+            .map(|(_, transaction)| transaction.signature_index_cell())
+            .collect();
+        let written_size = self
+            .put_bincode_cells(
+                TRANSACTION_TABLE,
+                transaction_cells,
+                compression.for_table(TRANSACTION_TABLE),
+            )
+            .await?;
+        Ok(written_size
+            + self
+                .put_bincode_cells(
+                    TRANSACTION_BY_SIGNATURE_TABLE,
+                    &index_cells,
+                    compression.for_table(TRANSACTION_BY_SIGNATURE_TABLE),
+                )
+                .await?)
+    }
 }
 
 impl From<&MessageAddressTableLookup> for DbTransactionMessageAddressTableLookup {
@@ -282,7 +448,7 @@ impl From<&Reward> for DbReward {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct DbTransactionError {
     error_code: DbTransactionErrorCode,
     error_detail: Option<String>,
@@ -366,7 +532,11 @@ impl From<&TransactionStatusMeta> for DbTransactionStatusMeta {
     }
 }
 
-pub fn build_db_transaction(slot: u64, transaction_info: &ReplicaTransactionInfo) -> DbTransaction {
+pub fn build_db_transaction(
+    slot: u64,
+    transaction_info: &ReplicaTransactionInfo,
+    index: Option<usize>,
+) -> DbTransaction {
     DbTransaction {
         signature: transaction_info.signature.as_ref().to_vec(),
         is_vote: transaction_info.is_vote,
@@ -397,10 +567,11 @@ pub fn build_db_transaction(slot: u64, transaction_info: &ReplicaTransactionInfo
             .as_ref()
             .to_vec(),
         meta: DbTransactionStatusMeta::from(transaction_info.transaction_status_meta),
+        index: index.map(|index| index as u64),
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum DbTransactionErrorCode {
     AccountInUse,
     AccountLoadedTwice,
@@ -483,3 +654,29 @@ impl From<&TransactionError> for DbTransactionErrorCode {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_row_key() {
+        let signature = vec![1; 64];
+        assert_eq!(
+            transaction_row_key(300, Some(2), &signature),
+            "000000000000012c/00000002"
+        );
+        // The transactions of a slot are ordered by their index
+        assert!(
+            transaction_row_key(300, Some(2), &signature)
+                < transaction_row_key(300, Some(10), &signature)
+        );
+        assert_eq!(
+            transaction_row_key(300, None, &signature),
+            format!(
+                "000000000000012c/{}",
+                bs58::encode(&signature).into_string()
+            )
+        );
+    }
+}
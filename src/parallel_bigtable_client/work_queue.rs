@@ -0,0 +1,766 @@
+/// The queue of the work items between the notifying threads and the dispatchers. The
+/// queue is bounded by both the count of items and their approximate size in bytes, and
+/// the `BackpressurePolicy` decides what happens to the notifications once it is full.
+///
+/// The queue is split into two lanes, each served by its own dispatcher: the account
+/// updates, and the priority lane for the slot, block and transaction items so that they
+/// are not stuck behind the bulk of the account updates. Only the account lane is
+/// bounded in bytes, the priority lane is bounded by its count of items alone.
+use {
+    crate::parallel_bigtable_client::{account::UpdateAccountRequest, DbWorkItem},
+    crossbeam_channel::{
+        bounded, Receiver, RecvTimeoutError, SendError, SendTimeoutError, Sender, TrySendError,
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_metrics::*,
+    std::{
+        fs::{self, File, OpenOptions},
+        io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+        mem::size_of,
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Condvar, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// What happens to the notifications once the work queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Block the notifying thread until there is room in the queue
+    Block,
+    /// Drop the item being queued
+    DropNewest,
+    /// Drop the account updates, and block for the slot, block and transaction items
+    DropAccounts,
+    /// Spill the account updates to a queue on the local disk, and block for the other items
+    Spill,
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        Self::Block
+    }
+}
+
+/// The outcome of queueing a work item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueOutcome {
+    Queued,
+    Spilled,
+    Dropped,
+}
+
+impl DbWorkItem {
+    /// The approximate size in memory of the work item
+    fn size(&self) -> usize {
+        match self {
+            DbWorkItem::UpdateAccount(request) => {
+                size_of::<UpdateAccountRequest>()
+                    + request.account.data.len()
+                    + request.account.pubkey.len()
+                    + request.account.owner.len()
+            }
+            DbWorkItem::UpdateSlot(_) => size_of::<DbWorkItem>() * 8,
+            DbWorkItem::EndOfStartup(_) => size_of::<DbWorkItem>(),
+            DbWorkItem::LogTransaction(_) | DbWorkItem::UpdateBlockMetadata(_) => {
+                size_of::<DbWorkItem>() * 64
+            }
+        }
+    }
+
+    fn is_account(&self) -> bool {
+        matches!(self, DbWorkItem::UpdateAccount(_))
+    }
+
+    fn report_dropped_accounts(count: usize) {
+        inc_new_counter_info!("geyser-plugin-bigtable-dropped-accounts", count, 1000, 1000);
+    }
+
+    fn report_dropped(&self) {
+        match self {
+            DbWorkItem::UpdateAccount(_) => Self::report_dropped_accounts(1),
+            DbWorkItem::UpdateSlot(_) => {
+                inc_new_counter_info!("geyser-plugin-bigtable-dropped-slots", 1, 1000, 1000);
+            }
+            DbWorkItem::LogTransaction(_) => {
+                inc_new_counter_info!("geyser-plugin-bigtable-dropped-transactions", 1, 1000, 1000);
+            }
+            DbWorkItem::UpdateBlockMetadata(_) => {
+                inc_new_counter_info!("geyser-plugin-bigtable-dropped-blocks", 1, 1000, 1000);
+            }
+            DbWorkItem::EndOfStartup(_) => {}
+        }
+    }
+}
+
+/// The account updates spilled to the local disk, stored in order in a single file which
+/// is truncated once all its items have been read back. Each record is prefixed with its
+/// length, so that a record which cannot be decoded is skipped.
+struct SpillQueue {
+    state: Mutex<SpillState>,
+    /// The count of the items spilled but not read back yet
+    pending: AtomicUsize,
+}
+
+struct SpillState {
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+}
+
+impl SpillQueue {
+    fn new(dir: &Path) -> Result<Self, io::Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join("spill.queue");
+        let writer = OpenOptions::new().create(true).append(true).open(&path)?;
+        // Any leftover from a previous run is not readable without its count
+        writer.set_len(0)?;
+        let reader = File::open(&path)?;
+        Ok(Self {
+            state: Mutex::new(SpillState {
+                writer: BufWriter::new(writer),
+                reader: BufReader::new(reader),
+            }),
+            pending: AtomicUsize::default(),
+        })
+    }
+
+    fn push(&self, request: &UpdateAccountRequest) -> Result<(), io::Error> {
+        let data =
+            bincode::serialize(request).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut state = self.state.lock().unwrap();
+        state.writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        state.writer.write_all(&data)?;
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read back the oldest account update spilled. A record which cannot be decoded is
+    /// skipped and counted as dropped. If the file cannot be read any further, all the
+    /// updates left in it are counted as dropped.
+    fn pop(&self) -> Option<UpdateAccountRequest> {
+        let mut state = self.state.lock().unwrap();
+        while !self.is_empty() {
+            match Self::read_record(&mut state) {
+                Ok(Ok(request)) => {
+                    self.consume(&mut state, 1);
+                    return Some(request);
+                }
+                Ok(Err(err)) => {
+                    error!(
+                        "Error decoding a spilled account update, dropping it: {}",
+                        err
+                    );
+                    DbWorkItem::report_dropped_accounts(1);
+                    self.consume(&mut state, 1);
+                }
+                Err(err) => {
+                    let lost = self.len();
+                    error!(
+                        "Error reading the spilled account updates, dropping the {} left: {}",
+                        lost, err
+                    );
+                    DbWorkItem::report_dropped_accounts(lost);
+                    self.consume(&mut state, lost);
+                }
+            }
+        }
+        None
+    }
+
+    /// Read the next record. The outer error leaves the file unreadable past the record,
+    /// the inner one is a record read entirely but which does not decode.
+    fn read_record(
+        state: &mut SpillState,
+    ) -> Result<Result<UpdateAccountRequest, bincode::Error>, io::Error> {
+        state.writer.flush()?;
+        let mut len = [0; 8];
+        state.reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        let mut data = Vec::default();
+        (&mut state.reader).take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated spilled record",
+            ));
+        }
+        Ok(bincode::deserialize(&data))
+    }
+
+    /// Count the records read, reclaiming the disk space once they all have been
+    fn consume(&self, state: &mut SpillState, count: usize) {
+        if self.pending.fetch_sub(count, Ordering::Relaxed) != count {
+            return;
+        }
+        let result = state
+            .writer
+            .get_ref()
+            .set_len(0)
+            .and_then(|()| state.reader.seek(SeekFrom::Start(0)));
+        if let Err(err) = result {
+            error!("Error truncating the spilled account updates: {}", err);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) == 0
+    }
+
+    fn len(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+/// The size of the items queued in a lane. The notifying threads blocked for room wait on
+/// the condition variable, signalled whenever items leave the lane.
+#[derive(Default)]
+struct QueuedBytes {
+    bytes: AtomicUsize,
+    lock: Mutex<()>,
+    freed: Condvar,
+}
+
+impl QueuedBytes {
+    fn load(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, size: usize) {
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn sub(&self, size: usize) {
+        // Under the lock, so that a thread checking for room does not miss the signal
+        let _lock = self.lock.lock().unwrap();
+        self.bytes.fetch_sub(size, Ordering::Relaxed);
+        self.freed.notify_all();
+    }
+
+    /// Block until `has_room` holds for the size queued
+    fn wait_for_room<F: Fn(usize) -> bool>(&self, has_room: F) {
+        let mut lock = self.lock.lock().unwrap();
+        while !has_room(self.load()) {
+            lock = self.freed.wait(lock).unwrap();
+        }
+    }
+}
+
+/// The sending side of the work queue, used by the notifying threads
+pub(crate) struct WorkQueue {
+    sender: Sender<DbWorkItem>,
+    priority_sender: Sender<DbWorkItem>,
+    capacity_bytes: usize,
+    /// The size of the account updates queued, bounded by `capacity_bytes`
+    queued_bytes: Arc<QueuedBytes>,
+    priority_queued_bytes: Arc<QueuedBytes>,
+    policy: BackpressurePolicy,
+    spill: Option<Arc<SpillQueue>>,
+    /// Drop the items the policy would block on, so that the notifying thread is never
+    /// held up by this queue
+    never_block: bool,
+}
+
+/// The receiving side of a lane of the work queue, used by its dispatcher
+pub(crate) struct WorkReceiver {
+    receiver: Receiver<DbWorkItem>,
+    queued_bytes: Arc<QueuedBytes>,
+    spill: Option<Arc<SpillQueue>>,
+}
+
+/// The receivers of the lanes of the work queue
+pub(crate) struct WorkReceivers {
+    pub accounts: WorkReceiver,
+    pub priority: WorkReceiver,
+}
+
+impl WorkQueue {
+    pub fn new(
+        capacity: usize,
+        priority_capacity: usize,
+        capacity_bytes: usize,
+        policy: BackpressurePolicy,
+        spill_path: Option<&Path>,
+    ) -> Result<(Self, WorkReceivers), io::Error> {
+        let spill = match (policy, spill_path) {
+            (BackpressurePolicy::Spill, Some(path)) => Some(Arc::new(SpillQueue::new(path)?)),
+            (BackpressurePolicy::Spill, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the spill policy requires \"spill_path\"",
+                ))
+            }
+            _ => None,
+        };
+        let (sender, receiver) = bounded(capacity.max(1));
+        let (priority_sender, priority_receiver) = bounded(priority_capacity.max(1));
+        let queued_bytes = Arc::new(QueuedBytes::default());
+        let priority_queued_bytes = Arc::new(QueuedBytes::default());
+        Ok((
+            Self {
+                sender,
+                priority_sender,
+                capacity_bytes,
+                queued_bytes: queued_bytes.clone(),
+                priority_queued_bytes: priority_queued_bytes.clone(),
+                policy,
+                spill: spill.clone(),
+                never_block: false,
+            },
+            WorkReceivers {
+                accounts: WorkReceiver {
+                    receiver,
+                    queued_bytes,
+                    spill,
+                },
+                priority: WorkReceiver {
+                    receiver: priority_receiver,
+                    queued_bytes: priority_queued_bytes,
+                    spill: None,
+                },
+            },
+        ))
+    }
+
+    /// Drop the items the backpressure policy would block on instead, for the queues of
+    /// several sinks notified by the same thread
+    pub fn set_never_block(&mut self, never_block: bool) {
+        self.never_block = never_block;
+    }
+
+    /// Queue the work item, applying the backpressure policy if the queue is full
+    pub fn send(&self, item: DbWorkItem) -> Result<QueueOutcome, SendError<DbWorkItem>> {
+        let size = item.size();
+        let is_account = item.is_account();
+        // Once accounts are spilled, the following ones are spilled as well to keep the
+        // updates of an account in order
+        let spilling = is_account && self.spill.as_ref().map_or(false, |s| !s.is_empty());
+        let item = if !spilling && self.has_room(&item, size) {
+            match self.lane(&item).try_send(item) {
+                Ok(()) => {
+                    self.lane_bytes(is_account).add(size);
+                    return Ok(QueueOutcome::Queued);
+                }
+                Err(TrySendError::Disconnected(item)) => return Err(SendError(item)),
+                Err(TrySendError::Full(item)) => item,
+            }
+        } else {
+            item
+        };
+
+        match (self.policy, item) {
+            (BackpressurePolicy::DropNewest, item)
+            | (BackpressurePolicy::DropAccounts, item @ DbWorkItem::UpdateAccount(_)) => {
+                item.report_dropped();
+                Ok(QueueOutcome::Dropped)
+            }
+            (BackpressurePolicy::Spill, DbWorkItem::UpdateAccount(request)) => {
+                match self.spill.as_ref().unwrap().push(&request) {
+                    Ok(()) => {
+                        inc_new_counter_debug!("geyser-plugin-bigtable-spilled-accounts", 1);
+                        Ok(QueueOutcome::Spilled)
+                    }
+                    Err(err) => {
+                        error!("Error spilling the account update to disk: {}", err);
+                        DbWorkItem::UpdateAccount(request).report_dropped();
+                        Ok(QueueOutcome::Dropped)
+                    }
+                }
+            }
+            (_, item) if self.never_block => {
+                item.report_dropped();
+                Ok(QueueOutcome::Dropped)
+            }
+            (_, item) => self.send_blocking(item, size),
+        }
+    }
+
+    fn send_blocking(
+        &self,
+        item: DbWorkItem,
+        size: usize,
+    ) -> Result<QueueOutcome, SendError<DbWorkItem>> {
+        let is_account = item.is_account();
+        if is_account {
+            self.queued_bytes
+                .wait_for_room(|queued_bytes| self.has_room_for(queued_bytes, size));
+        }
+        self.lane(&item).send(item)?;
+        self.lane_bytes(is_account).add(size);
+        Ok(QueueOutcome::Queued)
+    }
+
+    /// Queue an item made by `make_item` in each of the lanes, blocking until the deadline
+    /// regardless of the backpressure policy. Returns the count of the lanes.
+    pub fn send_to_all_lanes<F>(
+        &self,
+        make_item: F,
+        deadline: Instant,
+    ) -> Result<usize, SendTimeoutError<DbWorkItem>>
+    where
+        F: Fn() -> DbWorkItem,
+    {
+        let lanes = [
+            (&self.sender, &self.queued_bytes),
+            (&self.priority_sender, &self.priority_queued_bytes),
+        ];
+        for (lane, queued_bytes) in lanes {
+            let item = make_item();
+            let size = item.size();
+            lane.send_deadline(item, deadline)?;
+            queued_bytes.add(size);
+        }
+        Ok(lanes.len())
+    }
+
+    fn lane(&self, item: &DbWorkItem) -> &Sender<DbWorkItem> {
+        if item.is_account() {
+            &self.sender
+        } else {
+            &self.priority_sender
+        }
+    }
+
+    fn lane_bytes(&self, is_account: bool) -> &QueuedBytes {
+        if is_account {
+            &self.queued_bytes
+        } else {
+            &self.priority_queued_bytes
+        }
+    }
+
+    /// The account updates are bounded by the byte capacity, an update larger than the
+    /// capacity is still queued once the lane is empty. The priority lane is only bounded
+    /// by its count of items, so that it does not wait behind the account updates.
+    fn has_room(&self, item: &DbWorkItem, size: usize) -> bool {
+        !item.is_account() || self.has_room_for(self.queued_bytes.load(), size)
+    }
+
+    fn has_room_for(&self, queued_bytes: usize, size: usize) -> bool {
+        queued_bytes == 0 || queued_bytes + size <= self.capacity_bytes
+    }
+
+    /// The count of items queued, including the spilled ones
+    pub fn len(&self) -> usize {
+        self.sender.len() + self.priority_len() + self.spill.as_ref().map_or(0, |spill| spill.len())
+    }
+
+    /// The count of the account updates spilled to disk and not read back yet
+    pub fn spilled_len(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.len())
+    }
+
+    /// The count of items queued in the priority lane
+    pub fn priority_len(&self) -> usize {
+        self.priority_sender.len()
+    }
+
+    /// The size of the items queued in both lanes
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load() + self.priority_queued_bytes.load()
+    }
+}
+
+impl WorkReceiver {
+    /// The count of work items left in the lane, including the spilled ones
+    pub fn len(&self) -> usize {
+        self.receiver.len() + self.spill.as_ref().map_or(0, |spill| spill.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Receive the next work item. The spilled accounts are read back once the queue in
+    /// memory is drained.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<DbWorkItem, RecvTimeoutError> {
+        if let Some(spill) = &self.spill {
+            if self.receiver.is_empty() {
+                if let Some(request) = spill.pop() {
+                    return Ok(DbWorkItem::UpdateAccount(Box::new(request)));
+                }
+            }
+        }
+
+        let item = self.receiver.recv_timeout(timeout)?;
+        self.queued_bytes.sub(item.size());
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::parallel_bigtable_client::{account::DbAccountInfo, slot::UpdateSlotRequest},
+        solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus,
+        solana_sdk::pubkey::Pubkey,
+    };
+
+    fn account_item(write_version: u64) -> DbWorkItem {
+        DbWorkItem::UpdateAccount(Box::new(UpdateAccountRequest {
+            account: DbAccountInfo {
+                pubkey: Pubkey::new_unique().to_bytes().to_vec(),
+                lamports: 1,
+                owner: Pubkey::new_unique().to_bytes().to_vec(),
+                executable: false,
+                rent_epoch: 0,
+                data: vec![1; 100],
+                slot: 1,
+                write_version,
+                updated_since_epoch: Duration::default(),
+                txn_signature: None,
+            },
+            is_startup: false,
+        }))
+    }
+
+    fn write_version(item: DbWorkItem) -> u64 {
+        match item {
+            DbWorkItem::UpdateAccount(request) => request.account.write_version,
+            _ => panic!("unexpected work item"),
+        }
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let (queue, receivers) =
+            WorkQueue::new(2, 2, usize::MAX, BackpressurePolicy::DropNewest, None).unwrap();
+        let receiver = receivers.accounts;
+        assert_eq!(queue.send(account_item(0)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(account_item(1)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(account_item(2)).unwrap(), QueueOutcome::Dropped);
+        assert_eq!(queue.len(), 2);
+
+        let timeout = Duration::from_millis(10);
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 0);
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 1);
+        assert_eq!(queue.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn test_capacity_bytes() {
+        let size = account_item(0).size();
+        let (queue, _receiver) =
+            WorkQueue::new(100, 100, size * 2, BackpressurePolicy::DropAccounts, None).unwrap();
+        assert_eq!(queue.send(account_item(0)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(account_item(1)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(account_item(2)).unwrap(), QueueOutcome::Dropped);
+    }
+
+    #[test]
+    fn test_spill_keeps_order() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let (queue, receivers) = WorkQueue::new(
+            2,
+            2,
+            usize::MAX,
+            BackpressurePolicy::Spill,
+            Some(spill_dir.path()),
+        )
+        .unwrap();
+        let receiver = receivers.accounts;
+        for write_version in 0..5 {
+            queue.send(account_item(write_version)).unwrap();
+        }
+        assert_eq!(queue.len(), 5);
+        assert_eq!(receiver.len(), 5);
+
+        let timeout = Duration::from_millis(10);
+        // Room in memory does not let newer updates overtake the spilled ones
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 0);
+        assert_eq!(queue.send(account_item(5)).unwrap(), QueueOutcome::Spilled);
+        for expected in 1..6 {
+            assert_eq!(
+                write_version(receiver.recv_timeout(timeout).unwrap()),
+                expected
+            );
+        }
+        assert_eq!(queue.len(), 0);
+        assert!(receiver.is_empty());
+        assert_eq!(queue.send(account_item(6)).unwrap(), QueueOutcome::Queued);
+    }
+
+    #[test]
+    fn test_spill_skips_corrupt_record() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let (queue, receivers) = WorkQueue::new(
+            1,
+            1,
+            usize::MAX,
+            BackpressurePolicy::Spill,
+            Some(spill_dir.path()),
+        )
+        .unwrap();
+        let receiver = receivers.accounts;
+        for write_version in 0..4 {
+            queue.send(account_item(write_version)).unwrap();
+        }
+        assert_eq!(queue.spilled_len(), 3);
+        let timeout = Duration::from_millis(10);
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 0);
+
+        // Garble the payload of the second record spilled
+        let spill = queue.spill.as_ref().unwrap();
+        spill.state.lock().unwrap().writer.flush().unwrap();
+        let path = spill_dir.path().join("spill.queue");
+        let mut data = fs::read(&path).unwrap();
+        let record_len = data.len() / 3;
+        data[record_len + 8..record_len * 2].fill(0xff);
+        fs::write(&path, data).unwrap();
+
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 1);
+        assert_eq!(write_version(receiver.recv_timeout(timeout).unwrap()), 3);
+        assert!(receiver.recv_timeout(timeout).is_err());
+        assert_eq!(queue.spilled_len(), 0);
+        assert_eq!(queue.send(account_item(4)).unwrap(), QueueOutcome::Queued);
+    }
+
+    #[test]
+    fn test_blocked_send_wakes_up() {
+        let size = account_item(0).size();
+        let (queue, receivers) =
+            WorkQueue::new(100, 100, size, BackpressurePolicy::Block, None).unwrap();
+        assert_eq!(queue.send(account_item(0)).unwrap(), QueueOutcome::Queued);
+
+        let sender = std::thread::spawn(move || queue.send(account_item(1)).unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!sender.is_finished());
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            write_version(receivers.accounts.recv_timeout(timeout).unwrap()),
+            0
+        );
+        assert_eq!(sender.join().unwrap(), QueueOutcome::Queued);
+        assert_eq!(
+            write_version(receivers.accounts.recv_timeout(timeout).unwrap()),
+            1
+        );
+    }
+
+    #[test]
+    fn test_send_to_all_lanes() {
+        let (queue, receivers) =
+            WorkQueue::new(1, 1, usize::MAX, BackpressurePolicy::DropNewest, None).unwrap();
+        let (ack_sender, _ack_receiver) = crossbeam_channel::unbounded();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(
+            queue
+                .send_to_all_lanes(|| DbWorkItem::EndOfStartup(ack_sender.clone()), deadline)
+                .unwrap(),
+            2
+        );
+        assert_eq!(queue.len(), 2);
+
+        // The barrier is not dropped but waits for room until the deadline
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(matches!(
+            queue.send_to_all_lanes(|| DbWorkItem::EndOfStartup(ack_sender.clone()), deadline),
+            Err(SendTimeoutError::Timeout(_))
+        ));
+
+        let timeout = Duration::from_millis(10);
+        for receiver in [&receivers.accounts, &receivers.priority] {
+            assert!(matches!(
+                receiver.recv_timeout(timeout).unwrap(),
+                DbWorkItem::EndOfStartup(_)
+            ));
+        }
+
+        drop(receivers);
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(matches!(
+            queue.send_to_all_lanes(|| DbWorkItem::EndOfStartup(ack_sender.clone()), deadline),
+            Err(SendTimeoutError::Disconnected(_))
+        ));
+    }
+
+    #[test]
+    fn test_priority_lane_capacity_bytes() {
+        let size = account_item(0).size();
+        let (queue, receivers) =
+            WorkQueue::new(100, 100, size, BackpressurePolicy::Block, None).unwrap();
+        assert_eq!(queue.send(account_item(0)).unwrap(), QueueOutcome::Queued);
+
+        // The account lane is full in bytes, the slots are queued without blocking
+        for slot in 0..10 {
+            let slot_item = DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
+                slot,
+                parent: None,
+                slot_status: SlotStatus::Processed,
+                updated_since_epoch: Duration::default(),
+            }));
+            assert_eq!(queue.send(slot_item).unwrap(), QueueOutcome::Queued);
+        }
+        assert_eq!(queue.priority_len(), 10);
+
+        let timeout = Duration::from_millis(10);
+        while receivers.priority.recv_timeout(timeout).is_ok() {}
+        assert_eq!(queue.queued_bytes(), size);
+        receivers.accounts.recv_timeout(timeout).unwrap();
+        assert_eq!(queue.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn test_priority_lane() {
+        let (queue, receivers) =
+            WorkQueue::new(2, 2, usize::MAX, BackpressurePolicy::DropAccounts, None).unwrap();
+        for write_version in 0..3 {
+            queue.send(account_item(write_version)).unwrap();
+        }
+        // The slots are queued while the account lane is full
+        let slot_item = DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
+            slot: 10,
+            parent: Some(9),
+            slot_status: SlotStatus::Rooted,
+            updated_since_epoch: Duration::default(),
+        }));
+        assert_eq!(queue.send(slot_item).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.priority_len(), 1);
+        assert_eq!(queue.len(), 3);
+
+        let timeout = Duration::from_millis(10);
+        match receivers.priority.recv_timeout(timeout).unwrap() {
+            DbWorkItem::UpdateSlot(request) => assert_eq!(request.slot, 10),
+            _ => panic!("unexpected work item"),
+        }
+        assert!(receivers.priority.recv_timeout(timeout).is_err());
+        assert_eq!(
+            write_version(receivers.accounts.recv_timeout(timeout).unwrap()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_never_block() {
+        let (mut queue, receivers) =
+            WorkQueue::new(1, 1, usize::MAX, BackpressurePolicy::Block, None).unwrap();
+        queue.set_never_block(true);
+        let slot_item = |slot| {
+            DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
+                slot,
+                parent: None,
+                slot_status: SlotStatus::Rooted,
+                updated_since_epoch: Duration::default(),
+            }))
+        };
+        // The full lanes drop the items instead of blocking the notifying thread
+        assert_eq!(queue.send(account_item(0)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(account_item(1)).unwrap(), QueueOutcome::Dropped);
+        assert_eq!(queue.send(slot_item(10)).unwrap(), QueueOutcome::Queued);
+        assert_eq!(queue.send(slot_item(11)).unwrap(), QueueOutcome::Dropped);
+
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            write_version(receivers.accounts.recv_timeout(timeout).unwrap()),
+            0
+        );
+        assert!(receivers.accounts.recv_timeout(timeout).is_err());
+        assert!(receivers.priority.recv_timeout(timeout).is_ok());
+        assert!(receivers.priority.recv_timeout(timeout).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+/// Exports the tables stored by the plugin to Parquet files for analytics. The rows are
+/// partitioned into directories named after the partition, `owner=<pubkey>` for the
+/// accounts and `slot_range=<first>-<last>` for the slots, so that query engines can prune
+/// the partitions. The columns follow the fields of `DbAccountInfo` and of the slot
+/// updates, with the pubkeys base58 encoded.
+use {
+    crate::{
+        bigtable_reader::{BigtableReader, BigtableReaderError},
+        models::{accounts, slots},
+    },
+    log::*,
+    parquet::{
+        basic::Compression,
+        data_type::{BoolType, ByteArray, ByteArrayType, DataType, Int64Type},
+        errors::ParquetError,
+        file::{
+            properties::WriterProperties,
+            writer::{SerializedFileWriter, SerializedRowGroupWriter},
+        },
+        schema::parser::parse_message_type,
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+    thiserror::Error,
+};
+
+/// The maximum count of rows in each of the files
+pub const DEFAULT_ROWS_PER_FILE: usize = 100_000;
+
+/// The count of slots in each of the slot partitions, an epoch
+pub const DEFAULT_SLOT_PARTITION_SIZE: u64 = 432_000;
+
+/// The size of the rows buffered across the partitions before the largest is written out
+const MAX_BUFFERED_BYTES: usize = 256 * 1024 * 1024;
+
+/// The count of rows read from Bigtable at once
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+const ACCOUNT_SCHEMA: &str = "
+message account {
+    REQUIRED BYTE_ARRAY pubkey (UTF8);
+    REQUIRED BYTE_ARRAY owner (UTF8);
+    REQUIRED INT64 lamports;
+    REQUIRED INT64 slot;
+    REQUIRED BOOLEAN executable;
+    REQUIRED INT64 rent_epoch;
+    REQUIRED BYTE_ARRAY data;
+    REQUIRED INT64 write_version;
+    OPTIONAL INT64 updated_on (TIMESTAMP_MILLIS);
+}
+";
+
+const SLOT_SCHEMA: &str = "
+message slot {
+    REQUIRED INT64 slot;
+    OPTIONAL INT64 parent;
+    REQUIRED BYTE_ARRAY status (UTF8);
+    OPTIONAL INT64 updated_on (TIMESTAMP_MILLIS);
+}
+";
+
+#[derive(Error, Debug)]
+pub enum ParquetExportError {
+    #[error("I/O error: ({0})")]
+    Io(#[from] io::Error),
+
+    #[error("Parquet error: ({0})")]
+    Parquet(#[from] ParquetError),
+
+    #[error("Error reading the rows: ({0})")]
+    Reader(#[from] BigtableReaderError),
+}
+
+/// A row of a table exported to Parquet
+pub trait ParquetRecord: Sized {
+    /// The Parquet message type of the rows
+    fn schema() -> &'static str;
+
+    /// The approximate size of the row in memory
+    fn buffered_size(&self) -> usize;
+
+    /// Write the columns of the rows in the order of the schema
+    fn write_columns(
+        rows: &[Self],
+        row_group: &mut SerializedRowGroupWriter<'_, File>,
+    ) -> Result<(), ParquetError>;
+}
+
+impl ParquetRecord for accounts::Account {
+    fn schema() -> &'static str {
+        ACCOUNT_SCHEMA
+    }
+
+    fn buffered_size(&self) -> usize {
+        self.pubkey.len() + self.owner.len() + self.data.len() + 64
+    }
+
+    fn write_columns(
+        rows: &[Self],
+        row_group: &mut SerializedRowGroupWriter<'_, File>,
+    ) -> Result<(), ParquetError> {
+        write_column::<ByteArrayType>(
+            row_group,
+            rows.iter().map(|row| base58_value(&row.pubkey)).collect(),
+        )?;
+        write_column::<ByteArrayType>(
+            row_group,
+            rows.iter().map(|row| base58_value(&row.owner)).collect(),
+        )?;
+        write_column::<Int64Type>(
+            row_group,
+            rows.iter().map(|row| row.lamports as i64).collect(),
+        )?;
+        write_column::<Int64Type>(row_group, rows.iter().map(|row| row.slot as i64).collect())?;
+        write_column::<BoolType>(row_group, rows.iter().map(|row| row.executable).collect())?;
+        write_column::<Int64Type>(
+            row_group,
+            rows.iter().map(|row| row.rent_epoch as i64).collect(),
+        )?;
+        write_column::<ByteArrayType>(
+            row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.data.clone()))
+                .collect(),
+        )?;
+        write_column::<Int64Type>(
+            row_group,
+            rows.iter().map(|row| row.write_version as i64).collect(),
+        )?;
+        write_optional_column::<Int64Type>(
+            row_group,
+            rows.iter().map(|row| {
+                row.updated_on
+                    .as_ref()
+                    .map(|updated_on| updated_on.timestamp)
+            }),
+        )
+    }
+}
+
+impl ParquetRecord for slots::Slot {
+    fn schema() -> &'static str {
+        SLOT_SCHEMA
+    }
+
+    fn buffered_size(&self) -> usize {
+        self.status.len() + 32
+    }
+
+    fn write_columns(
+        rows: &[Self],
+        row_group: &mut SerializedRowGroupWriter<'_, File>,
+    ) -> Result<(), ParquetError> {
+        write_column::<Int64Type>(row_group, rows.iter().map(|row| row.slot as i64).collect())?;
+        write_optional_column::<Int64Type>(
+            row_group,
+            rows.iter()
+                .map(|row| row.parent.map(|parent| parent as i64)),
+        )?;
+        write_column::<ByteArrayType>(
+            row_group,
+            rows.iter()
+                .map(|row| ByteArray::from(row.status.as_str()))
+                .collect(),
+        )?;
+        write_optional_column::<Int64Type>(
+            row_group,
+            rows.iter().map(|row| {
+                row.updated_on
+                    .as_ref()
+                    .map(|updated_on| updated_on.timestamp)
+            }),
+        )
+    }
+}
+
+fn base58_value(pubkey: &[u8]) -> ByteArray {
+    ByteArray::from(bs58::encode(pubkey).into_string().into_bytes())
+}
+
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, File>,
+    values: Vec<T::T>,
+) -> Result<(), ParquetError> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("More columns than in the schema".to_string()))?;
+    column.typed::<T>().write_batch(&values, None, None)?;
+    column.close()
+}
+
+fn write_optional_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, File>,
+    values: impl Iterator<Item = Option<T::T>>,
+) -> Result<(), ParquetError> {
+    let mut definition_levels = vec![];
+    let values: Vec<T::T> = values
+        .filter_map(|value| {
+            definition_levels.push(i16::from(value.is_some()));
+            value
+        })
+        .collect();
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("More columns than in the schema".to_string()))?;
+    column
+        .typed::<T>()
+        .write_batch(&values, Some(&definition_levels), None)?;
+    column.close()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub rows: usize,
+    pub files: usize,
+    pub partitions: usize,
+}
+
+/// Writes the rows to the Parquet files of their partition. The rows are buffered by
+/// partition, and each file holds a single row group.
+pub struct PartitionedWriter<T: ParquetRecord> {
+    dir: PathBuf,
+    rows_per_file: usize,
+    partitions: HashMap<String, Vec<T>>,
+    /// The count of files written for each of the partitions
+    file_counts: HashMap<String, usize>,
+    buffered_bytes: usize,
+    rows_written: usize,
+}
+
+impl<T: ParquetRecord> PartitionedWriter<T> {
+    pub fn new(dir: &Path, rows_per_file: usize) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            rows_per_file: rows_per_file.max(1),
+            partitions: HashMap::default(),
+            file_counts: HashMap::default(),
+            buffered_bytes: 0,
+            rows_written: 0,
+        }
+    }
+
+    pub fn write(&mut self, partition: String, row: T) -> Result<(), ParquetExportError> {
+        self.buffered_bytes += row.buffered_size();
+        let rows = self.partitions.entry(partition.clone()).or_default();
+        rows.push(row);
+        if rows.len() >= self.rows_per_file {
+            self.write_partition(&partition)?;
+        }
+        if self.buffered_bytes >= MAX_BUFFERED_BYTES {
+            let largest = self
+                .partitions
+                .iter()
+                .max_by_key(|(_, rows)| rows.len())
+                .map(|(partition, _)| partition.clone());
+            if let Some(largest) = largest {
+                self.write_partition(&largest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the rows still buffered
+    pub fn finish(mut self) -> Result<ExportSummary, ParquetExportError> {
+        let partitions: Vec<String> = self.partitions.keys().cloned().collect();
+        for partition in partitions {
+            self.write_partition(&partition)?;
+        }
+        Ok(ExportSummary {
+            rows: self.rows_written,
+            files: self.file_counts.values().sum(),
+            partitions: self.file_counts.len(),
+        })
+    }
+
+    fn write_partition(&mut self, partition: &str) -> Result<(), ParquetExportError> {
+        let rows = match self.partitions.remove(partition) {
+            Some(rows) if !rows.is_empty() => rows,
+            _ => return Ok(()),
+        };
+        self.buffered_bytes = self
+            .buffered_bytes
+            .saturating_sub(rows.iter().map(T::buffered_size).sum());
+
+        let dir = self.dir.join(partition);
+        fs::create_dir_all(&dir)?;
+        let file_count = self.file_counts.entry(partition.to_string()).or_default();
+        let path = dir.join(format!("part-{:05}.parquet", file_count));
+        *file_count += 1;
+
+        let schema = Arc::new(parse_message_type(T::schema())?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        let mut writer = SerializedFileWriter::new(File::create(&path)?, schema, properties)?;
+        let mut row_group = writer.next_row_group()?;
+        T::write_columns(&rows, &mut row_group)?;
+        row_group.close()?;
+        writer.close()?;
+
+        debug!("Wrote {} rows to {:?}", rows.len(), path);
+        self.rows_written += rows.len();
+        Ok(())
+    }
+}
+
+pub fn account_partition(account: &accounts::Account) -> String {
+    format!("owner={}", bs58::encode(&account.owner).into_string())
+}
+
+pub fn slot_partition(slot: u64, partition_size: u64) -> String {
+    let partition_size = partition_size.max(1);
+    let first = slot - slot % partition_size;
+    format!("slot_range={}-{}", first, first + partition_size - 1)
+}
+
+/// Export the latest state of the accounts, partitioned by owner. Only the accounts of
+/// `owner` are exported if set.
+pub async fn export_accounts(
+    reader: &BigtableReader,
+    dir: &Path,
+    rows_per_file: usize,
+    owner: Option<&Pubkey>,
+) -> Result<ExportSummary, ParquetExportError> {
+    let mut writer = PartitionedWriter::new(&dir.join("account"), rows_per_file);
+    let mut start_at = None;
+    loop {
+        let page = reader
+            .scan_accounts_with_data(start_at.clone(), EXPORT_PAGE_SIZE)
+            .await?;
+        let page_len = page.len();
+        let mut last_key = None;
+        for (key, account) in page {
+            // The scan starts at the last key of the previous page
+            if Some(&key) == start_at.as_ref() {
+                continue;
+            }
+            if owner.map_or(true, |owner| account.owner == owner.as_ref()) {
+                writer.write(account_partition(&account), account)?;
+            }
+            last_key = Some(key);
+        }
+        if last_key.is_none() || page_len < EXPORT_PAGE_SIZE as usize {
+            break;
+        }
+        start_at = last_key;
+    }
+    writer.finish()
+}
+
+/// Export the latest status of the slots, partitioned by ranges of `partition_size` slots
+pub async fn export_slots(
+    reader: &BigtableReader,
+    dir: &Path,
+    rows_per_file: usize,
+    partition_size: u64,
+) -> Result<ExportSummary, ParquetExportError> {
+    let mut writer = PartitionedWriter::new(&dir.join("slot"), rows_per_file);
+    let mut start_at = None;
+    loop {
+        let page = reader
+            .scan_slots(start_at.clone(), EXPORT_PAGE_SIZE)
+            .await?;
+        let page_len = page.len();
+        let mut last_key = None;
+        for (key, slot) in page {
+            if Some(&key) == start_at.as_ref() {
+                continue;
+            }
+            writer.write(slot_partition(slot.slot, partition_size), slot)?;
+            last_key = Some(key);
+        }
+        if last_key.is_none() || page_len < EXPORT_PAGE_SIZE as usize {
+            break;
+        }
+        start_at = last_key;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        parquet::file::reader::{FileReader, SerializedFileReader},
+    };
+
+    fn num_rows(path: &Path) -> i64 {
+        SerializedFileReader::new(File::open(path).unwrap())
+            .unwrap()
+            .metadata()
+            .file_metadata()
+            .num_rows()
+    }
+
+    #[test]
+    fn test_slot_partition() {
+        assert_eq!(slot_partition(0, 1000), "slot_range=0-999");
+        assert_eq!(slot_partition(12345, 1000), "slot_range=12000-12999");
+    }
+
+    #[test]
+    fn test_partitioned_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let owners = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut writer = PartitionedWriter::new(dir.path(), 2);
+        for i in 0..5 {
+            let account = accounts::Account {
+                pubkey: Pubkey::new_unique().to_bytes().to_vec(),
+                owner: owners[i % 2].to_bytes().to_vec(),
+                lamports: 1234,
+                slot: 10,
+                executable: false,
+                rent_epoch: 0,
+                data: vec![i as u8; 10],
+                write_version: i as u64,
+                updated_on: (i == 0).then(|| accounts::UnixTimestamp { timestamp: 1000 }),
+            };
+            writer.write(account_partition(&account), account).unwrap();
+        }
+        let summary = writer.finish().unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                rows: 5,
+                files: 3,
+                partitions: 2,
+            }
+        );
+
+        let first = dir.path().join(format!("owner={}", owners[0]));
+        assert_eq!(num_rows(&first.join("part-00000.parquet")), 2);
+        assert_eq!(num_rows(&first.join("part-00001.parquet")), 1);
+        let second = dir.path().join(format!("owner={}", owners[1]));
+        assert_eq!(num_rows(&second.join("part-00000.parquet")), 2);
+    }
+}
@@ -0,0 +1,274 @@
+/// Replays the writes spilled to the `<table>.failed` files by the `spill-to-disk` error
+/// policy. The records are written again in batches, except those superseded by a write
+/// which succeeded since: the account rows holding a newer write and the slot rows holding
+/// a later status. The rows of the other tables are keyed by version and written as is.
+/// The records failing again are kept in the file, which is removed once all of them are
+/// written.
+use {
+    crate::{
+        bigtable_reader::{BigtableReader, BigtableReaderError},
+        compression::CompressedCell,
+        models::{accounts, slots},
+        parallel_bigtable_client::{
+            account::ACCOUNT_TABLE,
+            error_policy::{read_failed_write, write_failed_write},
+            slot::SLOT_TABLE,
+            standalone::StandaloneWriter,
+        },
+    },
+    log::*,
+    solana_bigtable_connection::bigtable::RowKey,
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashSet,
+        fmt,
+        fs::{self, File},
+        io::{self, BufReader, BufWriter, Write},
+        mem,
+        path::Path,
+    },
+    thiserror::Error,
+};
+
+/// The count of records written at once
+pub const DEFAULT_REPLAY_BATCH_SIZE: usize = 1000;
+
+/// The statuses of a slot, in the order they are reached
+const SLOT_STATUS_ORDER: [SlotStatus; 3] = [
+    SlotStatus::Processed,
+    SlotStatus::Confirmed,
+    SlotStatus::Rooted,
+];
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("Error accessing the failed writes: ({0})")]
+    IoError(#[from] io::Error),
+
+    #[error("Error reading the rows stored: ({0})")]
+    ReaderError(#[from] BigtableReaderError),
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    /// Not written since a newer write of the row succeeded
+    pub superseded: usize,
+    /// Failed again, kept in the file
+    pub failed: usize,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Replayed {} rows, skipped {} rows superseded by a newer write, {} rows failed again",
+            self.replayed, self.superseded, self.failed
+        )
+    }
+}
+
+/// If the account of the failed write is older than the account stored
+pub fn is_account_superseded(
+    spilled: &accounts::Account,
+    stored: Option<&accounts::Account>,
+) -> bool {
+    stored.map_or(false, |stored| {
+        (stored.slot, stored.write_version) >= (spilled.slot, spilled.write_version)
+    })
+}
+
+/// If the status of the failed write is not later than the status stored
+pub fn is_slot_superseded(spilled: &slots::Slot, stored: Option<&slots::Slot>) -> bool {
+    let rank = |status: &str| {
+        SLOT_STATUS_ORDER
+            .iter()
+            .position(|slot_status| slot_status.as_str() == status)
+    };
+    stored.map_or(false, |stored| {
+        rank(&stored.status) >= rank(&spilled.status)
+    })
+}
+
+pub struct Replayer {
+    reader: BigtableReader,
+    writer: StandaloneWriter,
+    batch_size: usize,
+}
+
+impl Replayer {
+    pub fn new(reader: BigtableReader, writer: StandaloneWriter, batch_size: usize) -> Self {
+        Self {
+            reader,
+            writer,
+            batch_size: batch_size.max(1),
+        }
+    }
+
+    /// Replay the failed writes to the table spilled to the file
+    pub async fn replay_file(&self, table: &str, path: &Path) -> Result<ReplayReport, ReplayError> {
+        let mut records = BufReader::new(File::open(path)?);
+        let remaining_path = path.with_extension("failed.remaining");
+        let mut remaining = BufWriter::new(File::create(&remaining_path)?);
+        let mut report = ReplayReport::default();
+        let mut batch = Vec::with_capacity(self.batch_size);
+        loop {
+            let record = read_failed_write(&mut records)?;
+            let end = record.is_none();
+            batch.extend(record);
+            if batch.len() >= self.batch_size || (end && !batch.is_empty()) {
+                let cells = mem::take(&mut batch);
+                self.replay_batch(table, cells, &mut remaining, &mut report)
+                    .await?;
+            }
+            if end {
+                break;
+            }
+        }
+        remaining.flush()?;
+        drop(remaining);
+
+        if report.failed == 0 {
+            fs::remove_file(&remaining_path)?;
+            fs::remove_file(path)?;
+        } else {
+            fs::rename(&remaining_path, path)?;
+        }
+        Ok(report)
+    }
+
+    async fn replay_batch<W: Write>(
+        &self,
+        table: &str,
+        cells: Vec<(RowKey, CompressedCell)>,
+        remaining: &mut W,
+        report: &mut ReplayReport,
+    ) -> Result<(), ReplayError> {
+        let count = cells.len();
+        let cells = self.drop_superseded(table, cells).await?;
+        report.superseded += count - cells.len();
+        if cells.is_empty() {
+            return Ok(());
+        }
+
+        match self.writer.write_cells(table, &cells).await {
+            Ok(_) => report.replayed += cells.len(),
+            Err(err) => {
+                warn!(
+                    "Error replaying {} rows of the {} table: {}",
+                    cells.len(),
+                    table,
+                    err
+                );
+                for (key, cell) in &cells {
+                    write_failed_write(&mut *remaining, key, cell)?;
+                }
+                report.failed += cells.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the records superseded by a later record of the same row in the batch, or by
+    /// the row stored
+    async fn drop_superseded(
+        &self,
+        table: &str,
+        cells: Vec<(RowKey, CompressedCell)>,
+    ) -> Result<Vec<(RowKey, CompressedCell)>, ReplayError> {
+        // The records are in the order of the writes, the last one of a row is the latest
+        let mut keys = HashSet::with_capacity(cells.len());
+        let mut cells: Vec<_> = cells
+            .into_iter()
+            .rev()
+            .filter(|(key, _)| keys.insert(key.clone()))
+            .collect();
+        cells.reverse();
+
+        let superseded = match table {
+            ACCOUNT_TABLE => {
+                let spilled = cells
+                    .iter()
+                    .map(|(key, cell)| {
+                        self.reader
+                            .decode_compressed_cell::<accounts::Account>(key, &cell.0)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let pubkeys: Vec<Pubkey> = spilled
+                    .iter()
+                    .map(|account| Pubkey::new(&account.pubkey))
+                    .collect();
+                let stored = self.reader.get_accounts(&pubkeys).await?;
+                spilled
+                    .iter()
+                    .zip(&pubkeys)
+                    .map(|(account, pubkey)| is_account_superseded(account, stored.get(pubkey)))
+                    .collect()
+            }
+            SLOT_TABLE => {
+                let mut superseded = Vec::with_capacity(cells.len());
+                for (key, cell) in &cells {
+                    let spilled: slots::Slot = self.reader.decode_compressed_cell(key, &cell.0)?;
+                    let stored = self.reader.get_slot(spilled.slot).await?;
+                    superseded.push(is_slot_superseded(&spilled, stored.as_ref()));
+                }
+                superseded
+            }
+            _ => return Ok(cells),
+        };
+        Ok(cells
+            .into_iter()
+            .zip(superseded)
+            .filter(|(_, superseded)| !superseded)
+            .map(|(cell, _)| cell)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_superseded() {
+        let account = accounts::Account {
+            slot: 10,
+            write_version: 7,
+            ..accounts::Account::default()
+        };
+        assert!(!is_account_superseded(&account, None));
+        assert!(is_account_superseded(&account, Some(&account)));
+        let older = accounts::Account {
+            write_version: 6,
+            ..account.clone()
+        };
+        assert!(!is_account_superseded(&account, Some(&older)));
+        // The write versions start over when the validator restarts
+        let restarted = accounts::Account {
+            slot: 11,
+            write_version: 1,
+            ..account.clone()
+        };
+        assert!(is_account_superseded(&account, Some(&restarted)));
+
+        let slot = |status: SlotStatus| slots::Slot {
+            slot: 10,
+            status: status.as_str().to_string(),
+            ..slots::Slot::default()
+        };
+        assert!(!is_slot_superseded(&slot(SlotStatus::Rooted), None));
+        assert!(!is_slot_superseded(
+            &slot(SlotStatus::Rooted),
+            Some(&slot(SlotStatus::Confirmed))
+        ));
+        assert!(is_slot_superseded(
+            &slot(SlotStatus::Confirmed),
+            Some(&slot(SlotStatus::Rooted))
+        ));
+        assert!(is_slot_superseded(
+            &slot(SlotStatus::Processed),
+            Some(&slot(SlotStatus::Processed))
+        ));
+    }
+}
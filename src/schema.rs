@@ -0,0 +1,148 @@
+/// Versioning of the cells written by the plugin. The version of the schema of a cell is
+/// encoded as a field of its protobuf encoding, numbered `SCHEMA_VERSION_TAG`, which the
+/// models do not use. The cells stay plain protobuf encodings of the models under the
+/// `proto` qualifier: the readers unaware of the versioning skip the field as unknown,
+/// and the cells written before the versioning, which do not hold the field, are decoded
+/// as `SchemaVersion::Unversioned`.
+///
+/// Changing a model in a way that is not wire compatible takes a new version, written
+/// from then on, along with the decoding of the cells of the previous versions into the
+/// new model in `decode_cell`.
+use {
+    prost::{
+        encoding::{
+            check_wire_type, decode_key, decode_varint, encode_key, encode_varint,
+            encoded_len_varint, key_len, skip_field, DecodeContext, WireType,
+        },
+        Message,
+    },
+    thiserror::Error,
+};
+
+/// The field number of the schema version, reserved in all the models
+pub const SCHEMA_VERSION_TAG: u32 = 2047;
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("The schema version {0} is not supported")]
+    UnsupportedVersion(u64),
+
+    #[error("Error decoding the cell: ({0})")]
+    DecodeError(#[from] prost::DecodeError),
+}
+
+/// The versions of the schema of the cells, all of them are decoded by the readers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// The cells written before the versioning, without a version field
+    Unversioned,
+    /// The protobuf encoding of the models of `solana-bigtable-geyser-models` 1.10, kept
+    /// wire compatible by the `models` module
+    V1,
+}
+
+impl SchemaVersion {
+    /// The version of the cells written
+    pub const CURRENT: SchemaVersion = SchemaVersion::V1;
+
+    /// The versions the readers decode
+    pub const SUPPORTED: &'static [SchemaVersion] =
+        &[SchemaVersion::Unversioned, SchemaVersion::V1];
+
+    /// The supported version numbered `version`
+    pub fn from_u8(version: u8) -> Option<Self> {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|supported| supported.as_u8() == version)
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            SchemaVersion::Unversioned => 0,
+            SchemaVersion::V1 => 1,
+        }
+    }
+}
+
+/// Encode the message along with the version field of the current version
+pub fn encode_cell<T: Message>(message: &T) -> Vec<u8> {
+    let version = u64::from(SchemaVersion::CURRENT.as_u8());
+    let mut data = Vec::with_capacity(
+        message.encoded_len() + key_len(SCHEMA_VERSION_TAG) + encoded_len_varint(version),
+    );
+    message.encode(&mut data).unwrap();
+    encode_key(SCHEMA_VERSION_TAG, WireType::Varint, &mut data);
+    encode_varint(version, &mut data);
+    data
+}
+
+/// The version of the schema of the cell, read from its version field
+pub fn schema_version(mut data: &[u8]) -> Result<SchemaVersion, SchemaError> {
+    let mut version = None;
+    while !data.is_empty() {
+        let (tag, wire_type) = decode_key(&mut data)?;
+        if tag == SCHEMA_VERSION_TAG {
+            check_wire_type(WireType::Varint, wire_type)?;
+            version = Some(decode_varint(&mut data)?);
+        } else {
+            skip_field(wire_type, tag, &mut data, DecodeContext::default())?;
+        }
+    }
+    match version {
+        None => Ok(SchemaVersion::Unversioned),
+        Some(version) => u8::try_from(version)
+            .ok()
+            .and_then(SchemaVersion::from_u8)
+            .ok_or(SchemaError::UnsupportedVersion(version)),
+    }
+}
+
+/// Decode the cell of any of the supported versions
+pub fn decode_cell<T: Message + Default>(data: &[u8]) -> Result<T, SchemaError> {
+    match schema_version(data)? {
+        // The version field is skipped as unknown by the models
+        SchemaVersion::Unversioned | SchemaVersion::V1 => Ok(T::decode(data)?),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::models::slots};
+
+    #[test]
+    fn test_decode_cell() {
+        let slot = slots::Slot {
+            slot: 42,
+            parent: Some(41),
+            status: "rooted".to_string(),
+            updated_on: Some(slots::UnixTimestamp { timestamp: 1234 }),
+        };
+
+        let data = encode_cell(&slot);
+        assert_eq!(schema_version(&data).unwrap(), SchemaVersion::CURRENT);
+        assert_eq!(decode_cell::<slots::Slot>(&data).unwrap(), slot);
+        // The readers unaware of the versioning decode the versioned cells
+        assert_eq!(slots::Slot::decode(&data[..]).unwrap(), slot);
+
+        // The cells written before the versioning
+        let data = slot.encode_to_vec();
+        assert_eq!(schema_version(&data).unwrap(), SchemaVersion::Unversioned);
+        assert_eq!(decode_cell::<slots::Slot>(&data).unwrap(), slot);
+        assert_eq!(
+            decode_cell::<slots::Slot>(&[]).unwrap(),
+            slots::Slot::default()
+        );
+
+        let mut data = slot.encode_to_vec();
+        encode_key(SCHEMA_VERSION_TAG, WireType::Varint, &mut data);
+        encode_varint(200, &mut data);
+        assert!(matches!(
+            decode_cell::<slots::Slot>(&data),
+            Err(SchemaError::UnsupportedVersion(200))
+        ));
+        for version in SchemaVersion::SUPPORTED {
+            assert_eq!(SchemaVersion::from_u8(version.as_u8()), Some(*version));
+        }
+    }
+}
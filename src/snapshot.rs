@@ -0,0 +1,304 @@
+/// Reads the accounts of a validator snapshot without running a validator, either from a
+/// snapshot archive unpacked to a temporary directory or from an `accounts` directory of
+/// append vecs. The append vecs are read from the highest slot down so that only the
+/// latest version of each account is visited, which requires to keep the pubkeys already
+/// visited in memory.
+use {
+    crate::parallel_bigtable_client::account::{DbAccountInfo, ReadableAccountInfo},
+    bzip2::bufread::BzDecoder,
+    flate2::bufread::GzDecoder,
+    log::*,
+    solana_runtime::{
+        append_vec::{AppendVec, StoredAccountMeta},
+        hardened_unpack::unpack_snapshot,
+    },
+    solana_sdk::{clock::Slot, pubkey::Pubkey},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+        fs::{self, File},
+        io::{self, BufReader, Read},
+        path::{Path, PathBuf},
+    },
+    tar::Archive,
+    tempfile::TempDir,
+    thiserror::Error,
+    tokio::{sync::mpsc, task::JoinHandle},
+};
+
+/// The count of accounts buffered between the snapshot reader and the consumer
+const ACCOUNTS_CHANNEL_SIZE: usize = 10_000;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("I/O error: ({0})")]
+    Io(#[from] io::Error),
+
+    #[error("Error unpacking the snapshot archive: ({0})")]
+    UnpackError(String),
+
+    #[error("The snapshot archive {0:?} is not a .tar, .tar.gz, .tar.bz2 or .tar.zst archive")]
+    UnsupportedArchive(PathBuf),
+}
+
+/// A range of pubkeys from `start` included to `end` excluded, unbounded if not set. The
+/// snapshot is read in the order of the slots, so the accounts are loaded by ranges to
+/// split a load between processes or to resume a range which failed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PubkeyRange {
+    pub start: Option<Pubkey>,
+    pub end: Option<Pubkey>,
+}
+
+impl PubkeyRange {
+    pub fn contains(&self, pubkey: &[u8]) -> bool {
+        self.start.map_or(true, |start| pubkey >= start.as_ref())
+            && self.end.map_or(true, |end| pubkey < end.as_ref())
+    }
+}
+
+impl fmt::Display for PubkeyRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bound = |pubkey: &Option<Pubkey>| {
+            pubkey
+                .map(|pubkey| pubkey.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        };
+        write!(f, "[{}, {})", bound(&self.start), bound(&self.end))
+    }
+}
+
+impl<'a> ReadableAccountInfo for StoredAccountMeta<'a> {
+    fn pubkey(&self) -> &[u8] {
+        self.meta.pubkey.as_ref()
+    }
+
+    fn owner(&self) -> &[u8] {
+        self.account_meta.owner.as_ref()
+    }
+
+    fn lamports(&self) -> u64 {
+        self.account_meta.lamports
+    }
+
+    fn executable(&self) -> bool {
+        self.account_meta.executable
+    }
+
+    fn rent_epoch(&self) -> u64 {
+        self.account_meta.rent_epoch
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn write_version(&self) -> u64 {
+        self.meta.write_version
+    }
+
+    fn txn_signature(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+pub struct SnapshotAccounts {
+    /// The append vecs and their slot, from the highest slot
+    storages: Vec<(Slot, PathBuf)>,
+    /// The directory the archive is unpacked to, removed once dropped
+    _unpack_dir: Option<TempDir>,
+}
+
+impl SnapshotAccounts {
+    /// Open a snapshot archive, or an accounts directory if the path is a directory
+    pub fn open(path: &Path) -> Result<Self, SnapshotError> {
+        if path.is_dir() {
+            Self::from_accounts_dir(path)
+        } else {
+            Self::from_archive(path)
+        }
+    }
+
+    /// Unpack the append vecs of the snapshot archive next to it
+    pub fn from_archive(archive_path: &Path) -> Result<Self, SnapshotError> {
+        let unpack_dir = tempfile::Builder::new()
+            .prefix("geyser-bigtable-snapshot-")
+            .tempdir_in(archive_path.parent().unwrap_or_else(|| Path::new(".")))?;
+        let accounts_dir = unpack_dir.path().join("accounts");
+        fs::create_dir_all(&accounts_dir)?;
+        info!(
+            "Unpacking the snapshot archive {:?} to {:?}",
+            archive_path,
+            unpack_dir.path()
+        );
+
+        let file = BufReader::new(File::open(archive_path)?);
+        let name = archive_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if name.ends_with(".tar.zst") {
+            unpack(
+                zstd::stream::read::Decoder::with_buffer(file)?,
+                &unpack_dir,
+                &accounts_dir,
+            )?
+        } else if name.ends_with(".tar.bz2") {
+            unpack(BzDecoder::new(file), &unpack_dir, &accounts_dir)?
+        } else if name.ends_with(".tar.gz") {
+            unpack(GzDecoder::new(file), &unpack_dir, &accounts_dir)?
+        } else if name.ends_with(".tar") {
+            unpack(file, &unpack_dir, &accounts_dir)?
+        } else {
+            return Err(SnapshotError::UnsupportedArchive(
+                archive_path.to_path_buf(),
+            ));
+        }
+
+        let mut accounts = Self::from_accounts_dir(&accounts_dir)?;
+        accounts._unpack_dir = Some(unpack_dir);
+        Ok(accounts)
+    }
+
+    /// Open the directory of the append vecs, named `<slot>.<id>`
+    pub fn from_accounts_dir(accounts_dir: &Path) -> Result<Self, SnapshotError> {
+        let mut storages = vec![];
+        for entry in fs::read_dir(accounts_dir)? {
+            let path = entry?.path();
+            let slot = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('.').next())
+                .and_then(|slot| slot.parse::<Slot>().ok());
+            match slot {
+                Some(slot) => storages.push((slot, path)),
+                None => warn!("Skipping {:?} which is not an append vec", path),
+            }
+        }
+        storages.sort_unstable_by(|a, b| b.cmp(a));
+        info!("Found {} append vecs in {:?}", storages.len(), accounts_dir);
+        Ok(Self {
+            storages,
+            _unpack_dir: None,
+        })
+    }
+
+    pub fn storage_count(&self) -> usize {
+        self.storages.len()
+    }
+
+    /// Visit the latest version of each account selected by `filter`, called with the
+    /// pubkey and the owner. The accounts deleted are not visited, and the reading stops
+    /// once `visit` returns false.
+    pub fn for_each_account<F, V>(&self, filter: F, mut visit: V) -> Result<(), SnapshotError>
+    where
+        F: Fn(&[u8], &[u8]) -> bool,
+        V: FnMut(DbAccountInfo) -> bool,
+    {
+        let mut visited = HashSet::new();
+        for (index, (slot, path)) in self.storages.iter().enumerate() {
+            let file_len = fs::metadata(path)?.len() as usize;
+            let (append_vec, _) = AppendVec::new_from_file(path, file_len)?;
+
+            // An append vec can hold several versions of an account, keep the latest
+            let mut latest: HashMap<Pubkey, StoredAccountMeta> = HashMap::new();
+            for account in append_vec.accounts(0) {
+                // The capacity left at the end of the file reads as empty accounts
+                if account.meta.pubkey == Pubkey::default() && account.meta.write_version == 0 {
+                    break;
+                }
+                let is_latest = latest.get(&account.meta.pubkey).map_or(true, |other| {
+                    other.meta.write_version < account.meta.write_version
+                });
+                if is_latest {
+                    latest.insert(account.meta.pubkey, account);
+                }
+            }
+
+            for (pubkey, account) in latest {
+                if !visited.insert(pubkey) || account.account_meta.lamports == 0 {
+                    continue;
+                }
+                if filter(account.pubkey(), account.owner())
+                    && !visit(DbAccountInfo::new(&account, *slot))
+                {
+                    return Ok(());
+                }
+            }
+            if (index + 1) % 1000 == 0 {
+                info!(
+                    "Read {} of the {} append vecs, {} accounts",
+                    index + 1,
+                    self.storages.len(),
+                    visited.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the accounts of the snapshot at `path` on a blocking thread, sending the accounts
+/// selected by `filter` over the channel returned. The reading stops early if the
+/// receiver is dropped.
+pub fn stream_accounts<F>(
+    path: PathBuf,
+    filter: F,
+) -> (
+    mpsc::Receiver<DbAccountInfo>,
+    JoinHandle<Result<(), SnapshotError>>,
+)
+where
+    F: Fn(&[u8], &[u8]) -> bool + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(ACCOUNTS_CHANNEL_SIZE);
+    let reader = tokio::task::spawn_blocking(move || {
+        let snapshot = SnapshotAccounts::open(&path)?;
+        snapshot.for_each_account(filter, |account| sender.blocking_send(account).is_ok())
+    });
+    (receiver, reader)
+}
+
+fn unpack<R: Read>(
+    reader: R,
+    unpack_dir: &TempDir,
+    accounts_dir: &Path,
+) -> Result<(), SnapshotError> {
+    unpack_snapshot(
+        &mut Archive::new(reader),
+        unpack_dir.path(),
+        &[accounts_dir.to_path_buf()],
+        None,
+    )
+    .map(|_| ())
+    .map_err(|err| SnapshotError::UnpackError(err.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pubkey_range() {
+        let pubkeys: Vec<Pubkey> = (0..3u8).map(|i| Pubkey::new(&[i; 32])).collect();
+
+        let range = PubkeyRange::default();
+        assert!(pubkeys.iter().all(|pubkey| range.contains(pubkey.as_ref())));
+        assert_eq!(range.to_string(), "[-, -)");
+
+        let range = PubkeyRange {
+            start: Some(pubkeys[1]),
+            end: Some(pubkeys[2]),
+        };
+        assert!(!range.contains(pubkeys[0].as_ref()));
+        assert!(range.contains(pubkeys[1].as_ref()));
+        assert!(!range.contains(pubkeys[2].as_ref()));
+
+        let range = PubkeyRange {
+            start: Some(pubkeys[1]),
+            end: None,
+        };
+        assert!(!range.contains(pubkeys[0].as_ref()));
+        assert!(range.contains(pubkeys[2].as_ref()));
+    }
+}
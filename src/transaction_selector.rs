@@ -2,7 +2,7 @@
 /// in the plugin framework.
 use {log::*, solana_sdk::pubkey::Pubkey, std::collections::HashSet};
 
-pub(crate) struct TransactionSelector {
+pub struct TransactionSelector {
     pub mentioned_addresses: HashSet<Vec<u8>>,
     pub select_all_transactions: bool,
     pub select_all_vote_transactions: bool,
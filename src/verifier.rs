@@ -0,0 +1,291 @@
+/// Checks that the `account` table matches the accounts of a snapshot. Each account of
+/// the snapshot is compared with the row stored, which is missing, stale if it holds an
+/// older write version, or mismatched if it holds the same write version with other
+/// content. The rows updated after the snapshot hold a newer write version and are not
+/// checked. The accounts found out of sync can be written again to repair the table: they
+/// are looked up again right before the repair, which is skipped for the rows updated in
+/// between, and written out at once. A write of the plugin landing between that lookup and
+/// the repair is still overwritten, so the repair is meant to run with the plugin stopped.
+use {
+    crate::{
+        bigtable_reader::{BigtableReader, BigtableReaderError},
+        models::accounts,
+        parallel_bigtable_client::{account::DbAccountInfo, standalone::StandaloneWriter},
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::{fmt, mem},
+};
+
+/// The count of accounts looked up in Bigtable at once
+pub const DEFAULT_VERIFY_BATCH_SIZE: usize = 1000;
+
+/// The maximum count of accounts listed in the report for each of the checks failed
+const MAX_REPORTED_ACCOUNTS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountCheck {
+    Matching,
+    /// Updated after the snapshot
+    Newer,
+    Missing,
+    Stale,
+    Mismatched,
+}
+
+impl AccountCheck {
+    pub fn is_out_of_sync(&self) -> bool {
+        matches!(
+            self,
+            AccountCheck::Missing | AccountCheck::Stale | AccountCheck::Mismatched
+        )
+    }
+}
+
+/// Compare the account of the snapshot with the row stored
+pub fn check_account(expected: &DbAccountInfo, stored: Option<&accounts::Account>) -> AccountCheck {
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return AccountCheck::Missing,
+    };
+    if stored.write_version > expected.write_version {
+        AccountCheck::Newer
+    } else if stored.write_version < expected.write_version {
+        AccountCheck::Stale
+    } else if stored.lamports != expected.lamports
+        || stored.owner != expected.owner
+        || stored.executable != expected.executable
+        || stored.rent_epoch != expected.rent_epoch
+        || stored.data != expected.data
+    {
+        AccountCheck::Mismatched
+    } else {
+        AccountCheck::Matching
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub matching: usize,
+    pub newer: usize,
+    pub missing: usize,
+    pub stale: usize,
+    pub mismatched: usize,
+    /// The first accounts found out of sync
+    pub out_of_sync_accounts: Vec<(Pubkey, AccountCheck)>,
+    pub repaired: usize,
+    /// Updated after the check, left as they are
+    pub repair_skipped: usize,
+    pub repair_failures: usize,
+}
+
+impl VerifyReport {
+    fn record(&mut self, pubkey: Pubkey, check: AccountCheck) {
+        self.checked += 1;
+        match check {
+            AccountCheck::Matching => self.matching += 1,
+            AccountCheck::Newer => self.newer += 1,
+            AccountCheck::Missing => self.missing += 1,
+            AccountCheck::Stale => self.stale += 1,
+            AccountCheck::Mismatched => self.mismatched += 1,
+        }
+        if check.is_out_of_sync() && self.out_of_sync_accounts.len() < MAX_REPORTED_ACCOUNTS {
+            self.out_of_sync_accounts.push((pubkey, check));
+        }
+    }
+
+    pub fn out_of_sync(&self) -> usize {
+        self.missing + self.stale + self.mismatched
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checked {} accounts:", self.checked)?;
+        writeln!(f, "  matching:   {}", self.matching)?;
+        writeln!(f, "  newer:      {}", self.newer)?;
+        writeln!(f, "  missing:    {}", self.missing)?;
+        writeln!(f, "  stale:      {}", self.stale)?;
+        writeln!(f, "  mismatched: {}", self.mismatched)?;
+        if self.repaired > 0 {
+            writeln!(
+                f,
+                "Repaired {} accounts, {} failed",
+                self.repaired - self.repair_failures,
+                self.repair_failures
+            )?;
+        }
+        if self.repair_skipped > 0 {
+            writeln!(
+                f,
+                "Skipped the repair of {} accounts updated since the check",
+                self.repair_skipped
+            )?;
+        }
+        for (pubkey, check) in &self.out_of_sync_accounts {
+            writeln!(f, "  {} {:?}", pubkey, check)?;
+        }
+        if self.out_of_sync() > self.out_of_sync_accounts.len() {
+            writeln!(
+                f,
+                "  ... and {} more",
+                self.out_of_sync() - self.out_of_sync_accounts.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Verifier {
+    reader: BigtableReader,
+    /// Writes the accounts out of sync when repairing
+    writer: Option<StandaloneWriter>,
+    batch_size: usize,
+    pending_accounts: Vec<DbAccountInfo>,
+    report: VerifyReport,
+}
+
+impl Verifier {
+    pub fn new(
+        reader: BigtableReader,
+        writer: Option<StandaloneWriter>,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            reader,
+            writer,
+            batch_size: batch_size.max(1),
+            pending_accounts: Vec::default(),
+            report: VerifyReport::default(),
+        }
+    }
+
+    /// Buffer the account of the snapshot, checking the batch once it is full
+    pub async fn verify_account(
+        &mut self,
+        account: DbAccountInfo,
+    ) -> Result<(), BigtableReaderError> {
+        self.pending_accounts.push(account);
+        if self.pending_accounts.len() >= self.batch_size {
+            self.check_pending_accounts().await?;
+        }
+        Ok(())
+    }
+
+    /// Check the accounts still buffered and wait for the repairs to complete
+    pub async fn finish(mut self) -> Result<VerifyReport, BigtableReaderError> {
+        self.check_pending_accounts().await?;
+        if let Some(writer) = self.writer {
+            self.report.repair_failures = writer.finish().await;
+        }
+        Ok(self.report)
+    }
+
+    async fn check_pending_accounts(&mut self) -> Result<(), BigtableReaderError> {
+        if self.pending_accounts.is_empty() {
+            return Ok(());
+        }
+        let accounts = mem::take(&mut self.pending_accounts);
+        let pubkeys: Vec<Pubkey> = accounts
+            .iter()
+            .map(|account| Pubkey::new(&account.pubkey))
+            .collect();
+        let stored_accounts = self.reader.get_accounts(&pubkeys).await?;
+
+        let mut out_of_sync = Vec::new();
+        for (pubkey, account) in pubkeys.into_iter().zip(accounts) {
+            let check = check_account(&account, stored_accounts.get(&pubkey));
+            self.report.record(pubkey, check);
+            if check.is_out_of_sync() {
+                out_of_sync.push((pubkey, account));
+            }
+        }
+        if self.writer.is_some() && !out_of_sync.is_empty() {
+            self.repair_accounts(out_of_sync).await?;
+        }
+        Ok(())
+    }
+
+    /// Write the accounts out of sync again, unless their rows were updated since the check
+    async fn repair_accounts(
+        &mut self,
+        accounts: Vec<(Pubkey, DbAccountInfo)>,
+    ) -> Result<(), BigtableReaderError> {
+        let pubkeys: Vec<Pubkey> = accounts.iter().map(|(pubkey, _)| *pubkey).collect();
+        let stored_accounts = self.reader.get_accounts(&pubkeys).await?;
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        for (pubkey, account) in accounts {
+            if check_account(&account, stored_accounts.get(&pubkey)).is_out_of_sync() {
+                writer.write_account(account).await;
+                self.report.repaired += 1;
+            } else {
+                self.report.repair_skipped += 1;
+            }
+        }
+        // Not left buffered, to keep the repairs close to the lookup
+        writer.flush().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, std::time::Duration};
+
+    #[test]
+    fn test_check_account() {
+        let expected = DbAccountInfo {
+            pubkey: Pubkey::new_unique().to_bytes().to_vec(),
+            lamports: 1234,
+            owner: Pubkey::new_unique().to_bytes().to_vec(),
+            executable: false,
+            rent_epoch: 0,
+            data: vec![1, 2, 3],
+            slot: 10,
+            write_version: 7,
+            updated_since_epoch: Duration::default(),
+            txn_signature: None,
+        };
+        let stored = accounts::Account::from(&expected);
+
+        assert_eq!(check_account(&expected, None), AccountCheck::Missing);
+        assert_eq!(
+            check_account(&expected, Some(&stored)),
+            AccountCheck::Matching
+        );
+        assert_eq!(
+            check_account(
+                &expected,
+                Some(&accounts::Account {
+                    write_version: 6,
+                    ..stored.clone()
+                })
+            ),
+            AccountCheck::Stale
+        );
+        assert_eq!(
+            check_account(
+                &expected,
+                Some(&accounts::Account {
+                    write_version: 8,
+                    lamports: 1,
+                    ..stored.clone()
+                })
+            ),
+            AccountCheck::Newer
+        );
+        assert_eq!(
+            check_account(
+                &expected,
+                Some(&accounts::Account {
+                    data: vec![1, 2],
+                    ..stored
+                })
+            ),
+            AccountCheck::Mismatched
+        );
+    }
+}
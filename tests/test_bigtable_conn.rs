@@ -3,10 +3,8 @@
 /// For integration tests locally, use the Google Bigtable Emulator.
 /// See this project's README.md on testing in Development Environment.
 use {
-    log::*,
-    solana_bigtable_connection::bigtable::BigTableConnection,
-    solana_bigtable_geyser_models::models::accounts,
-    solana_sdk::pubkey::Pubkey,
+    log::*, solana_bigtable_connection::bigtable::BigTableConnection,
+    solana_geyser_plugin_bigtable::models::accounts, solana_sdk::pubkey::Pubkey,
     std::time::SystemTime,
 };
 